@@ -3,15 +3,65 @@ extern crate env_logger;
 use rand::Rng;
 
 use liars::agent;
+use liars::bench;
+use liars::decision;
+use liars::gossip;
+use liars::limits;
 use liars::play;
+use liars::playagree;
 use liars::playexpert;
+use liars::playsample;
+use liars::playsnowball;
+use liars::reputation;
+use liars::snowball;
 use liars::start;
+use liars::stats;
+use liars::status;
+use liars::supervisor;
+use liars::tls;
+use liars::trace;
+use liars::transcript;
+
+fn format_traffic(traffic: &stats::Traffic) -> String {
+    format!(
+        "messages: {}, bytes sent: {}, bytes received: {}, connections: {}",
+        traffic.messages, traffic.bytes_sent, traffic.bytes_received, traffic.connections
+    )
+}
+
+/// Print the value found by a player and the traffic it took, in total,
+/// per message type and per agent.
+fn print_outcome(value: Option<bool>, ledger: &stats::Ledger) {
+    match value {
+        Some(value) => println!("{}", value),
+        None => println!("undecided"),
+    }
+    println!("{}", format_traffic(&ledger.total));
+    for (message, traffic) in &ledger.by_message {
+        println!("  {}: {}", message, format_traffic(traffic));
+    }
+    for (port, traffic) in &ledger.by_peer {
+        println!("  port {}: {}", port, format_traffic(traffic));
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
     use clap::{Arg, SubCommand};
     let app = clap::App::new("Liars lie")
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .possible_value("text")
+                .possible_value("json")
+                .help("Log format, inherited by spawned agents. Defaults to $LIARSLIE_LOG_FORMAT or text"),
+        )
+        .arg(
+            Arg::with_name("trace-file")
+                .long("trace-file")
+                .value_name("FILE")
+                .help("Append spans to this file as OTLP/JSON, inherited by spawned agents. Defaults to $LIARSLIE_TRACE_FILE"),
+        )
         .subcommand(
             SubCommand::with_name("start")
                 .about("Start a number of agents, generate file agents.conf")
@@ -37,9 +87,134 @@ async fn main() {
                         .default_value("0.1")
                         .validator(|s| match s.parse::<f64>() {
                             Err(e) => Err(format!("{}", e)),
-                            Ok(v) if 0. <= v && v < 0.5 => Ok(()),
+                            Ok(v) if (0. ..0.5).contains(&v) => Ok(()),
                             Ok(v) => Err(format!("Expected a value in [0., 0.5[, got {}", v)),
                         }),
+                )
+                .arg(
+                    Arg::with_name("weights")
+                        .long("weights")
+                        .value_name("distribution")
+                        .default_value("uniform")
+                        .possible_values(&["uniform", "random", "zipf"])
+                        .help("How to distribute voting weights among agents"),
+                )
+                .arg(
+                    Arg::with_name("tls")
+                        .long("tls")
+                        .value_name("DIR")
+                        .help("Generate self-signed agent certificates in this directory, talk to agents over TLS"),
+                )
+                .arg(
+                    Arg::with_name("admin-token")
+                        .long("admin-token")
+                        .value_name("FILE")
+                        .default_value("admin.token")
+                        .help("Generate the token required on administrative messages, store it in this private file"),
+                )
+                .arg(
+                    Arg::with_name("gossip")
+                        .long("gossip")
+                        .help("Let agents discover their peers by gossip"),
+                )
+                .arg(
+                    Arg::with_name("snowball")
+                        .long("snowball")
+                        .requires("gossip")
+                        .help("Let agents converge on a value with their peers, liars stick to theirs"),
+                )
+                .arg(
+                    Arg::with_name("daemon")
+                        .long("daemon")
+                        .help("Stay resident, respawning agents that die, until interrupted or stopped"),
+                )
+                .arg(
+                    Arg::with_name("control")
+                        .long("control")
+                        .value_name("SOCKET")
+                        .default_value("supervisor.sock")
+                        .help("With --daemon, accept commands on this unix socket"),
+                )
+                .arg(
+                    Arg::with_name("chaos-period")
+                        .long("chaos-period")
+                        .value_name("ms")
+                        .requires("daemon")
+                        .help("Randomly kill agents every <ms> milliseconds")
+                        .validator(|s| {
+                            s.parse::<u64>().map(|_| ()).map_err(|e| format!("{}", e))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("chaos-probability")
+                        .long("chaos-probability")
+                        .value_name("probability")
+                        .default_value("0.1")
+                        .help("Probability for each agent to be killed by chaos")
+                        .validator(|s| match s.parse::<f64>() {
+                            Err(e) => Err(format!("{}", e)),
+                            Ok(v) if (0. ..=1.).contains(&v) => Ok(()),
+                            Ok(v) => Err(format!("Expected a value in [0., 1.], got {}", v)),
+                        }),
+                )
+                .arg(
+                    Arg::with_name("metrics")
+                        .long("metrics")
+                        .value_name("PORT")
+                        .requires("daemon")
+                        .help("Serve the metrics of the fleet on http://127.0.0.1:PORT/metrics")
+                        .validator(|s| {
+                            s.parse::<u16>().map(|_| ()).map_err(|e| format!("{}", e))
+                        }),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("control")
+                .about("Send a command to a fleet started with `start --daemon`")
+                .arg(
+                    Arg::with_name("control")
+                        .long("control")
+                        .value_name("SOCKET")
+                        .default_value("supervisor.sock"),
+                )
+                .subcommand(SubCommand::with_name("status").about("Describe the fleet"))
+                .subcommand(
+                    SubCommand::with_name("membership")
+                        .about("Print the current membership of the fleet"),
+                )
+                .subcommand(SubCommand::with_name("stop").about("Stop all agents and the supervisor"))
+                .subcommand(
+                    SubCommand::with_name("restart")
+                        .about("Kill and respawn an agent")
+                        .arg(
+                            Arg::with_name("pid")
+                                .required(true)
+                                .validator(|s| {
+                                    s.parse::<u32>().map(|_| ()).map_err(|e| format!("{}", e))
+                                }),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("add-agents")
+                        .about("Spawn additional reliable agents")
+                        .arg(
+                            Arg::with_name("number")
+                                .required(true)
+                                .validator(|s| {
+                                    s.parse::<usize>().map(|_| ()).map_err(|e| format!("{}", e))
+                                }),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("set-liars")
+                        .about("Respawn agents so that exactly <number> of them are liars")
+                        .arg(
+                            Arg::with_name("number")
+                                .required(true)
+                                .validator(|s| {
+                                    s.parse::<usize>().map(|_| ()).map_err(|e| format!("{}", e))
+                                }),
+                        ),
                 ),
         )
         .subcommand(
@@ -49,7 +224,198 @@ async fn main() {
                     Arg::with_name("agents")
                         .long("agents")
                         .value_name("FILE")
+                        .help("The registry, or the control socket of a supervisor")
+                        .default_value("agents.conf"),
+                )
+                .arg(
+                    Arg::with_name("transcript")
+                        .long("transcript")
+                        .value_name("FILE")
+                        .help("Record the round to this file, to replay it with `replay`"),
+                )
+                .arg(
+                    Arg::with_name("reputation")
+                        .long("reputation")
+                        .value_name("FILE")
+                        .help("Record which agents disagreed with the decision to this file"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("suspects")
+                .about("List the agents suspected to be liars, as recorded with `--reputation`")
+                .arg(
+                    Arg::with_name("reputation")
+                        .long("reputation")
+                        .value_name("FILE")
+                        .default_value("reputation.json"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("replay")
+                .about("Replay a round recorded with `--transcript`, without contacting agents")
+                .arg(Arg::with_name("transcript").required(true).value_name("FILE")),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Check a certificate written by `playexpert --certificate`, without contacting agents")
+                .arg(Arg::with_name("certificate").required(true).value_name("FILE"))
+                .arg(
+                    Arg::with_name("agents")
+                        .long("agents")
+                        .value_name("FILE")
+                        .help("The registry, or the control socket of a supervisor")
+                        .default_value("agents.conf"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("Print the status of every agent, flag those that can't be reached")
+                .arg(
+                    Arg::with_name("agents")
+                        .long("agents")
+                        .value_name("FILE")
+                        .help("The registry, or the control socket of a supervisor")
+                        .default_value("agents.conf"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("playagree")
+                .about("Have the agents agree on the original value, read it from a single agent")
+                .arg(
+                    Arg::with_name("agents")
+                        .long("agents")
+                        .value_name("FILE")
+                        .help("The registry, or the control socket of a supervisor")
+                        .default_value("agents.conf"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("playsample")
+                .about("Sample agents at random until confident about the original value")
+                .arg(
+                    Arg::with_name("agents")
+                        .long("agents")
+                        .value_name("FILE")
+                        .help("The registry, or the control socket of a supervisor")
+                        .default_value("agents.conf"),
+                )
+                .arg(
+                    Arg::with_name("liar-ratio")
+                        .long("liar-ratio")
+                        .value_name("ratio")
+                        .default_value("0.1")
+                        .validator(|s| match s.parse::<f64>() {
+                            Err(e) => Err(format!("{}", e)),
+                            Ok(v) if (0. ..0.5).contains(&v) => Ok(()),
+                            Ok(v) => Err(format!("Expected a value in [0., 0.5[, got {}", v)),
+                        }),
+                )
+                .arg(
+                    Arg::with_name("confidence")
+                        .long("confidence")
+                        .value_name("probability")
+                        .default_value("0.999")
+                        .validator(|s| match s.parse::<f64>() {
+                            Err(e) => Err(format!("{}", e)),
+                            Ok(v) if (0.5..1.).contains(&v) => Ok(()),
+                            Ok(v) => Err(format!("Expected a value in [0.5, 1.[, got {}", v)),
+                        }),
+                )
+                .arg(
+                    Arg::with_name("batch")
+                        .long("batch")
+                        .value_name("number")
+                        .default_value("3")
+                        .help("Number of agents to sample before re-evaluating confidence")
+                        .validator(|s| match s.parse::<usize>() {
+                            Err(e) => Err(format!("{}", e)),
+                            Ok(0) => Err("Expected a positive number".to_string()),
+                            Ok(_) => Ok(()),
+                        }),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("playsnowball")
+                .about("Ask a few agents for the value they have converged upon")
+                .arg(
+                    Arg::with_name("agents")
+                        .long("agents")
+                        .value_name("FILE")
+                        .help("The registry, or the control socket of a supervisor")
                         .default_value("agents.conf"),
+                )
+                .arg(
+                    Arg::with_name("ask")
+                        .long("ask")
+                        .value_name("number")
+                        .default_value("5")
+                        .help("Number of decided agents to ask")
+                        .validator(|s| match s.parse::<usize>() {
+                            Err(e) => Err(format!("{}", e)),
+                            Ok(0) => Err("Expected a positive number".to_string()),
+                            Ok(_) => Ok(()),
+                        }),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("Compare play strategies, print results as CSV or JSON. Overwrites agents.conf")
+                .arg(
+                    Arg::with_name("num-agents")
+                        .long("num-agents")
+                        .value_name("number,...")
+                        .use_delimiter(true)
+                        .default_value("10")
+                        .validator(|s| {
+                            s.parse::<usize>().map(|_| ()).map_err(|e| format!("{}", e))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("liar-ratio")
+                        .long("liar-ratio")
+                        .value_name("ratio,...")
+                        .use_delimiter(true)
+                        .default_value("0.1")
+                        .validator(|s| match s.parse::<f64>() {
+                            Err(e) => Err(format!("{}", e)),
+                            Ok(v) if (0. ..0.5).contains(&v) => Ok(()),
+                            Ok(v) => Err(format!("Expected a value in [0., 0.5[, got {}", v)),
+                        }),
+                )
+                .arg(
+                    Arg::with_name("strategy")
+                        .long("strategy")
+                        .value_name("name,...")
+                        .use_delimiter(true)
+                        .default_value("play,playexpert")
+                        .possible_values(&["play", "playexpert", "playsample", "playagree", "playsnowball"]),
+                )
+                .arg(
+                    Arg::with_name("rounds")
+                        .long("rounds")
+                        .value_name("number")
+                        .default_value("10")
+                        .help("Number of rounds per strategy and configuration")
+                        .validator(|s| {
+                            s.parse::<usize>().map(|_| ()).map_err(|e| format!("{}", e))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("warmup")
+                        .long("warmup")
+                        .value_name("ms")
+                        .default_value("2000")
+                        .help("How long to let each fleet settle before playing")
+                        .validator(|s| {
+                            s.parse::<u64>().map(|_| ()).map_err(|e| format!("{}", e))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .default_value("csv")
+                        .possible_value("csv")
+                        .possible_value("json"),
                 ),
         )
         .subcommand(
@@ -62,6 +428,209 @@ async fn main() {
                         .possible_value("true")
                         .possible_value("false")
                         .required(true),
+                )
+                .arg(
+                    Arg::with_name("join")
+                        .long("join")
+                        .value_name("SOCKET")
+                        .help("Join the fleet of the supervisor listening on this control socket"),
+                )
+                .arg(
+                    Arg::with_name("registry")
+                        .long("registry")
+                        .value_name("FILE")
+                        .help("Check agreement against this registry, or control socket (default: the one of --join)"),
+                )
+                .arg(
+                    Arg::with_name("gossip")
+                        .long("gossip")
+                        .help("Discover peers by gossiping with them"),
+                )
+                .arg(
+                    Arg::with_name("bootstrap")
+                        .long("bootstrap")
+                        .value_name("PORT[:FINGERPRINT]")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .requires("gossip")
+                        .help("Start gossiping with the agent listening on this port, with this TLS certificate if specified")
+                        .validator(|s| gossip::parse_bootstrap(&s).map(|_| ())),
+                )
+                .arg(
+                    Arg::with_name("gossip-period")
+                        .long("gossip-period")
+                        .value_name("ms")
+                        .default_value("1000")
+                        .validator(|s| {
+                            s.parse::<u64>().map(|_| ()).map_err(|e| format!("{}", e))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("snowball")
+                        .long("snowball")
+                        .requires("gossip")
+                        .help("Converge on a value by repeatedly sampling peers"),
+                )
+                .arg(
+                    Arg::with_name("snowball-sample")
+                        .long("snowball-sample")
+                        .value_name("number")
+                        .default_value("5")
+                        .help("Number of peers to sample at each step")
+                        .validator(|s| match s.parse::<usize>() {
+                            Err(e) => Err(format!("{}", e)),
+                            Ok(0) => Err("Expected a positive number".to_string()),
+                            Ok(_) => Ok(()),
+                        }),
+                )
+                .arg(
+                    Arg::with_name("snowball-alpha")
+                        .long("snowball-alpha")
+                        .value_name("number")
+                        .default_value("3")
+                        .help("Number of peers in a sample that must agree for the sample to count")
+                        .validator(|s| {
+                            s.parse::<usize>().map(|_| ()).map_err(|e| format!("{}", e))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("snowball-beta")
+                        .long("snowball-beta")
+                        .value_name("number")
+                        .default_value("5")
+                        .help("Number of consecutive successful samples needed to decide")
+                        .validator(|s| match s.parse::<usize>() {
+                            Err(e) => Err(format!("{}", e)),
+                            Ok(0) => Err("Expected a positive number".to_string()),
+                            Ok(_) => Ok(()),
+                        }),
+                )
+                .arg(
+                    Arg::with_name("snowball-period")
+                        .long("snowball-period")
+                        .value_name("ms")
+                        .default_value("100")
+                        .validator(|s| {
+                            s.parse::<u64>().map(|_| ()).map_err(|e| format!("{}", e))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("stubborn")
+                        .long("stubborn")
+                        .requires("snowball")
+                        .help("Never change value, claim to have decided"),
+                )
+                .arg(
+                    Arg::with_name("equivocate")
+                        .long("equivocate")
+                        .help("Misbehave: vouch for the value, but campaign for the opposite value"),
+                )
+                .arg(
+                    Arg::with_name("tls-cert")
+                        .long("tls-cert")
+                        .value_name("FILE")
+                        .requires("tls-key")
+                        .help("Only speak TLS, with this PEM certificate"),
+                )
+                .arg(
+                    Arg::with_name("tls-key")
+                        .long("tls-key")
+                        .value_name("FILE")
+                        .requires("tls-cert")
+                        .help("The PKCS#8 PEM private key of the TLS certificate"),
+                )
+                .arg(
+                    Arg::with_name("admin-token")
+                        .long("admin-token")
+                        .value_name("FILE")
+                        .help("Require the token stored in this file on administrative messages"),
+                )
+                .arg(
+                    Arg::with_name("max-line")
+                        .long("max-line")
+                        .value_name("bytes")
+                        .default_value("1048576")
+                        .help("Refuse requests longer than this, and close the connection")
+                        .validator(|s| match s.parse::<usize>() {
+                            Err(e) => Err(format!("{}", e)),
+                            Ok(0) => Err("Expected a positive number".to_string()),
+                            Ok(_) => Ok(()),
+                        }),
+                )
+                .arg(
+                    Arg::with_name("max-children")
+                        .long("max-children")
+                        .value_name("number")
+                        .default_value("1024")
+                        .help("Refuse requests listing more agents than this")
+                        .validator(|s| {
+                            s.parse::<usize>().map(|_| ()).map_err(|e| format!("{}", e))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("max-connections")
+                        .long("max-connections")
+                        .value_name("number")
+                        .default_value("1024")
+                        .help("Refuse connections beyond this number of concurrent connections")
+                        .validator(|s| {
+                            s.parse::<usize>().map(|_| ()).map_err(|e| format!("{}", e))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("rate")
+                        .long("rate")
+                        .value_name("requests/s")
+                        .help("Refuse requests beyond this average rate, per connection")
+                        .validator(|s| match s.parse::<f64>() {
+                            Err(e) => Err(format!("{}", e)),
+                            Ok(v) if v > 0. => Ok(()),
+                            Ok(v) => Err(format!("Expected a positive rate, got {}", v)),
+                        }),
+                )
+                .arg(
+                    Arg::with_name("burst")
+                        .long("burst")
+                        .value_name("number")
+                        .default_value("100")
+                        .help("Number of requests a connection may send at once when rate-limited")
+                        .validator(|s| {
+                            s.parse::<usize>().map(|_| ()).map_err(|e| format!("{}", e))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("idle-timeout")
+                        .long("idle-timeout")
+                        .value_name("ms")
+                        .default_value("60000")
+                        .help("Close connections that don't send a request for this long")
+                        .validator(|s| {
+                            s.parse::<u64>().map(|_| ()).map_err(|e| format!("{}", e))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("cache-ttl")
+                        .long("cache-ttl")
+                        .value_name("ms")
+                        .default_value("5000")
+                        .help("Reuse the certificates of peers for this long across rounds, while the registry keeps its epoch, 0 to always ask")
+                        .validator(|s| {
+                            s.parse::<u64>().map(|_| ()).map_err(|e| format!("{}", e))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("metrics")
+                        .long("metrics")
+                        .value_name("PORT")
+                        .help("Serve metrics on http://127.0.0.1:PORT/metrics")
+                        .validator(|s| {
+                            s.parse::<u16>().map(|_| ()).map_err(|e| format!("{}", e))
+                        }),
+                )
+                .arg(
+                    Arg::with_name("debug-status")
+                        .long("debug-status")
+                        .help("Tell whether this agent is honest, stubborn or equivocating when asked for its status"),
                 ),
         )
         .subcommand(
@@ -71,6 +640,7 @@ async fn main() {
                     Arg::with_name("agents")
                         .long("agents")
                         .value_name("FILE")
+                        .help("The registry, or the control socket of a supervisor")
                         .default_value("agents.conf")
                 )
                 .arg(
@@ -80,13 +650,62 @@ async fn main() {
                         .default_value("0.1")
                         .validator(|s| match s.parse::<f64>() {
                             Err(e) => Err(format!("{}", e)),
-                            Ok(v) if 0. <= v && v < 0.5 => Ok(()),
+                            Ok(v) if (0. ..0.5).contains(&v) => Ok(()),
                             Ok(v) => Err(format!("Expected a value in [0., 0.5[, got {}", v)),
                         }),
+                )
+                .arg(
+                    Arg::with_name("discover")
+                        .long("discover")
+                        .help("Let agents campaign among the peers they know, only count the votes of listed agents"),
+                )
+                .arg(
+                    Arg::with_name("transcript")
+                        .long("transcript")
+                        .value_name("FILE")
+                        .help("Record the round to this file, to replay it with `replay`"),
+                )
+                .arg(
+                    Arg::with_name("reputation")
+                        .long("reputation")
+                        .value_name("FILE")
+                        .help("Record which agents disagreed with the decision to this file"),
+                )
+                .arg(
+                    Arg::with_name("avoid-suspects")
+                        .long("avoid-suspects")
+                        .requires("reputation")
+                        .help("Prefer agents that are not suspected to be liars"),
+                )
+                .arg(
+                    Arg::with_name("stream")
+                        .long("stream")
+                        .help("Decide as soon as any agent has found a quorum, without waiting for the others"),
+                )
+                .arg(
+                    Arg::with_name("aggregate")
+                        .long("aggregate")
+                        .conflicts_with("stream")
+                        .help("Have agents combine the votes of their allies into a single certificate"),
+                )
+                .arg(
+                    Arg::with_name("certificate")
+                        .long("certificate")
+                        .value_name("FILE")
+                        .help("Write a certificate of the decision to this file, see `verify`"),
                 ),
         );
 
-    match app.get_matches().subcommand() {
+    let matches = app.get_matches();
+    if let Some(format) = matches.value_of("log-format") {
+        std::env::set_var(trace::LOG_FORMAT, format);
+    }
+    if let Some(path) = matches.value_of("trace-file") {
+        std::env::set_var(trace::TRACE_FILE, path);
+    }
+    trace::init_logging();
+
+    match matches.subcommand() {
         ("start", Some(args)) => {
             let start_args = start::StartArgs {
                 value: match args.value_of("value") {
@@ -104,10 +723,98 @@ async fn main() {
                     .parse::<f64>()
                     .expect("Invalud value: value"),
                 exe: std::env::current_exe().expect("Could not get executable"),
+                weights: args
+                    .value_of("weights")
+                    .expect("Missing arg: weights")
+                    .parse::<start::Weights>()
+                    .expect("Invalud value: weights"),
+                tls: args.value_of("tls").map(|dir| {
+                    dir.parse::<std::path::PathBuf>()
+                        .expect("Invalud value: tls")
+                }),
+                admin_token: args.value_of("admin-token").map(|path| {
+                    path.parse::<std::path::PathBuf>()
+                        .expect("Invalud value: admin-token")
+                }),
+                gossip: args.is_present("gossip"),
+                snowball: args.is_present("snowball"),
             };
             assert!(start_args.liar_ratio >= 0.);
             assert!(start_args.liar_ratio < 0.5);
-            start::start(&start_args).await;
+            if args.is_present("daemon") {
+                let chaos = args
+                    .value_of("chaos-period")
+                    .map(|period| supervisor::ChaosArgs {
+                        period: std::time::Duration::from_millis(
+                            period.parse::<u64>().expect("Invalud value: chaos-period"),
+                        ),
+                        kill_probability: args
+                            .value_of("chaos-probability")
+                            .expect("Missing arg: chaos-probability")
+                            .parse::<f64>()
+                            .expect("Invalud value: chaos-probability"),
+                    });
+                let supervise_args = supervisor::SuperviseArgs {
+                    path: std::path::PathBuf::from("agents.conf"),
+                    control: Some(
+                        args.value_of("control")
+                            .expect("Missing arg: control")
+                            .parse::<std::path::PathBuf>()
+                            .expect("Invalud value: control"),
+                    ),
+                    chaos,
+                    metrics: args
+                        .value_of("metrics")
+                        .map(|port| port.parse::<u16>().expect("Invalud value: metrics")),
+                };
+                let supervisor = supervisor::Supervisor::start(&start_args, supervise_args).await;
+                let (tshutdown, rshutdown) = tokio::sync::oneshot::channel();
+                tokio::spawn(async move {
+                    let _ = tokio::signal::ctrl_c().await;
+                    let _ = tshutdown.send(());
+                });
+                supervisor.run(rshutdown).await;
+            } else {
+                start::start(&start_args).await;
+            }
+        }
+        ("control", Some(args)) => {
+            let command = match args.subcommand() {
+                ("status", _) => supervisor::Command::Status,
+                ("stop", _) => supervisor::Command::Stop,
+                ("membership", _) => supervisor::Command::Membership,
+                ("restart", Some(args)) => supervisor::Command::Restart(
+                    args.value_of("pid")
+                        .expect("Missing arg: pid")
+                        .parse::<u32>()
+                        .expect("Invalud value: pid"),
+                ),
+                ("add-agents", Some(args)) => supervisor::Command::AddAgents(
+                    args.value_of("number")
+                        .expect("Missing arg: number")
+                        .parse::<usize>()
+                        .expect("Invalud value: number"),
+                ),
+                ("set-liars", Some(args)) => supervisor::Command::SetLiars(
+                    args.value_of("number")
+                        .expect("Missing arg: number")
+                        .parse::<usize>()
+                        .expect("Invalud value: number"),
+                ),
+                _ => panic!("Missing command"),
+            };
+            let control_args = supervisor::ControlArgs {
+                control: args
+                    .value_of("control")
+                    .expect("Missing arg: control")
+                    .parse::<std::path::PathBuf>()
+                    .expect("Invalud value: control"),
+                command,
+            };
+            if let Err(err) = supervisor::control(&control_args).await {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
         }
         ("agent", Some(args)) => {
             let agent_args = agent::AgentArgs {
@@ -116,7 +823,128 @@ async fn main() {
                     "false" => false,
                     v => panic!("Invalid boolean {}", v),
                 },
+                join: args.value_of("join").map(|join| {
+                    join.parse::<std::path::PathBuf>()
+                        .expect("Invalud value: join")
+                }),
+                registry: args.value_of("registry").map(|registry| {
+                    registry
+                        .parse::<std::path::PathBuf>()
+                        .expect("Invalud value: registry")
+                }),
+                gossip: if args.is_present("gossip") {
+                    Some(gossip::GossipArgs {
+                        bootstrap: args
+                            .values_of("bootstrap")
+                            .map(|agents| {
+                                agents
+                                    .map(|agent| {
+                                        gossip::parse_bootstrap(agent)
+                                            .expect("Invalud value: bootstrap")
+                                    })
+                                    .collect()
+                            })
+                            .unwrap_or_default(),
+                        period: std::time::Duration::from_millis(
+                            args.value_of("gossip-period")
+                                .expect("Missing arg: gossip-period")
+                                .parse::<u64>()
+                                .expect("Invalud value: gossip-period"),
+                        ),
+                    })
+                } else {
+                    None
+                },
+                snowball: if args.is_present("snowball") {
+                    Some(snowball::SnowballArgs {
+                        sample: args
+                            .value_of("snowball-sample")
+                            .expect("Missing arg: snowball-sample")
+                            .parse::<usize>()
+                            .expect("Invalud value: snowball-sample"),
+                        alpha: args
+                            .value_of("snowball-alpha")
+                            .expect("Missing arg: snowball-alpha")
+                            .parse::<usize>()
+                            .expect("Invalud value: snowball-alpha"),
+                        beta: args
+                            .value_of("snowball-beta")
+                            .expect("Missing arg: snowball-beta")
+                            .parse::<usize>()
+                            .expect("Invalud value: snowball-beta"),
+                        period: std::time::Duration::from_millis(
+                            args.value_of("snowball-period")
+                                .expect("Missing arg: snowball-period")
+                                .parse::<u64>()
+                                .expect("Invalud value: snowball-period"),
+                        ),
+                        stubborn: args.is_present("stubborn"),
+                    })
+                } else {
+                    None
+                },
+                metrics: args
+                    .value_of("metrics")
+                    .map(|port| port.parse::<u16>().expect("Invalud value: metrics")),
+                equivocate: args.is_present("equivocate"),
+                tls: match (args.value_of("tls-cert"), args.value_of("tls-key")) {
+                    (Some(cert), Some(key)) => Some(tls::TlsArgs {
+                        cert: cert.parse().expect("Invalud value: tls-cert"),
+                        key: key.parse().expect("Invalud value: tls-key"),
+                    }),
+                    _ => None,
+                },
+                admin_token: args.value_of("admin-token").map(|path| {
+                    path.parse::<std::path::PathBuf>()
+                        .expect("Invalud value: admin-token")
+                }),
+                limits: limits::Limits {
+                    max_line: args
+                        .value_of("max-line")
+                        .expect("Missing arg: max-line")
+                        .parse::<usize>()
+                        .expect("Invalud value: max-line"),
+                    max_children: args
+                        .value_of("max-children")
+                        .expect("Missing arg: max-children")
+                        .parse::<usize>()
+                        .expect("Invalud value: max-children"),
+                    max_connections: args
+                        .value_of("max-connections")
+                        .expect("Missing arg: max-connections")
+                        .parse::<usize>()
+                        .expect("Invalud value: max-connections"),
+                    rate: args
+                        .value_of("rate")
+                        .map(|rate| rate.parse::<f64>().expect("Invalud value: rate")),
+                    burst: args
+                        .value_of("burst")
+                        .expect("Missing arg: burst")
+                        .parse::<usize>()
+                        .expect("Invalud value: burst"),
+                    idle_timeout: std::time::Duration::from_millis(
+                        args.value_of("idle-timeout")
+                            .expect("Missing arg: idle-timeout")
+                            .parse::<u64>()
+                            .expect("Invalud value: idle-timeout"),
+                    ),
+                },
+                cache_ttl: std::time::Duration::from_millis(
+                    args.value_of("cache-ttl")
+                        .expect("Missing arg: cache-ttl")
+                        .parse::<u64>()
+                        .expect("Invalud value: cache-ttl"),
+                ),
+                debug_status: args.is_present("debug-status"),
             };
+            if let Some(Err(err)) = agent_args
+                .snowball
+                .as_ref()
+                .map(snowball::SnowballArgs::check)
+            {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
             agent::agent(&agent_args).await;
             unreachable!();
         }
@@ -127,8 +955,164 @@ async fn main() {
                     .expect("Missing arg: agents")
                     .parse::<std::path::PathBuf>()
                     .expect("Invalud value: agents"),
+                transcript: args.value_of("transcript").map(|path| {
+                    path.parse::<std::path::PathBuf>()
+                        .expect("Invalud value: transcript")
+                }),
+                reputation: args.value_of("reputation").map(|path| {
+                    path.parse::<std::path::PathBuf>()
+                        .expect("Invalud value: reputation")
+                }),
+            };
+            let value = play::play(&play_args).await;
+            print_outcome(value, &stats::stats().outgoing);
+        }
+        ("suspects", Some(args)) => {
+            let path = args
+                .value_of("reputation")
+                .expect("Missing arg: reputation")
+                .parse::<std::path::PathBuf>()
+                .expect("Invalud value: reputation");
+            if let Err(err) = reputation::suspects(&path) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        ("replay", Some(args)) => {
+            let path = args
+                .value_of("transcript")
+                .expect("Missing arg: transcript")
+                .parse::<std::path::PathBuf>()
+                .expect("Invalud value: transcript");
+            if let Err(err) = transcript::replay(&path) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        ("verify", Some(args)) => {
+            let path = args
+                .value_of("certificate")
+                .expect("Missing arg: certificate")
+                .parse::<std::path::PathBuf>()
+                .expect("Invalud value: certificate");
+            let agents = args
+                .value_of("agents")
+                .expect("Missing arg: agents")
+                .parse::<std::path::PathBuf>()
+                .expect("Invalud value: agents");
+            if let Err(err) = decision::verify(&path, &agents).await {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        ("status", Some(args)) => {
+            let path = args
+                .value_of("agents")
+                .expect("Missing arg: agents")
+                .parse::<std::path::PathBuf>()
+                .expect("Invalud value: agents");
+            if let Err(err) = status::status(&path).await {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        ("playagree", Some(args)) => {
+            let play_args = playagree::PlayAgreeArgs {
+                path: args
+                    .value_of("agents")
+                    .expect("Missing arg: agents")
+                    .parse::<std::path::PathBuf>()
+                    .expect("Invalud value: agents"),
+            };
+            let value = playagree::play(&play_args).await;
+            print_outcome(value, &stats::stats().outgoing);
+        }
+        ("playsample", Some(args)) => {
+            let play_args = playsample::PlaySampleArgs {
+                path: args
+                    .value_of("agents")
+                    .expect("Missing arg: agents")
+                    .parse::<std::path::PathBuf>()
+                    .expect("Invalud value: agents"),
+                liar_ratio: args
+                    .value_of("liar-ratio")
+                    .expect("Missing arg: liar-ratio")
+                    .parse::<f64>()
+                    .expect("Invalud value: liar-ratio"),
+                confidence: args
+                    .value_of("confidence")
+                    .expect("Missing arg: confidence")
+                    .parse::<f64>()
+                    .expect("Invalud value: confidence"),
+                batch: args
+                    .value_of("batch")
+                    .expect("Missing arg: batch")
+                    .parse::<usize>()
+                    .expect("Invalud value: batch"),
+            };
+            let outcome = playsample::play(&play_args).await;
+            print_outcome(outcome.value, &stats::stats().outgoing);
+            println!("confidence: {}", outcome.confidence);
+        }
+        ("playsnowball", Some(args)) => {
+            let play_args = playsnowball::PlaySnowballArgs {
+                path: args
+                    .value_of("agents")
+                    .expect("Missing arg: agents")
+                    .parse::<std::path::PathBuf>()
+                    .expect("Invalud value: agents"),
+                ask: args
+                    .value_of("ask")
+                    .expect("Missing arg: ask")
+                    .parse::<usize>()
+                    .expect("Invalud value: ask"),
+            };
+            let value = playsnowball::play(&play_args).await;
+            print_outcome(value, &stats::stats().outgoing);
+        }
+        ("bench", Some(args)) => {
+            let bench_args = bench::BenchArgs {
+                exe: std::env::current_exe().expect("Could not get executable"),
+                num_agents: args
+                    .values_of("num-agents")
+                    .expect("Missing arg: num-agents")
+                    .map(|n| n.parse::<usize>().expect("Invalud value: num-agents"))
+                    .collect(),
+                liar_ratios: args
+                    .values_of("liar-ratio")
+                    .expect("Missing arg: liar-ratio")
+                    .map(|r| r.parse::<f64>().expect("Invalud value: liar-ratio"))
+                    .collect(),
+                strategies: args
+                    .values_of("strategy")
+                    .expect("Missing arg: strategy")
+                    .map(|s| {
+                        s.parse::<bench::Strategy>()
+                            .expect("Invalud value: strategy")
+                    })
+                    .collect(),
+                rounds: args
+                    .value_of("rounds")
+                    .expect("Missing arg: rounds")
+                    .parse::<usize>()
+                    .expect("Invalud value: rounds"),
+                warmup: std::time::Duration::from_millis(
+                    args.value_of("warmup")
+                        .expect("Missing arg: warmup")
+                        .parse::<u64>()
+                        .expect("Invalud value: warmup"),
+                ),
             };
-            play::play(&play_args).await;
+            let rows = bench::bench(&bench_args).await;
+            match args.value_of("format") {
+                Some("json") => println!("{}", serde_json::to_string_pretty(&rows).unwrap()),
+                _ => {
+                    println!("{}", bench::Row::CSV_HEADER);
+                    for row in rows {
+                        println!("{}", row.to_csv());
+                    }
+                }
+            }
         }
         ("playexpert", Some(args)) => {
             let play_args = playexpert::PlayExpertArgs {
@@ -142,8 +1126,25 @@ async fn main() {
                     .expect("Missing arg: value")
                     .parse::<f64>()
                     .expect("Invalud value: value"),
+                discover: args.is_present("discover"),
+                transcript: args.value_of("transcript").map(|path| {
+                    path.parse::<std::path::PathBuf>()
+                        .expect("Invalud value: transcript")
+                }),
+                reputation: args.value_of("reputation").map(|path| {
+                    path.parse::<std::path::PathBuf>()
+                        .expect("Invalud value: reputation")
+                }),
+                avoid_suspects: args.is_present("avoid-suspects"),
+                stream: args.is_present("stream"),
+                aggregate: args.is_present("aggregate"),
+                certificate: args.value_of("certificate").map(|path| {
+                    path.parse::<std::path::PathBuf>()
+                        .expect("Invalud value: certificate")
+                }),
             };
-            playexpert::play(&play_args).await;
+            let value = playexpert::play(&play_args).await;
+            print_outcome(value, &stats::stats().outgoing);
         }
         _ => {
             panic!("Missing command");
@@ -0,0 +1,292 @@
+//! Signed certificates and provable evidence of equivocation.
+//!
+//! Each agent signs its certificates with a key generated at startup. A
+//! certificate is bound to a round, i.e. the trace id of the request that
+//! caused it to be issued, see module `trace`, or to an epoch of the
+//! registry, see module `cache`. An agent that signs both `true` and
+//! `false` in the same round or epoch is provably faulty: the two
+//! certificates form an `EquivocationProof`, which anybody can check
+//! without trusting whoever found it.
+//!
+//! Agents that receive a valid proof exclude the culprit from their quorums.
+//!
+//! Keys are published along with agents, see `Child::signing_key`:
+//! certificates, hence proofs, are only valid if they are signed with the
+//! key published for their issuer, so that nobody can forge a proof
+//! against an honest agent. Agents check proofs against their own registry,
+//! never against keys chosen by the accuser, see `conf::registry`, and
+//! identify culprits by pid, port and key.
+
+use std::collections::VecDeque;
+use std::sync::{Mutex, OnceLock};
+
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::agent::{Certificate, Message, RemoteAgent, Response};
+use crate::conf::Child;
+use crate::util::{from_hex, to_hex};
+
+/// The number of certificates remembered by `observe`.
+const EVIDENCE_CAPACITY: usize = 4096;
+
+static EVIDENCE: Mutex<VecDeque<Certificate>> = Mutex::new(VecDeque::new());
+static EXCLUDED: Mutex<Vec<Child>> = Mutex::new(Vec::new());
+
+/// The key of this process, generated upon first use.
+fn keypair() -> &'static Keypair {
+    static KEYPAIR: OnceLock<Keypair> = OnceLock::new();
+    KEYPAIR.get_or_init(|| Keypair::generate(&mut rand::rngs::OsRng))
+}
+
+/// The public key of this process, hex-encoded, to be published in the registry.
+pub fn public_key() -> String {
+    to_hex(keypair().public.as_bytes())
+}
+
+/// The agent of `children` that is `issuer`, with its published key.
+fn published<'a>(issuer: &Child, children: &'a [Child]) -> Option<&'a Child> {
+    children.iter().find(|child| *child == issuer)
+}
+
+/// The bytes covered by the signature of a certificate.
+fn payload(value: bool, issuer: &Child, round: Option<u64>, epoch: Option<u64>) -> Vec<u8> {
+    format!(
+        "liarslie/certificate/{}/{}/{}/{}/{}",
+        issuer.pid,
+        issuer.socket,
+        crate::trace::format(round),
+        epoch.map_or_else(|| "-".to_string(), |epoch| epoch.to_string()),
+        value
+    )
+    .into_bytes()
+}
+
+/// Sign `message` with the key of this process, return the signature, hex-encoded.
+pub fn sign_message(message: &[u8]) -> String {
+    to_hex(&keypair().sign(message).to_bytes())
+}
+
+/// Check that `signature` of `message` was made with the key published for `signer`.
+pub fn verify_message(message: &[u8], signature: &str, signer: &Child) -> bool {
+    let key = match signer
+        .signing_key
+        .as_ref()
+        .and_then(|key| from_hex(key))
+        .and_then(|key| PublicKey::from_bytes(&key).ok())
+    {
+        Some(key) => key,
+        None => return false,
+    };
+    let signature =
+        match from_hex(signature).and_then(|signature| Signature::from_bytes(&signature).ok()) {
+            Some(signature) => signature,
+            None => return false,
+        };
+    key.verify(message, &signature).is_ok()
+}
+
+/// Issue a certificate for `value`, in round `round`, signed with the key of this process.
+pub fn sign(value: bool, issuer: &Child, round: Option<u64>) -> Certificate {
+    Certificate {
+        value,
+        issuer: issuer.clone(),
+        round,
+        epoch: None,
+        key: public_key(),
+        signature: sign_message(&payload(value, issuer, round, None)),
+        vote: Some(crate::aggregate::vote(value, issuer)),
+    }
+}
+
+/// Issue a certificate for `value`, bound to epoch `epoch` of the registry
+/// rather than to a round, signed with the key of this process.
+pub fn sign_in_epoch(value: bool, issuer: &Child, epoch: u64) -> Certificate {
+    Certificate {
+        value,
+        issuer: issuer.clone(),
+        round: None,
+        epoch: Some(epoch),
+        key: public_key(),
+        signature: sign_message(&payload(value, issuer, None, Some(epoch))),
+        vote: Some(crate::aggregate::vote(value, issuer)),
+    }
+}
+
+/// Check that `certificate` has been issued by `issuer` and signed with
+/// the key published for it.
+///
+/// Certificates of agents that have no published key are never valid.
+pub fn verify(certificate: &Certificate, issuer: &Child) -> bool {
+    certificate.issuer == *issuer
+        && issuer.signing_key.as_ref() == Some(&certificate.key)
+        && verify_message(
+            &payload(
+                certificate.value,
+                &certificate.issuer,
+                certificate.round,
+                certificate.epoch,
+            ),
+            &certificate.signature,
+            issuer,
+        )
+}
+
+/// Whether `first` and `second` are valid certificates signed by `culprit`,
+/// in the same round or epoch, for different values.
+///
+/// A certificate bound to an epoch holds in every round, so it also
+/// conflicts with certificates bound to a round.
+fn conflict(first: &Certificate, second: &Certificate, culprit: &Child) -> bool {
+    let overlap = match (first.round, first.epoch, second.round, second.epoch) {
+        (Some(first), _, Some(second), _) => first == second,
+        (_, Some(first), _, Some(second)) => first == second,
+        (Some(_), _, _, Some(_)) | (_, Some(_), Some(_), _) => true,
+        _ => false,
+    };
+    // Check signatures last, they're expensive.
+    overlap && first.value != second.value && verify(first, culprit) && verify(second, culprit)
+}
+
+/// Two certificates signed by the same agent, in the same round or epoch, for different values.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EquivocationProof {
+    pub first: Certificate,
+    pub second: Certificate,
+}
+impl EquivocationProof {
+    /// Build a proof from two certificates, if they conflict, `culprit`
+    /// being their issuer, with its published key.
+    pub fn try_new(first: &Certificate, second: &Certificate, culprit: &Child) -> Option<Self> {
+        if !conflict(first, second, culprit) {
+            return None;
+        }
+        Some(EquivocationProof {
+            first: first.clone(),
+            second: second.clone(),
+        })
+    }
+
+    /// Check the proof against the key published for the culprit among
+    /// `children`, without trusting whoever found it.
+    pub fn verify(&self, children: &[Child]) -> bool {
+        published(self.culprit(), children)
+            .is_some_and(|culprit| conflict(&self.first, &self.second, culprit))
+    }
+
+    pub fn culprit(&self) -> &Child {
+        &self.first.issuer
+    }
+}
+
+/// Find the agents of `children` that have equivocated among `certificates`.
+///
+/// At most one proof per culprit.
+pub fn find<'a>(
+    certificates: impl IntoIterator<Item = &'a Certificate>,
+    children: &[Child],
+) -> Vec<EquivocationProof> {
+    let mut seen: Vec<&Certificate> = vec![];
+    let mut proofs: Vec<EquivocationProof> = vec![];
+    for certificate in certificates {
+        if proofs
+            .iter()
+            .any(|proof| proof.culprit().pid == certificate.issuer.pid)
+        {
+            continue;
+        }
+        let culprit = match published(&certificate.issuer, children) {
+            Some(culprit) => culprit,
+            None => continue,
+        };
+        match seen
+            .iter()
+            .find_map(|other| EquivocationProof::try_new(other, certificate, culprit))
+        {
+            Some(proof) => proofs.push(proof),
+            None => seen.push(certificate),
+        }
+    }
+    proofs
+}
+
+/// Remember `certificate`, issued by `issuer`, return a proof if it
+/// conflicts with a certificate previously observed by this process.
+pub fn observe(certificate: &Certificate, issuer: &Child) -> Option<EquivocationProof> {
+    if certificate.round.is_none() && certificate.epoch.is_none() {
+        return None;
+    }
+    let mut evidence = EVIDENCE.lock().unwrap();
+    if let Some(proof) = evidence
+        .iter()
+        .find_map(|other| EquivocationProof::try_new(other, certificate, issuer))
+    {
+        return Some(proof);
+    }
+    if evidence.len() >= EVIDENCE_CAPACITY {
+        evidence.pop_front();
+    }
+    evidence.push_back(certificate.clone());
+    None
+}
+
+/// Whether `first` and `second` are the same agent, with the same key.
+fn same(first: &Child, second: &Child) -> bool {
+    first == second && first.signing_key == second.signing_key
+}
+
+/// Exclude the culprit of `proof` from quorums, if the proof is valid
+/// against the keys published in the registry.
+///
+/// Return `true` if the culprit was not excluded yet.
+pub async fn accept(proof: &EquivocationProof) -> bool {
+    let registry = match crate::conf::registry().await {
+        Some(registry) => registry.children,
+        None => {
+            warn!(target: "accountability", "No registry, ignoring proof against {}", proof.culprit().pid);
+            return false;
+        }
+    };
+    let culprit = match published(proof.culprit(), &registry) {
+        Some(culprit) if conflict(&proof.first, &proof.second, culprit) => culprit,
+        _ => {
+            warn!(target: "accountability", "Rejecting invalid proof against {}", proof.culprit().pid);
+            return false;
+        }
+    };
+    let mut excluded = EXCLUDED.lock().unwrap();
+    if excluded.iter().any(|known| same(known, culprit)) {
+        return false;
+    }
+    info!(target: "accountability", "Agent {} has equivocated, excluding it", culprit.pid);
+    excluded.push(culprit.clone());
+    true
+}
+
+/// Whether `child`, with its key, has been proven to equivocate.
+pub fn is_excluded(child: &Child) -> bool {
+    EXCLUDED
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|known| same(known, child))
+}
+
+/// The agents excluded by this process.
+pub fn excluded() -> Vec<Child> {
+    EXCLUDED.lock().unwrap().clone()
+}
+
+/// Send `proof` to every child.
+pub async fn broadcast(children: Vec<Child>, proof: EquivocationProof) {
+    for child in children {
+        let remote = RemoteAgent::new(child.clone());
+        match remote.call(&Message::Accuse(Box::new(proof.clone()))).await {
+            Ok(Response::Excluded(_)) => {}
+            other => {
+                debug!(target: "accountability", "Could not send proof to {}: {:?}", child.pid, other);
+            }
+        }
+    }
+}
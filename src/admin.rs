@@ -0,0 +1,42 @@
+//! Access control for administrative messages.
+//!
+//! `start` generates a random admin token and stores it in a private file,
+//! which it hands to each agent. Agents then require the token on
+//! administrative messages, see `Message::is_admin`, and refuse them with
+//! `ErrorCode::Unauthorized` to anybody else. Other messages remain public.
+//!
+//! Agents started without a token accept administrative messages from
+//! anybody, as they used to.
+
+use std::path::Path;
+
+use rand::RngCore;
+
+use crate::util;
+
+/// Generate a new admin token, write it to the private file `path`.
+pub fn generate(path: &Path) -> Result<String, std::io::Error> {
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    let token = util::to_hex(&bytes);
+    util::write_private(path, token.as_bytes())?;
+    Ok(token)
+}
+
+/// Read the admin token stored in `path`.
+pub fn load(path: &Path) -> Result<String, std::io::Error> {
+    Ok(std::fs::read_to_string(path)?.trim().to_string())
+}
+
+/// Whether a request carrying `presented` may perform administrative
+/// actions on an agent that expects `expected`.
+pub fn check(expected: Option<&str>, presented: Option<&str>) -> bool {
+    match (expected, presented) {
+        (None, _) => true,
+        (Some(expected), Some(presented)) => {
+            ring::constant_time::verify_slices_are_equal(expected.as_bytes(), presented.as_bytes())
+                .is_ok()
+        }
+        (Some(_), None) => false,
+    }
+}
@@ -1,16 +1,41 @@
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 use log::*;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info_span, Instrument};
 
-use crate::conf::Child;
+use crate::accountability::{self, EquivocationProof};
+use crate::admin;
+use crate::aggregate::{self, AggregateCertificate};
+use crate::agreement::{self, Commitment, Prepared};
+use crate::cache;
+use crate::conf::{self, Child};
+use crate::gossip::{self, GossipArgs, View};
+use crate::limits::{Connections, Limits, RateLimiter};
+use crate::metrics;
+use crate::snowball::{self, SnowballArgs};
+use crate::stats::{self, Stats, Traffic};
+use crate::status::{self, Status};
+use crate::supervisor::{Command, RemoteSupervisor, Reply};
+use crate::tls::{self, TlsArgs};
+use crate::trace;
 use crate::util;
 use serde_derive::{Deserialize, Serialize};
 use serde_json;
 
-#[derive(Debug, Deserialize, Serialize)]
+/// How long to wait before accepting again after an error.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Message {
+    /// Close the connection.
+    ///
+    /// Administrative, see module `admin`. Response is `Response::Stop`.
     Stop,
 
     /// Get the value carried by this agent.
@@ -18,162 +43,674 @@ pub enum Message {
     /// Response is `Response::Certificate(Certificate)`.
     GetValue,
 
+    /// Get the value carried by this agent, bound to the given epoch of
+    /// the registry rather than to the current round, so that it can be
+    /// reused across rounds, see module `cache`.
+    ///
+    /// Response is `Response::Certificate(Certificate)`.
+    GetVote(u64),
+
     /// Request a list of allies for this agent.
     ///
     /// Response is `Response::Quorum(...)`.
     Campaign(Vec<Child>),
+
+    /// Request a list of allies for this agent, among the peers it knows.
+    ///
+    /// Response is `Response::Quorum(...)`.
+    CampaignPeers,
+
+    /// Like `Campaign`, but the agent combines the votes of its allies into
+    /// a single certificate, see module `aggregate`.
+    ///
+    /// Response is `Response::Aggregate(...)` or `Response::Undecided` if
+    /// the agent has no ally with a valid vote.
+    AggregateCampaign(Vec<Child>),
+
+    /// Like `Campaign`, but the agent sends each ally as soon as it has found it.
+    ///
+    /// Response is any number of `Response::Vote(...)`, then `Response::EndOfStream`.
+    StreamCampaign(Vec<Child>),
+
+    /// Like `CampaignPeers`, but the agent sends each ally as soon as it has found it.
+    ///
+    /// Response is any number of `Response::Vote(...)`, then `Response::EndOfStream`.
+    StreamCampaignPeers,
+
+    /// Get the peers known to this agent, including itself.
+    ///
+    /// Response is `Response::Peers(...)`.
+    GetPeers,
+
+    /// Share the peers known to the sender, including itself.
+    ///
+    /// Response is `Response::Peers(...)`.
+    Gossip(Vec<Child>),
+
+    /// Run a round of agreement among `children`, see module `agreement`.
+    ///
+    /// Response is `Response::Decision(...)` or `Response::Undecided`.
+    Agree { round: u64, children: Vec<Child> },
+
+    /// Phase 1 of a round of agreement among `children`.
+    ///
+    /// Response is `Response::Prepared(...)` or `Response::Undecided`.
+    Prepare { round: u64, children: Vec<Child> },
+
+    /// Let the agent know that the round has been committed.
+    ///
+    /// Response is `Response::Decision(...)` or `Response::Undecided` if
+    /// the commitment is invalid in the registry of the agent.
+    Commit(Commitment),
+
+    /// Get the value committed during a round.
+    ///
+    /// Response is `Response::Decision(...)` or `Response::Undecided`.
+    GetDecision(u64),
+
+    /// Get the preference of this agent, see module `snowball`.
+    ///
+    /// Response is `Response::Preference { .. }`.
+    GetPreference,
+
+    /// Get the traffic recorded by this agent, see module `stats`.
+    ///
+    /// Response is `Response::Stats(...)`.
+    Stats,
+
+    /// Get the status of this agent, see module `status`.
+    ///
+    /// Response is `Response::Status(...)`.
+    Status,
+
+    /// Get the health metrics of this agent, see module `metrics`.
+    ///
+    /// Response is `Response::Metrics(...)`, in the Prometheus text format.
+    Metrics,
+
+    /// Let the agent know that an agent has equivocated, see module `accountability`.
+    ///
+    /// Response is `Response::Excluded(...)`.
+    Accuse(Box<EquivocationProof>),
+}
+impl Message {
+    /// The name of this type of message, for accounting purposes.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Message::Stop => "Stop",
+            Message::GetValue => "GetValue",
+            Message::GetVote(_) => "GetVote",
+            Message::Campaign(_) => "Campaign",
+            Message::CampaignPeers => "CampaignPeers",
+            Message::AggregateCampaign(_) => "AggregateCampaign",
+            Message::StreamCampaign(_) => "StreamCampaign",
+            Message::StreamCampaignPeers => "StreamCampaignPeers",
+            Message::GetPeers => "GetPeers",
+            Message::Gossip(_) => "Gossip",
+            Message::Agree { .. } => "Agree",
+            Message::Prepare { .. } => "Prepare",
+            Message::Commit(_) => "Commit",
+            Message::GetDecision(_) => "GetDecision",
+            Message::GetPreference => "GetPreference",
+            Message::Stats => "Stats",
+            Message::Status => "Status",
+            Message::Metrics => "Metrics",
+            Message::Accuse(_) => "Accuse",
+        }
+    }
+
+    /// The number of agents listed in this message, see `Limits::max_children`.
+    pub fn num_children(&self) -> usize {
+        match *self {
+            Message::Campaign(ref children)
+            | Message::AggregateCampaign(ref children)
+            | Message::StreamCampaign(ref children)
+            | Message::Gossip(ref children) => children.len(),
+            Message::Agree { ref children, .. } | Message::Prepare { ref children, .. } => {
+                children.len()
+            }
+            _ => 0,
+        }
+    }
+
+    /// Whether this message requires the admin token, see module `admin`.
+    pub fn is_admin(&self) -> bool {
+        matches!(*self, Message::Stop)
+    }
+}
+/// A message, as sent on the wire.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Request {
+    /// The round this message belongs to, see module `trace`.
+    #[serde(default)]
+    pub trace: Option<u64>,
+
+    /// The span of the call, within the round.
+    #[serde(default)]
+    pub span: Option<u64>,
+
+    /// The admin token, only sent along with administrative messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+
+    /// The port of the agent sending this message, if it is sent by an
+    /// agent, see module `stats`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<u16>,
+    pub message: Message,
 }
+
 #[derive(Debug, Deserialize, Serialize)]
 pub enum Response {
     Stop,
     Certificate(Certificate),
     Quorum(Vec<Certificate>),
+
+    /// The allies of an aggregate campaign, see `Message::AggregateCampaign`.
+    Aggregate(AggregateCertificate),
+
+    /// One of the allies of a streaming campaign, see `Message::StreamCampaign`.
+    Vote(Certificate),
+
+    /// The end of a streaming campaign.
+    EndOfStream,
+    Peers(Vec<Child>),
+    Prepared(Prepared),
+    Decision(Commitment),
+    Undecided,
+    Preference {
+        value: bool,
+        decided: bool,
+    },
+    Stats(Stats),
+    Status(Status),
+    Metrics(String),
+
+    /// The agents excluded from quorums by this agent, with their keys.
+    Excluded(Vec<Child>),
+
+    /// The agent refused the request, see `RemoteAgent::call`.
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
 }
 
-/// Representation of an unforgeable response.
-///
-/// In an actual implementation, this could either be
-/// - backed by cryptography; or
-/// - backed by double-checking with the agent that they have issued this response.
-#[derive(Debug, Deserialize, Serialize)]
+/// Why an agent refused a request, see `Response::Error`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ErrorCode {
+    /// The request is not valid JSON.
+    Malformed,
+
+    /// The request is valid JSON, but not a message this agent understands.
+    Unsupported,
+
+    /// The agent is too busy to handle the request, or the client has sent
+    /// too many requests, try again later, see module `limits`.
+    Overloaded,
+
+    /// The request exceeds the limits of the agent, see module `limits`.
+    TooLarge,
+
+    /// The message is administrative, and the request didn't carry the
+    /// right admin token, see module `admin`.
+    Unauthorized,
+}
+impl ErrorCode {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ErrorCode::Malformed => "Malformed",
+            ErrorCode::Unsupported => "Unsupported",
+            ErrorCode::Overloaded => "Overloaded",
+            ErrorCode::TooLarge => "TooLarge",
+            ErrorCode::Unauthorized => "Unauthorized",
+        }
+    }
+
+    /// The error to report for a request that could not be parsed.
+    fn of(err: &serde_json::Error) -> Self {
+        match err.classify() {
+            serde_json::error::Category::Data => ErrorCode::Unsupported,
+            _ => ErrorCode::Malformed,
+        }
+    }
+}
+
+/// Why `RemoteAgent::call` failed.
+#[derive(Debug)]
+pub enum CallError {
+    /// We could not talk to the agent, or could not understand its response.
+    Io(std::io::Error),
+
+    /// The agent responded with `Response::Error`.
+    Refused { code: ErrorCode, message: String },
+}
+impl std::fmt::Display for CallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            CallError::Io(ref err) => write!(f, "{}", err),
+            CallError::Refused { code, ref message } => {
+                write!(f, "Refused ({}): {}", code.name(), message)
+            }
+        }
+    }
+}
+impl std::error::Error for CallError {}
+impl From<std::io::Error> for CallError {
+    fn from(err: std::io::Error) -> Self {
+        CallError::Io(err)
+    }
+}
+
+/// Representation of an unforgeable response, signed by its issuer,
+/// see module `accountability`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Certificate {
     pub value: bool,
     pub issuer: Child,
+
+    /// The round during which the certificate was issued, i.e. a trace id.
+    pub round: Option<u64>,
+
+    /// The epoch of the registry the certificate is bound to, instead of
+    /// a round, so that it can be reused across rounds, see module `cache`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub epoch: Option<u64>,
+
+    /// The public key of the issuer, hex-encoded.
+    pub key: String,
+
+    /// The signature of the issuer, hex-encoded.
+    pub signature: String,
+
+    /// The vote of the issuer, i.e. its BLS signature of `value`,
+    /// hex-encoded, see module `aggregate`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vote: Option<String>,
+}
+impl Certificate {
+    /// Check the signature of the certificate against the key published
+    /// for its issuer among `children`.
+    pub fn verify(&self, children: &[Child]) -> bool {
+        children
+            .iter()
+            .find(|child| **child == self.issuer)
+            .is_some_and(|issuer| accountability::verify(self, issuer))
+    }
 }
 
 /// An agent running in this process
 pub struct Agent {
     value: bool,
     listener: TcpListener,
+    view: View,
+    agreement: agreement::State,
+    snowball: snowball::State,
+    cache: cache::State,
+
+    /// If `true`, misbehave by signing both values, see `AgentArgs::equivocate`.
+    equivocate: bool,
+
+    /// If specified, only speak TLS, see module `tls`.
+    tls: Option<tokio_rustls::TlsAcceptor>,
+
+    /// If specified, require this token on administrative messages, see module `admin`.
+    admin_token: Option<String>,
+
+    /// If specified, disclose this strategy in `Response::Status`.
+    strategy: Option<status::Strategy>,
+
+    limits: Arc<Limits>,
+    connections: Connections,
 }
 impl Agent {
     /// Create an agent, open a socket.
-    pub async fn try_new(value: bool) -> Result<Self, std::io::Error> {
+    ///
+    /// If `tls` is specified, the agent only accepts TLS connections.
+    pub async fn try_new(value: bool, tls: Option<&TlsArgs>) -> Result<Self, std::io::Error> {
+        metrics::init();
+        let (acceptor, fingerprint) = match tls {
+            Some(tls) => (Some(tls.acceptor()?), Some(tls.fingerprint()?)),
+            None => (None, None),
+        };
         let listener = util::retry_future(|| tokio::net::TcpListener::bind("127.0.0.1:0")).await?;
-        Ok(Agent { value, listener })
+        let me = Child {
+            socket: listener.local_addr()?.port(),
+            pid: std::process::id(),
+            weight: 1,
+            fingerprint,
+            key: Some(aggregate::public_key()),
+            signing_key: Some(accountability::public_key()),
+        };
+        Ok(Agent {
+            value,
+            listener,
+            view: View::new(me.clone()),
+            agreement: agreement::State::new(me, None),
+            snowball: snowball::State::new(value),
+            cache: cache::State::default(),
+            equivocate: false,
+            tls: acceptor,
+            admin_token: None,
+            strategy: None,
+            limits: Arc::new(Limits::default()),
+            connections: Connections::default(),
+        })
     }
     pub fn socket(&self) -> SocketAddr {
         self.listener.local_addr().expect("No local address")
     }
 
+    /// The peers known to this agent.
+    pub fn view(&self) -> &View {
+        &self.view
+    }
+
+    /// The preference of this agent in metastable consensus.
+    pub fn snowball(&self) -> &snowball::State {
+        &self.snowball
+    }
+
     /// Enter the loop, forever.
     pub async fn exec(&mut self) {
         let value = self.value;
-        let issuer = Child {
-            socket: self.socket().port(),
-            pid: std::process::id(),
-        };
+        let equivocate = self.equivocate;
+        let strategy = self.strategy;
+        let admin_token = Arc::new(self.admin_token.clone());
+        let issuer = self.view.me().clone();
         loop {
             // Wait for a connection.
             debug!(target: "agent",
                 "Agent: waiting for connection on port {}",
                 self.socket().port()
             );
-            let (mut conn, _) = self
-                .listener
-                .accept()
-                .await
-                .expect("Could not accept connection");
+            let (stream, peer) = match self.listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    // Typically out of file descriptors, wait for some connections to close.
+                    warn!(target: "agent", "Could not accept connection {:?}", err);
+                    metrics::error("accept");
+                    tokio::time::delay_for(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
 
             let issuer = issuer.clone();
+            let view = self.view.clone();
+            let agreement = self.agreement.clone();
+            let snowball = self.snowball.clone();
+            let cache = self.cache.clone();
+            let tls = self.tls.clone();
+            let admin_token = admin_token.clone();
+            let limits = self.limits.clone();
+            let connections = self.connections.clone();
+            stats::incoming(
+                None,
+                None,
+                Traffic {
+                    connections: 1,
+                    ..Traffic::default()
+                },
+            );
             tokio::spawn(async move {
                 let issuer = issuer;
+                let _connection = metrics::Connection::new();
+                // Take a slot before the handshake, so that handshakes count as connections.
+                let _slot = match connections.try_acquire(limits.max_connections) {
+                    Some(slot) => slot,
+                    None => {
+                        debug!(target: "agent", "Too many connections, closing connection.");
+                        metrics::rejected("too_many_connections");
+                        // Over TLS, we would need a handshake to tell the client why.
+                        if tls.is_none() {
+                            let mut conn: Box<dyn tls::Stream> = Box::new(stream);
+                            let response = Response::Error {
+                                code: ErrorCode::Overloaded,
+                                message: "Too many connections".to_string(),
+                            };
+                            let _ = respond(&mut conn, &response).await;
+                        }
+                        return;
+                    }
+                };
+                let mut conn: Box<dyn tls::Stream> = match tls {
+                    None => Box::new(stream),
+                    Some(acceptor) => {
+                        match tokio::time::timeout(limits.idle_timeout, acceptor.accept(stream))
+                            .await
+                        {
+                            Ok(Ok(stream)) => Box::new(stream),
+                            Ok(Err(err)) => {
+                                debug!(target: "agent", "TLS handshake failed, closing connection {:?}.", err);
+                                metrics::error("handshake");
+                                return;
+                            }
+                            Err(_) => {
+                                debug!(target: "agent", "TLS handshake idle, closing connection.");
+                                metrics::rejected("idle_timeout");
+                                return;
+                            }
+                        }
+                    }
+                };
 
                 // Process requests.
+                let mut rate_limiter = RateLimiter::default();
                 let mut reader = BufReader::new(&mut conn);
                 'lines: loop {
                     debug!(target: "agent", "received connection");
                     // Receive message.
                     let mut line = String::new();
-                    let line = match reader.read_line(&mut line).await {
-                        Ok(0) => {
+                    let mut limited = (&mut reader).take(limits.max_line as u64);
+                    let read = limited.read_line(&mut line);
+                    let line = match tokio::time::timeout(limits.idle_timeout, read).await {
+                        Err(_) => {
+                            debug!(target: "agent", "Connection idle, closing connection.");
+                            metrics::rejected("idle_timeout");
+                            break 'lines;
+                        }
+                        Ok(Ok(0)) => {
                             debug!(target: "agent", "connection closed by remote host");
                             break 'lines;
                         }
-                        Ok(_) => line,
-                        Err(err) => {
+                        Ok(Ok(read)) if read >= limits.max_line && !line.ends_with('\n') => {
+                            // We can't find the start of the next request, give up.
+                            debug!(target: "agent", "Request too long, closing connection.");
+                            metrics::rejected("line_too_long");
+                            let response = Response::Error {
+                                code: ErrorCode::TooLarge,
+                                message: format!(
+                                    "Requests are limited to {} bytes",
+                                    limits.max_line
+                                ),
+                            };
+                            let _ = respond(reader.get_mut(), &response).await;
+                            break 'lines;
+                        }
+                        Ok(Ok(_)) => line,
+                        Ok(Err(err)) => {
                             debug!(target: "agent", "Could not read, closing connection {:?}.", err);
+                            metrics::error("read");
                             break 'lines;
                         }
                     };
 
                     debug!(target: "agent", "received message '{}'", line);
-                    let message = match serde_json::from_str(&line) {
+                    let request: Request = match serde_json::from_str(&line) {
                         Err(err) => {
-                            debug!(target: "agent", "Invalid message, closing connection {:?}.", err);
-                            break 'lines;
+                            debug!(target: "agent", "Invalid message {:?}.", err);
+                            metrics::error("invalid_message");
+                            let response = Response::Error {
+                                code: ErrorCode::of(&err),
+                                message: format!("Invalid message: {}", err),
+                            };
+                            let written = respond(reader.get_mut(), &response).await;
+                            stats::incoming(
+                                None,
+                                None,
+                                Traffic {
+                                    bytes_received: line.len(),
+                                    bytes_sent: *written.as_ref().unwrap_or(&0),
+                                    ..Traffic::default()
+                                },
+                            );
+                            if let Err(err) = written {
+                                debug!(target: "agent", "Could not respond, closing connection {:?}.", err);
+                                metrics::error("write");
+                                break 'lines;
+                            }
+                            continue 'lines;
                         }
                         Ok(msg) => msg,
                     };
+                    let Request {
+                        trace,
+                        span,
+                        token,
+                        from,
+                        message,
+                    } = request;
+                    let name = message.name();
 
-                    debug!(target: "agent", "message is correct, preparing response");
-
-                    // And respond.
-                    let response = match message {
-                        Message::Stop => Response::Stop,
-                        Message::GetValue => Response::Certificate(Certificate {
-                            value,
-                            issuer: issuer.clone(),
-                        }),
-                        Message::Campaign(children) => {
-                            debug!(target: "campaign", "{} I'm a process that thinks the value is {}", issuer.pid, value);
-                            let (tcollect, mut rcollect) = tokio::sync::mpsc::channel(32);
-                            let collector = tokio::spawn(async move {
-                                let mut my_party = vec![];
-                                while let Some(certificate) = rcollect.recv().await {
-                                    my_party.push(certificate);
-                                }
-                                my_party
-                            });
-                            {
-                                // Make sure that `tcollect` is dropped after the async loop is over.
-                                let tcollect = tcollect;
-                                debug!(target: "campaign", "{} Talking to {} agents", issuer.pid, children.len());
-                                for child in children {
-                                    let issuer = issuer.clone();
-                                    let mut tcollect = tcollect.clone();
-                                    // We could of course avoid calling ourself.
-                                    // Let's see this as a stress-test for concurrency/reentrancy issues!
-                                    let remote = RemoteAgent::new(child);
-                                    match remote.call(&Message::GetValue).await {
-                                        Ok(Response::Certificate(certificate)) => {
-                                            if certificate.value != value {
-                                                // Remote agent disagrees with us, ignore it.
-                                                debug!(target: "campaign", "{} Process {} thinks that value is {}, ignoring it",
-                                                        issuer.pid,
-                                                        certificate.issuer.pid,
-                                                        certificate.value);
-                                            } else {
-                                                debug!(target: "campaign", "{} Process {} agrees that value is {}, using it",
-                                                        issuer.pid,
-                                                        certificate.issuer.pid,
-                                                        certificate.value);
-                                                tcollect.send(certificate).await.unwrap();
-                                            }
-                                        }
-                                        Err(err) => {
-                                            warn!(target: "campaign", "Couldn't communiccate {:?}", err);
-                                        }
-                                        message => {
-                                            // Remote agent can't or won't respond or bad response, skip it.
-                                            warn!(target: "campaign", "Received a message that doesn't make sense {:?}", message);
-                                        }
+                    // And respond, continuing the trace of the caller.
+                    let handler = info_span!(
+                        parent: None,
+                        "handle",
+                        otel.name = %format_args!("handle {}", name),
+                        trace,
+                        remote_parent = span,
+                        agent.pid = issuer.pid,
+                    );
+                    // Bytes sent before the response, by streaming messages.
+                    let mut streamed = 0;
+                    let response = async {
+                        if !rate_limiter.allow(&limits) {
+                            debug!(target: "agent", "Rate limit exceeded by {}", peer);
+                            metrics::rejected("rate_limited");
+                            return Response::Error {
+                                code: ErrorCode::Overloaded,
+                                message: "Rate limit exceeded".to_string(),
+                            };
+                        }
+                        if message.num_children() > limits.max_children {
+                            debug!(target: "agent", "Too many agents in {}", name);
+                            metrics::rejected("too_many_children");
+                            return Response::Error {
+                                code: ErrorCode::TooLarge,
+                                message: format!("Requests are limited to {} agents", limits.max_children),
+                            };
+                        }
+                        if message.is_admin()
+                            && !admin::check(admin_token.as_deref(), token.as_deref())
+                        {
+                            warn!(target: "agent", "Rejecting {} without a valid admin token", name);
+                            metrics::error("unauthorized");
+                            return Response::Error {
+                                code: ErrorCode::Unauthorized,
+                                message: format!("{} requires the admin token", name),
+                            };
+                        }
+                        debug!(target: "agent", "message {} is correct, preparing response", name);
+                        match message {
+                            Message::Stop => Response::Stop,
+                            Message::GetValue => Response::Certificate(accountability::sign(
+                                value,
+                                &issuer,
+                                trace::current(),
+                            )),
+                            Message::GetVote(epoch) => Response::Certificate(
+                                accountability::sign_in_epoch(value, &issuer, epoch),
+                            ),
+                            Message::Campaign(children) if equivocate => {
+                                Response::Quorum(equivocating_campaign(value, &issuer, children, &cache).await)
+                            }
+                            Message::CampaignPeers if equivocate => {
+                                Response::Quorum(equivocating_campaign(value, &issuer, view.peers(), &cache).await)
+                            }
+                            Message::Campaign(children) => {
+                                Response::Quorum(campaign(value, &issuer, children, &cache).await)
+                            }
+                            Message::CampaignPeers => {
+                                Response::Quorum(campaign(value, &issuer, view.peers(), &cache).await)
+                            }
+                            Message::AggregateCampaign(children) if equivocate => {
+                                let party = equivocating_campaign(value, &issuer, children.clone(), &cache).await;
+                                aggregate_campaign(!value, &children, &party)
+                            }
+                            Message::AggregateCampaign(children) => {
+                                let party = campaign(value, &issuer, children.clone(), &cache).await;
+                                aggregate_campaign(value, &children, &party)
+                            }
+                            Message::StreamCampaign(children) => {
+                                let conn = reader.get_mut();
+                                stream_campaign(value, equivocate, &issuer, &children, &cache, conn, &mut streamed).await
+                            }
+                            Message::StreamCampaignPeers => {
+                                let conn = reader.get_mut();
+                                stream_campaign(value, equivocate, &issuer, &view.peers(), &cache, conn, &mut streamed).await
+                            }
+                            Message::GetPeers => Response::Peers(view.peers()),
+                            Message::Gossip(peers) => {
+                                view.merge(&peers);
+                                Response::Peers(view.peers())
+                            }
+                            Message::Agree { round, children } => {
+                                match agreement.agree(round, &children).await {
+                                    Some(commitment) => {
+                                        // Let everybody know, so that clients may read the decision from anyone.
+                                        trace::spawn(agreement::broadcast(
+                                            children,
+                                            commitment.clone(),
+                                        ));
+                                        Response::Decision(commitment)
                                     }
+                                    None => Response::Undecided,
                                 }
                             }
-                            let party = collector.await.unwrap();
-                            debug!(target: "campaign", "{} Process ready to send proof that {} agents agree on value {}",
-                                issuer.pid,
-                                party.len(),
-                                value
-                            );
-                            Response::Quorum(party)
+                            Message::Prepare { round, children } => {
+                                match agreement.prepare(round, &children).await {
+                                    Some(prepared) => Response::Prepared(prepared),
+                                    None => Response::Undecided,
+                                }
+                            }
+                            Message::Commit(commitment) => match agreement.commit(commitment).await
+                            {
+                                Some(commitment) => Response::Decision(commitment),
+                                None => Response::Undecided,
+                            },
+                            Message::GetDecision(round) => match agreement.decision(round) {
+                                Some(commitment) => Response::Decision(commitment),
+                                None => Response::Undecided,
+                            },
+                            Message::GetPreference => {
+                                let (value, decided) = snowball.preference();
+                                Response::Preference { value, decided }
+                            }
+                            Message::Stats => Response::Stats(stats::stats()),
+                            Message::Status => Response::Status(Status::current(value, strategy).await),
+                            Message::Metrics => Response::Metrics(metrics::render()),
+                            Message::Accuse(proof) => {
+                                accountability::accept(&proof).await;
+                                Response::Excluded(accountability::excluded())
+                            }
                         }
-                    };
-                    let mut serialized = serde_json::to_string(&response).unwrap();
-                    serialized.push('\n');
-                    if let Err(err) = reader.get_mut().write_all(serialized.as_bytes()).await {
+                    }
+                    .instrument(handler)
+                    .await;
+                    let written = respond(reader.get_mut(), &response).await;
+                    stats::incoming(
+                        Some(name),
+                        from,
+                        Traffic {
+                            messages: 1,
+                            bytes_received: line.len(),
+                            bytes_sent: streamed + *written.as_ref().unwrap_or(&0),
+                            connections: 0,
+                        },
+                    );
+                    if let Err(err) = written {
                         debug!(target: "agent", "Could not respond, closing connection {:?}.", err);
+                        metrics::error("write");
                         break 'lines;
                     }
                     if let Response::Stop = response {
@@ -185,49 +722,513 @@ impl Agent {
     }
 }
 
+/// Send `response` on `conn`, return the number of bytes sent.
+async fn respond(
+    conn: &mut Box<dyn tls::Stream>,
+    response: &Response,
+) -> Result<usize, std::io::Error> {
+    let mut serialized = serde_json::to_string(response).unwrap();
+    serialized.push('\n');
+    conn.write_all(serialized.as_bytes()).await?;
+    Ok(serialized.len())
+}
+
+/// Ask each of `children` for their value, return the certificates of
+/// those who agree with `value`.
+///
+/// Agents that have been proven to equivocate are skipped. If an agent is
+/// caught equivocating, let all of `children` know.
+///
+/// Certificates found in `cache` are reused, and if the same campaign is
+/// already in progress, we wait for its result, see module `cache`.
+async fn campaign(
+    value: bool,
+    issuer: &Child,
+    children: Vec<Child>,
+    cache: &cache::State,
+) -> Vec<Certificate> {
+    cache
+        .coalesce(value, &children, collect(value, issuer, &children, cache))
+        .await
+}
+
+/// Ask each of `children` for their value, unless cached, see `campaign`.
+async fn collect(
+    value: bool,
+    issuer: &Child,
+    children: &[Child],
+    cache: &cache::State,
+) -> Vec<Certificate> {
+    let (tcollect, mut rcollect) = tokio::sync::mpsc::channel(32);
+    let collector = tokio::spawn(async move {
+        let mut my_party = vec![];
+        while let Some(certificate) = rcollect.recv().await {
+            my_party.push(certificate);
+        }
+        my_party
+    });
+    canvass(value, issuer, children, cache, tcollect).await;
+    let party = collector.await.unwrap();
+    debug!(target: "campaign", "{} Process ready to send proof that {} agents agree on value {}",
+        issuer.pid,
+        party.len(),
+        value
+    );
+    party
+}
+
+/// Ask each of `children` for their value, unless cached, send the
+/// certificates of those who agree with `value` to `tvotes`, as we receive them.
+async fn canvass(
+    value: bool,
+    issuer: &Child,
+    children: &[Child],
+    cache: &cache::State,
+    mut tvotes: tokio::sync::mpsc::Sender<Certificate>,
+) {
+    debug!(target: "campaign", "{} I'm a process that thinks the value is {}", issuer.pid, value);
+    let started = std::time::Instant::now();
+    debug!(target: "campaign", "{} Talking to {} agents", issuer.pid, children.len());
+    // Votes bound to the epoch of our registry may be reused across rounds,
+    // without a registry, we can only ask for certificates of this round.
+    let epoch = conf::registry().await.map(|registry| registry.epoch);
+    for child in children.iter().cloned() {
+        if accountability::is_excluded(&child) {
+            debug!(target: "campaign", "{} Process {} has equivocated, skipping it", issuer.pid, child.pid);
+            continue;
+        }
+        let issuer = issuer.clone();
+        if let Some(certificate) = epoch.and_then(|epoch| cache.get(&child, epoch)) {
+            // We have checked this certificate when we received it.
+            if certificate.value == value {
+                debug!(target: "campaign", "{} Process {} agrees that value is {}, using cached certificate",
+                        issuer.pid,
+                        child.pid,
+                        value);
+                // Whoever listens may have given up, see `stream_campaign`.
+                let _ = tvotes.send(certificate).await;
+            }
+            continue;
+        }
+        // We could of course avoid calling ourself.
+        // Let's see this as a stress-test for concurrency/reentrancy issues!
+        let remote = RemoteAgent::new(child.clone());
+        let message = match epoch {
+            Some(epoch) => Message::GetVote(epoch),
+            None => Message::GetValue,
+        };
+        match remote.call(&message).await {
+            Ok(Response::Certificate(certificate))
+                if certificate.epoch != epoch || !accountability::verify(&certificate, &child) =>
+            {
+                warn!(target: "campaign", "{} Process {} sent a forged certificate, ignoring it",
+                        issuer.pid,
+                        certificate.issuer.pid);
+            }
+            Ok(Response::Certificate(certificate)) => {
+                if let Some(proof) = accountability::observe(&certificate, &child) {
+                    if accountability::accept(&proof).await {
+                        trace::spawn(accountability::broadcast(children.to_vec(), proof));
+                    }
+                    continue;
+                }
+                if let Some(epoch) = epoch {
+                    cache.insert(&child, epoch, certificate.clone());
+                }
+                if certificate.value != value {
+                    // Remote agent disagrees with us, ignore it.
+                    debug!(target: "campaign", "{} Process {} thinks that value is {}, ignoring it",
+                            issuer.pid,
+                            certificate.issuer.pid,
+                            certificate.value);
+                } else {
+                    debug!(target: "campaign", "{} Process {} agrees that value is {}, using it",
+                            issuer.pid,
+                            certificate.issuer.pid,
+                            certificate.value);
+                    // Whoever listens may have given up, see `stream_campaign`.
+                    let _ = tvotes.send(certificate).await;
+                }
+            }
+            Err(err) => {
+                warn!(target: "campaign", "Couldn't communiccate {:?}", err);
+            }
+            message => {
+                // Remote agent can't or won't respond or bad response, skip it.
+                warn!(target: "campaign", "Received a message that doesn't make sense {:?}", message);
+            }
+        }
+    }
+    metrics::campaign(started.elapsed());
+}
+
+/// Like `campaign`, but send each certificate on `conn` as a `Response::Vote`
+/// as soon as we have it, then return `Response::EndOfStream`.
+///
+/// If `equivocate`, campaign for the opposite value, see `equivocating_campaign`.
+/// Streaming campaigns are not coalesced, but they reuse cached certificates.
+async fn stream_campaign(
+    value: bool,
+    equivocate: bool,
+    issuer: &Child,
+    children: &[Child],
+    cache: &cache::State,
+    conn: &mut Box<dyn tls::Stream>,
+    streamed: &mut usize,
+) -> Response {
+    let value = value != equivocate;
+    let (tvotes, mut rvotes) = tokio::sync::mpsc::channel(32);
+    // Take `rvotes` along, so that it is dropped as soon as we stop listening.
+    let send = async move {
+        while let Some(certificate) = rvotes.recv().await {
+            match respond(conn, &Response::Vote(certificate)).await {
+                Ok(written) => *streamed += written,
+                Err(err) => {
+                    // Stop listening, `canvass` will finish on its own.
+                    debug!(target: "campaign", "Could not stream vote {:?}", err);
+                    return;
+                }
+            }
+        }
+        if equivocate {
+            let certificate = accountability::sign(value, issuer, trace::current());
+            if let Ok(written) = respond(conn, &Response::Vote(certificate)).await {
+                *streamed += written;
+            }
+        }
+    };
+    tokio::join!(canvass(value, issuer, children, cache, tvotes), send);
+    Response::EndOfStream
+}
+
+/// Like `campaign`, but for the opposite value, adding a certificate of
+/// our own for that value, so as to equivocate.
+async fn equivocating_campaign(
+    value: bool,
+    issuer: &Child,
+    children: Vec<Child>,
+    cache: &cache::State,
+) -> Vec<Certificate> {
+    let mut party = campaign(!value, issuer, children, cache).await;
+    party.push(accountability::sign(!value, issuer, trace::current()));
+    party
+}
+
+/// Combine the votes of `party` for `value` into a single certificate, for
+/// a campaign among `children`.
+fn aggregate_campaign(value: bool, children: &[Child], party: &[Certificate]) -> Response {
+    match AggregateCertificate::new(value, children, party) {
+        Some(certificate) => Response::Aggregate(certificate),
+        None => Response::Undecided,
+    }
+}
+
 /// An agent running in another process.
 pub struct RemoteAgent {
     conf: Child,
+
+    /// Sent along with administrative messages, see module `admin`.
+    admin_token: Option<String>,
 }
 impl RemoteAgent {
     pub fn new(conf: Child) -> Self {
-        RemoteAgent { conf }
+        RemoteAgent {
+            conf,
+            admin_token: None,
+        }
+    }
+
+    /// Authenticate administrative messages with `token`.
+    pub fn with_admin_token(mut self, token: String) -> Self {
+        self.admin_token = Some(token);
+        self
+    }
+
+    /// The span of a call with `message`.
+    fn span(&self, message: &Message) -> tracing::Span {
+        info_span!(
+            "call",
+            otel.name = %format_args!("call {}", message.name()),
+            peer.pid = self.conf.pid,
+            peer.port = self.conf.socket,
+        )
+    }
+
+    /// Send `message`, wait for the response.
+    ///
+    /// If the agent responds with `Response::Error`, fail with `CallError::Refused`.
+    pub async fn call(&self, message: &Message) -> Result<Response, CallError> {
+        let result = self.call_impl(message).instrument(self.span(message)).await;
+        match result {
+            Ok(Response::Error { code, message }) => {
+                metrics::error("refused");
+                Err(CallError::Refused { code, message })
+            }
+            Ok(response) => Ok(response),
+            Err(err) => {
+                metrics::error("call");
+                Err(CallError::Io(err))
+            }
+        }
     }
-    pub async fn call(&self, message: &Message) -> Result<Response, std::io::Error> {
+
+    /// Send a streaming `message`, e.g. `Message::StreamCampaign`, call
+    /// `on_vote` with each `Response::Vote` as it arrives, return all the
+    /// votes once the agent sends `Response::EndOfStream`.
+    ///
+    /// If the agent responds with `Response::Error`, fail with `CallError::Refused`.
+    pub async fn stream<F>(
+        &self,
+        message: &Message,
+        on_vote: F,
+    ) -> Result<Vec<Certificate>, CallError>
+    where
+        F: FnMut(&Certificate),
+    {
+        let result = self
+            .stream_impl(message, on_vote)
+            .instrument(self.span(message))
+            .await;
+        match result {
+            Err(CallError::Refused { .. }) => metrics::error("refused"),
+            Err(CallError::Io(_)) => metrics::error("call"),
+            Ok(_) => {}
+        }
+        result
+    }
+    async fn stream_impl<F>(
+        &self,
+        message: &Message,
+        mut on_vote: F,
+    ) -> Result<Vec<Certificate>, CallError>
+    where
+        F: FnMut(&Certificate),
+    {
+        let mut reader = self.send(message).await?;
+        let mut votes = vec![];
+        loop {
+            match self.receive(message, &mut reader).await? {
+                Response::Vote(certificate) => {
+                    on_vote(&certificate);
+                    votes.push(certificate);
+                }
+                Response::EndOfStream => return Ok(votes),
+                Response::Error { code, message } => {
+                    return Err(CallError::Refused { code, message })
+                }
+                other => {
+                    return Err(CallError::Io(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Unexpected response in stream {:?}", other),
+                    )))
+                }
+            }
+        }
+    }
+
+    async fn call_impl(&self, message: &Message) -> Result<Response, std::io::Error> {
+        let mut reader = self.send(message).await?;
+        self.receive(message, &mut reader).await
+    }
+
+    /// Connect, send `message`, return the connection, to read the response.
+    async fn send(
+        &self,
+        message: &Message,
+    ) -> Result<BufReader<Box<dyn tls::Stream>>, std::io::Error> {
         debug!(target: "agent",
             "Play: Connecting with child {pid} on port {port}",
             port = self.conf.socket,
             pid = self.conf.pid
         );
-        let mut stream =
-            util::retry_future(|| TcpStream::connect(format!("127.0.0.1:{}", self.conf.socket)))
-                .await?;
+        // If the connection is refused, the agent is most likely dead, don't insist.
+        let stream = util::retry_future_if(
+            || TcpStream::connect(format!("127.0.0.1:{}", self.conf.socket)),
+            |err| err.kind() != std::io::ErrorKind::ConnectionRefused,
+        )
+        .await?;
+        let mut stream: Box<dyn tls::Stream> = match self.conf.fingerprint {
+            None => Box::new(stream),
+            Some(ref fingerprint) => Box::new(tls::connect(stream, fingerprint).await?),
+        };
+        stats::outgoing(
+            None,
+            self.conf.socket,
+            Traffic {
+                connections: 1,
+                ..Traffic::default()
+            },
+        );
 
         // Send request.
         debug!(target: "agent", "Play: Sending request");
-        let mut buffer = serde_json::to_string(message).unwrap();
+        let context = trace::context();
+        let request = Request {
+            trace: context.map(|context| context.trace),
+            span: context.and_then(|context| context.span),
+            token: if message.is_admin() {
+                self.admin_token.clone()
+            } else {
+                None
+            },
+            from: stats::port(),
+            message: message.clone(),
+        };
+        let mut buffer = serde_json::to_string(&request).unwrap();
         buffer.push('\n');
         stream.write_all(buffer.as_bytes()).await?;
         stream.flush().await?;
+        stats::outgoing(
+            Some(message.name()),
+            self.conf.socket,
+            Traffic {
+                messages: 1,
+                bytes_sent: buffer.len(),
+                ..Traffic::default()
+            },
+        );
 
-        // Wait for response.
+        Ok(BufReader::new(stream))
+    }
+
+    /// Read one response to `message`.
+    async fn receive(
+        &self,
+        message: &Message,
+        reader: &mut BufReader<Box<dyn tls::Stream>>,
+    ) -> Result<Response, std::io::Error> {
         debug!(target: "agent", "Play: Waiting for response");
-        let mut reader = BufReader::new(stream);
         let mut line = String::new();
-        reader.read_line(&mut line).await?;
+        let received = reader.read_line(&mut line).await?;
+        stats::outgoing(
+            Some(message.name()),
+            self.conf.socket,
+            Traffic {
+                bytes_received: received,
+                ..Traffic::default()
+            },
+        );
         Ok(serde_json::from_str(&line)?)
     }
 }
 
 pub struct AgentArgs {
     pub value: bool,
+
+    /// If specified, join the fleet managed by the supervisor listening
+    /// on this control socket, leave it upon SIGINT/SIGTERM.
+    pub join: Option<PathBuf>,
+
+    /// If specified, the registry of the fleet, or the control socket of
+    /// its supervisor, which agreement checks quorums and keys against, see
+    /// module `agreement`. Defaults to `join`.
+    pub registry: Option<PathBuf>,
+
+    /// If specified, maintain a view of the fleet by gossiping with peers,
+    /// starting with these agents.
+    pub gossip: Option<GossipArgs>,
+
+    /// If specified, converge on a value with peers from the gossip view,
+    /// see module `snowball`.
+    pub snowball: Option<SnowballArgs>,
+
+    /// If specified, serve metrics over HTTP on this local port.
+    pub metrics: Option<u16>,
+
+    /// If `true`, misbehave: vouch for `value` when asked for our value,
+    /// but campaign for the opposite value, signing a certificate for it,
+    /// see module `accountability`.
+    pub equivocate: bool,
+
+    /// If specified, only speak TLS, with this certificate, see module `tls`.
+    pub tls: Option<TlsArgs>,
+
+    /// If specified, require the admin token stored in this file on
+    /// administrative messages, see module `admin`.
+    pub admin_token: Option<PathBuf>,
+
+    /// What the agent accepts from its clients, see module `limits`.
+    pub limits: Limits,
+
+    /// How long to keep the certificates of peers, see module `cache`.
+    /// If zero, don't keep them.
+    pub cache_ttl: Duration,
+
+    /// If `true`, tell how the agent behaves in `Response::Status`, e.g.
+    /// to debug a fleet.
+    pub debug_status: bool,
 }
 
-/// Start agent, print port on stdout, enter agent main loop, never return.
+/// Start agent, print port, BLS public key then ed25519 public key on stdout,
+/// enter agent main loop, never return.
 pub async fn agent(args: &AgentArgs) {
-    let mut agent = Agent::try_new(args.value)
+    let mut agent = Agent::try_new(args.value, args.tls.as_ref())
         .await
         .expect("Could not start agent");
-    print!("{}\n", agent.socket().port());
-    agent.exec().await;
+    agent.equivocate = args.equivocate;
+    if let Some(ref path) = args.admin_token {
+        agent.admin_token = Some(admin::load(path).expect("Could not read admin token"));
+    }
+    agent.limits = Arc::new(args.limits.clone());
+    agent.cache = cache::State::new(args.cache_ttl);
+    let registry = args.registry.clone().or_else(|| args.join.clone());
+    if let Some(ref registry) = registry {
+        conf::set_registry(registry.clone());
+    }
+    agent.agreement = agreement::State::new(agent.view().me().clone(), registry);
+    if args.debug_status {
+        let stubborn = args.snowball.as_ref().is_some_and(|args| args.stubborn);
+        agent.strategy = Some(if args.equivocate {
+            status::Strategy::Equivocating
+        } else if stubborn {
+            status::Strategy::Stubborn
+        } else {
+            status::Strategy::Honest
+        });
+    }
+    stats::identify(agent.socket().port());
+    println!("{}", agent.socket().port());
+    println!("{}", aggregate::public_key());
+    println!("{}", accountability::public_key());
+    if let Some(ref gossip) = args.gossip {
+        tokio::spawn(gossip::gossip(agent.view().clone(), gossip.clone()));
+    }
+    if let Some(port) = args.metrics {
+        let listener = metrics::bind(port)
+            .await
+            .expect("Could not open metrics endpoint");
+        tokio::spawn(metrics::serve(listener, || async { metrics::render() }));
+    }
+    if let Some(ref args) = args.snowball {
+        tokio::spawn(snowball::snowball(
+            agent.snowball().clone(),
+            agent.view().clone(),
+            args.clone(),
+        ));
+    }
+    let path = match args.join {
+        None => {
+            agent.exec().await;
+            unreachable!();
+        }
+        Some(ref path) => path,
+    };
+
+    let me = agent.view().me().clone();
+    let supervisor = RemoteSupervisor::new(path);
+    match supervisor.call(&Command::Join(me.clone())).await {
+        Ok(Reply::Ok) => debug!(target: "agent", "Joined fleet"),
+        other => panic!("Could not join fleet: {:?}", other),
+    }
+    let mut terminate = signal(SignalKind::terminate()).expect("Could not listen to signals");
+    tokio::select! {
+        _ = agent.exec() => unreachable!(),
+        _ = tokio::signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
+    }
+    match supervisor.call(&Command::Leave(me)).await {
+        Ok(Reply::Ok) => debug!(target: "agent", "Left fleet"),
+        other => warn!(target: "agent", "Could not leave fleet: {:?}", other),
+    }
+    std::process::exit(0);
 }
@@ -0,0 +1,206 @@
+//! Aggregate certificates, i.e. quorum proofs whose size doesn't grow
+//! with the number of agents.
+//!
+//! Besides its ed25519 signature, each certificate carries a vote: a BLS
+//! signature of its value with a key generated at startup, which the
+//! registry publishes along with the agent, see `Child::key`. An agent
+//! asked to campaign with `Message::AggregateCampaign` combines the votes
+//! of its allies into an `AggregateCertificate`, i.e. a single signature
+//! and a bitmap of the signers, which a player checks in one step against
+//! the keys of the registry.
+//!
+//! Votes are not bound to a round, since an agent carries the same value
+//! for its lifetime. Each agent signs a message that names it, so an agent
+//! can't craft a key that forges the votes of others.
+//!
+//! Unlike parties, aggregates don't let players catch equivocating agents,
+//! see module `accountability`.
+
+use std::sync::OnceLock;
+
+use blst::min_pk::{AggregateSignature, PublicKey, SecretKey, Signature};
+use blst::BLST_ERROR;
+use rand::RngCore;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::agent::Certificate;
+use crate::conf::{Child, Conf};
+use crate::util::{from_hex, to_hex};
+
+/// The domain separation tag of votes, as recommended for BLS signatures
+/// in G2 with public keys in G1.
+const DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_NUL_";
+
+/// The key of this process, generated upon first use.
+fn secret_key() -> &'static SecretKey {
+    static SECRET_KEY: OnceLock<SecretKey> = OnceLock::new();
+    SECRET_KEY.get_or_init(|| {
+        let mut ikm = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut ikm);
+        SecretKey::key_gen(&ikm, &[]).expect("Could not generate key")
+    })
+}
+
+/// The public key of this process, hex-encoded, as published by the registry.
+pub fn public_key() -> String {
+    to_hex(&secret_key().sk_to_pk().to_bytes())
+}
+
+/// The bytes covered by a vote of `issuer` for `value`.
+fn message(value: bool, issuer: &Child) -> Vec<u8> {
+    format!("liarslie/vote/{}/{}/{}", issuer.pid, issuer.socket, value).into_bytes()
+}
+
+/// Vote for `value`, with the key of this process.
+pub fn vote(value: bool, issuer: &Child) -> String {
+    to_hex(
+        &secret_key()
+            .sign(&message(value, issuer), DST, &[])
+            .to_bytes(),
+    )
+}
+
+fn parse_key(key: &str) -> Option<PublicKey> {
+    from_hex(key).and_then(|key| PublicKey::from_bytes(&key).ok())
+}
+
+fn parse_signature(signature: &str) -> Option<Signature> {
+    from_hex(signature).and_then(|signature| Signature::from_bytes(&signature).ok())
+}
+
+/// Check `signature` against the votes of `signers` for `value`.
+fn verify(value: bool, signers: &[&Child], signature: &Signature) -> bool {
+    let keys = match signers
+        .iter()
+        .map(|signer| signer.key.as_deref().and_then(parse_key))
+        .collect::<Option<Vec<_>>>()
+    {
+        Some(keys) => keys,
+        None => return false,
+    };
+    let messages: Vec<_> = signers
+        .iter()
+        .map(|signer| message(value, signer))
+        .collect();
+    let messages: Vec<&[u8]> = messages.iter().map(Vec::as_slice).collect();
+    let keys: Vec<&PublicKey> = keys.iter().collect();
+    signature.aggregate_verify(true, &messages, DST, &keys, true) == BLST_ERROR::BLST_SUCCESS
+}
+
+/// The votes of a number of agents for the same value, combined into a single signature.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AggregateCertificate {
+    pub value: bool,
+
+    /// The signers, as a hex-encoded bitmap over the agents of the campaign,
+    /// i.e. bit `i % 8` of byte `i / 8` is set if the `i`-th agent has signed.
+    pub signers: String,
+
+    /// The aggregate of the votes of the signers, hex-encoded.
+    pub signature: String,
+}
+impl AggregateCertificate {
+    /// Combine the votes for `value` among `certificates`, by agents of
+    /// `children`, whose keys are those of `children`.
+    ///
+    /// Invalid votes are ignored. Return `None` if there is no valid vote.
+    pub fn new(value: bool, children: &[Child], certificates: &[Certificate]) -> Option<Self> {
+        let mut signers = vec![];
+        let mut votes = vec![];
+        for certificate in certificates.iter().filter(|c| c.value == value) {
+            let index = match children
+                .iter()
+                .position(|child| *child == certificate.issuer)
+            {
+                Some(index) if !signers.contains(&index) => index,
+                _ => continue,
+            };
+            if let Some(vote) = certificate.vote.as_deref().and_then(parse_signature) {
+                signers.push(index);
+                votes.push(vote);
+            }
+        }
+        // In most cases, all votes are valid and a single check is enough.
+        if !Self::all_valid(value, children, &signers, &votes) {
+            let checked: Vec<_> = signers
+                .into_iter()
+                .zip(votes)
+                .filter(|(index, vote)| verify(value, &[&children[*index]], vote))
+                .collect();
+            signers = checked.iter().map(|(index, _)| *index).collect();
+            votes = checked.into_iter().map(|(_, vote)| vote).collect();
+        }
+        if signers.is_empty() {
+            return None;
+        }
+        let votes: Vec<&Signature> = votes.iter().collect();
+        let signature = AggregateSignature::aggregate(&votes, false)
+            .ok()?
+            .to_signature();
+        let mut bitmap = vec![0u8; children.len().div_ceil(8)];
+        for index in signers {
+            bitmap[index / 8] |= 1 << (index % 8);
+        }
+        Some(AggregateCertificate {
+            value,
+            signers: to_hex(&bitmap),
+            signature: to_hex(&signature.to_bytes()),
+        })
+    }
+
+    /// Whether the aggregate of `votes`, by the agents at `signers` among `children`, is valid.
+    fn all_valid(value: bool, children: &[Child], signers: &[usize], votes: &[Signature]) -> bool {
+        let votes: Vec<&Signature> = votes.iter().collect();
+        let signature = match AggregateSignature::aggregate(&votes, false) {
+            Ok(signature) => signature.to_signature(),
+            Err(_) => return false,
+        };
+        let signers: Vec<&Child> = signers.iter().map(|index| &children[*index]).collect();
+        verify(value, &signers, &signature)
+    }
+
+    /// The signers, among `children`, the agents of the campaign.
+    ///
+    /// Return `None` if the bitmap doesn't match `children`.
+    pub fn signers<'a>(&self, children: &'a [Child]) -> Option<Vec<&'a Child>> {
+        let bitmap = from_hex(&self.signers)?;
+        if bitmap.len() != children.len().div_ceil(8) {
+            return None;
+        }
+        let mut signers = vec![];
+        for (index, byte) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (1 << bit) == 0 {
+                    continue;
+                }
+                signers.push(children.get(index * 8 + bit)?);
+            }
+        }
+        Some(signers)
+    }
+
+    /// Check the aggregate, for a campaign among `children`, against the
+    /// keys published by the registry `conf`.
+    ///
+    /// Return the weight of the signers, by registry, or `None` if the
+    /// aggregate is invalid, e.g. if a signer is not a member.
+    pub fn verify(&self, children: &[Child], conf: &Conf) -> Option<usize> {
+        let mut signers: Vec<&Child> = vec![];
+        for signer in self.signers(children)? {
+            // Keys and weights are those of the registry, not those claimed by the agent.
+            let member = conf.children.iter().find(|child| *child == signer)?;
+            if signers.contains(&member) {
+                return None;
+            }
+            signers.push(member);
+        }
+        if signers.is_empty() {
+            return None;
+        }
+        let signature = parse_signature(&self.signature)?;
+        if !verify(self.value, &signers, &signature) {
+            return None;
+        }
+        Some(signers.iter().map(|signer| signer.weight).sum())
+    }
+}
@@ -0,0 +1,336 @@
+//! Agreement among agents.
+//!
+//! A round of agreement proceeds in two phases, each of which is a
+//! campaign among the same `children`:
+//!
+//! 1. *Prepare*: each agent collects the certificates of every child. If a
+//!    quorum of children vouch for the same value, the agent prepares that
+//!    value and keeps the certificates as proof. An agent never prepares two
+//!    values during the same round.
+//! 2. *Commit*: each agent collects the preparations of every child. Once a
+//!    quorum of valid preparations agree on the same value, the agent commits
+//!    this value. The preparations form a certificate of commitment, which any
+//!    client can check without talking to anybody else.
+//!
+//! Quorums are those of the registry, i.e. a majority of the weight of the
+//! fleet, and every signature is checked against the keys published in the
+//! registry, whatever the clients or other agents claim, see
+//! `AgentArgs::registry`.
+//!
+//! As long as liars are a minority, honest agents all prepare and commit the
+//! value carried by honest agents.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::accountability;
+use crate::agent::{Certificate, Message, RemoteAgent, Response};
+use crate::conf::{Child, Conf};
+
+/// The bytes covered by the signature of a preparation.
+fn payload(round: u64, value: bool, issuer: &Child) -> Vec<u8> {
+    format!(
+        "liarslie/prepared/{}/{}/{}/{}",
+        issuer.pid, issuer.socket, round, value
+    )
+    .into_bytes()
+}
+
+/// Evidence that `issuer` has prepared `value` during `round`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Prepared {
+    pub round: u64,
+    pub value: bool,
+    pub issuer: Child,
+
+    /// A quorum of certificates for `value`.
+    pub proof: Vec<Certificate>,
+
+    /// The signature of the issuer, hex-encoded, see module `accountability`.
+    pub signature: String,
+}
+impl Prepared {
+    /// Prepare `value` during `round`, signed with the key of this process.
+    fn sign(round: u64, value: bool, issuer: &Child, proof: Vec<Certificate>) -> Self {
+        Prepared {
+            round,
+            value,
+            issuer: issuer.clone(),
+            proof,
+            signature: accountability::sign_message(&payload(round, value, issuer)),
+        }
+    }
+
+    /// Check that this is a valid preparation among the agents of `registry`.
+    pub fn verify(&self, round: u64, registry: &Conf) -> bool {
+        if self.round != round {
+            return false;
+        }
+        let issuer = match registry
+            .children
+            .iter()
+            .find(|child| **child == self.issuer)
+        {
+            Some(issuer) => issuer,
+            None => return false,
+        };
+        if !accountability::verify_message(
+            &payload(self.round, self.value, issuer),
+            &self.signature,
+            issuer,
+        ) {
+            return false;
+        }
+        let mut issuers = vec![];
+        for certificate in &self.proof {
+            if certificate.value != self.value
+                || issuers.contains(&&certificate.issuer)
+                || !certificate.verify(&registry.children)
+            {
+                return false;
+            }
+            issuers.push(&certificate.issuer);
+        }
+        weight(registry, issuers) >= registry.quorum()
+    }
+}
+
+/// The weight of `issuers` in `registry`.
+fn weight<'a>(registry: &Conf, issuers: impl IntoIterator<Item = &'a Child>) -> usize {
+    issuers
+        .into_iter()
+        .map(|issuer| registry.weight_of(issuer.pid))
+        .sum()
+}
+
+/// A certificate of commitment: evidence that a quorum of agents have
+/// prepared `value` during `round`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Commitment {
+    pub round: u64,
+    pub value: bool,
+    pub prepared: Vec<Prepared>,
+}
+impl Commitment {
+    /// Check that this is a valid commitment among the agents of `registry`.
+    pub fn verify(&self, round: u64, registry: &Conf) -> bool {
+        if self.round != round {
+            return false;
+        }
+        let mut issuers = vec![];
+        for prepared in &self.prepared {
+            if prepared.value != self.value
+                || issuers.contains(&&prepared.issuer)
+                || !prepared.verify(round, registry)
+            {
+                return false;
+            }
+            issuers.push(&prepared.issuer);
+        }
+        weight(registry, issuers) >= registry.quorum()
+    }
+}
+
+/// The agreement state of an agent, shared between its connections.
+#[derive(Clone)]
+pub struct State {
+    me: Child,
+
+    /// The registry of the fleet, read whenever we need it, since the
+    /// supervisor may rewrite it. Without it, we don't take part in agreement.
+    registry: Option<Arc<PathBuf>>,
+
+    prepared: Arc<Mutex<HashMap<u64, Prepared>>>,
+    committed: Arc<Mutex<HashMap<u64, Commitment>>>,
+}
+impl State {
+    pub fn new(me: Child, registry: Option<PathBuf>) -> Self {
+        State {
+            me,
+            registry: registry.map(Arc::new),
+            prepared: Arc::new(Mutex::new(HashMap::new())),
+            committed: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The current registry of the fleet, if we have one.
+    async fn registry(&self) -> Option<Conf> {
+        let path = match self.registry {
+            Some(ref path) => path,
+            None => {
+                warn!(target: "agreement", "{} No registry, cannot take part in agreement", self.me.pid);
+                return None;
+            }
+        };
+        match Conf::load(path).await {
+            Ok(registry) => Some(registry),
+            Err(err) => {
+                warn!(target: "agreement", "{} Could not read registry {:?}: {:?}", self.me.pid, path, err);
+                None
+            }
+        }
+    }
+
+    /// The value committed during `round`, if any.
+    pub fn decision(&self, round: u64) -> Option<Commitment> {
+        self.committed.lock().unwrap().get(&round).cloned()
+    }
+
+    /// Accept a commitment reached by another agent, if it is valid.
+    pub async fn commit(&self, commitment: Commitment) -> Option<Commitment> {
+        let registry = self.registry().await?;
+        if !commitment.verify(commitment.round, &registry) {
+            warn!(target: "agreement", "{} Round {}: invalid commitment", self.me.pid, commitment.round);
+            return None;
+        }
+        let commitment = self
+            .committed
+            .lock()
+            .unwrap()
+            .entry(commitment.round)
+            .or_insert(commitment)
+            .clone();
+        Some(commitment)
+    }
+
+    /// Phase 1: prepare a value for `round`, if a quorum of the registry
+    /// among `children` vouch for it.
+    pub async fn prepare(&self, round: u64, children: &[Child]) -> Option<Prepared> {
+        if let Some(prepared) = self.prepared.lock().unwrap().get(&round) {
+            return Some(prepared.clone());
+        }
+        let registry = self.registry().await?;
+        let mut yeas = vec![];
+        let mut nays = vec![];
+        for child in members(&registry, children) {
+            let remote = RemoteAgent::new(child.clone());
+            match remote.call(&Message::GetValue).await {
+                Ok(Response::Certificate(certificate))
+                    if !certificate.verify(&registry.children) =>
+                {
+                    warn!(target: "agreement", "{} Process {} sent a forged certificate, ignoring it", self.me.pid, child.pid)
+                }
+                Ok(Response::Certificate(certificate)) if certificate.issuer != *child => {
+                    warn!(target: "agreement", "{} Process {} sent the certificate of another agent, ignoring it", self.me.pid, child.pid)
+                }
+                Ok(Response::Certificate(certificate)) if certificate.value => {
+                    yeas.push(certificate)
+                }
+                Ok(Response::Certificate(certificate)) => nays.push(certificate),
+                other => {
+                    debug!(target: "agreement", "{} Could not get value from {}: {:?}", self.me.pid, child.pid, other)
+                }
+            }
+        }
+        let quorum = registry.quorum();
+        let (value, proof) = if weight(
+            &registry,
+            yeas.iter().map(|certificate| &certificate.issuer),
+        ) >= quorum
+        {
+            (true, yeas)
+        } else if weight(
+            &registry,
+            nays.iter().map(|certificate| &certificate.issuer),
+        ) >= quorum
+        {
+            (false, nays)
+        } else {
+            debug!(target: "agreement", "{} Round {}: no quorum, cannot prepare", self.me.pid, round);
+            return None;
+        };
+        let prepared = Prepared::sign(round, value, &self.me, proof);
+        // If we have prepared concurrently, stick to the first preparation.
+        let prepared = self
+            .prepared
+            .lock()
+            .unwrap()
+            .entry(round)
+            .or_insert(prepared)
+            .clone();
+        debug!(target: "agreement", "{} Round {}: prepared {}", self.me.pid, round, prepared.value);
+        Some(prepared)
+    }
+
+    /// Phase 2: commit a value for `round`, if a quorum of the registry
+    /// among `children` have prepared it.
+    pub async fn agree(&self, round: u64, children: &[Child]) -> Option<Commitment> {
+        if let Some(commitment) = self.decision(round) {
+            return Some(commitment);
+        }
+        let registry = self.registry().await?;
+        let mut yeas = vec![];
+        let mut nays = vec![];
+        for child in members(&registry, children) {
+            let remote = RemoteAgent::new(child.clone());
+            let message = Message::Prepare {
+                round,
+                children: children.to_vec(),
+            };
+            match remote.call(&message).await {
+                Ok(Response::Prepared(prepared)) => {
+                    if prepared.issuer != *child || !prepared.verify(round, &registry) {
+                        warn!(target: "agreement", "{} Round {}: invalid preparation from {}", self.me.pid, round, child.pid);
+                    } else if prepared.value {
+                        yeas.push(prepared);
+                    } else {
+                        nays.push(prepared);
+                    }
+                }
+                other => {
+                    debug!(target: "agreement", "{} Could not get preparation from {}: {:?}", self.me.pid, child.pid, other)
+                }
+            }
+        }
+        let quorum = registry.quorum();
+        let (value, prepared) = if weight(&registry, yeas.iter().map(|prepared| &prepared.issuer))
+            >= quorum
+        {
+            (true, yeas)
+        } else if weight(&registry, nays.iter().map(|prepared| &prepared.issuer)) >= quorum {
+            (false, nays)
+        } else {
+            debug!(target: "agreement", "{} Round {}: no quorum, cannot commit", self.me.pid, round);
+            return None;
+        };
+        let commitment = Commitment {
+            round,
+            value,
+            prepared,
+        };
+        let commitment = self
+            .committed
+            .lock()
+            .unwrap()
+            .entry(round)
+            .or_insert(commitment)
+            .clone();
+        debug!(target: "agreement", "{} Round {}: committed {}", self.me.pid, round, commitment.value);
+        Some(commitment)
+    }
+}
+
+/// The agents of `registry` among `children`, with their published keys.
+///
+/// Whoever sends us `children` may have added agents of their own.
+fn members<'a>(registry: &'a Conf, children: &'a [Child]) -> impl Iterator<Item = &'a Child> {
+    registry
+        .children
+        .iter()
+        .filter(move |member| children.contains(member))
+}
+
+/// Send `commitment` to every child.
+pub async fn broadcast(children: Vec<Child>, commitment: Commitment) {
+    for child in &children {
+        let remote = RemoteAgent::new(child.clone());
+        let message = Message::Commit(commitment.clone());
+        if let Err(err) = remote.call(&message).await {
+            debug!(target: "agreement", "Could not send commitment to {}: {:?}", child.pid, err);
+        }
+    }
+}
@@ -0,0 +1,307 @@
+//! Compare play strategies across fleets of various sizes and liar ratios.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use log::*;
+use rand::Rng;
+use serde_derive::Serialize;
+
+use crate::agent::{Message, RemoteAgent, Response};
+use crate::conf::Child;
+use crate::play::{self, PlayArgs};
+use crate::playagree::{self, PlayAgreeArgs};
+use crate::playexpert::{self, PlayExpertArgs};
+use crate::playsample::{self, PlaySampleArgs};
+use crate::playsnowball::{self, PlaySnowballArgs};
+use crate::start::{self, StartArgs, Weights};
+use crate::stats::{self, Traffic};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strategy {
+    Play,
+    PlayExpert,
+    PlaySample,
+    PlayAgree,
+    PlaySnowball,
+}
+impl Strategy {
+    pub const ALL: [Strategy; 5] = [
+        Strategy::Play,
+        Strategy::PlayExpert,
+        Strategy::PlaySample,
+        Strategy::PlayAgree,
+        Strategy::PlaySnowball,
+    ];
+
+    /// The name of the subcommand implementing this strategy.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Strategy::Play => "play",
+            Strategy::PlayExpert => "playexpert",
+            Strategy::PlaySample => "playsample",
+            Strategy::PlayAgree => "playagree",
+            Strategy::PlaySnowball => "playsnowball",
+        }
+    }
+
+    /// Play a single round, using the registry at `path`.
+    async fn play(&self, path: &Path, liar_ratio: f64) -> Option<bool> {
+        let path = path.to_path_buf();
+        match *self {
+            Strategy::Play => {
+                play::play(&PlayArgs {
+                    path,
+                    transcript: None,
+                    reputation: None,
+                })
+                .await
+            }
+            Strategy::PlayExpert => {
+                playexpert::play(&PlayExpertArgs {
+                    path,
+                    liar_ratio,
+                    discover: false,
+                    transcript: None,
+                    reputation: None,
+                    avoid_suspects: false,
+                    stream: false,
+                    aggregate: false,
+                    certificate: None,
+                })
+                .await
+            }
+            Strategy::PlaySample => {
+                playsample::play(&PlaySampleArgs {
+                    path,
+                    liar_ratio,
+                    confidence: 0.999,
+                    batch: 3,
+                })
+                .await
+                .value
+            }
+            Strategy::PlayAgree => playagree::play(&PlayAgreeArgs { path }).await,
+            Strategy::PlaySnowball => playsnowball::play(&PlaySnowballArgs { path, ask: 5 }).await,
+        }
+    }
+}
+impl FromStr for Strategy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Strategy::ALL
+            .iter()
+            .find(|strategy| strategy.name() == s)
+            .cloned()
+            .ok_or_else(|| format!("Unknown strategy {}", s))
+    }
+}
+
+pub struct BenchArgs {
+    pub exe: PathBuf,
+    pub num_agents: Vec<usize>,
+    pub liar_ratios: Vec<f64>,
+    pub strategies: Vec<Strategy>,
+
+    /// The number of rounds to play per strategy and configuration.
+    pub rounds: usize,
+
+    /// How long to let each fleet settle before playing, e.g. to let gossip propagate.
+    pub warmup: Duration,
+}
+
+/// The results of playing a strategy with a configuration.
+///
+/// Fleet traffic covers messages exchanged between agents, including
+/// background gossip and metastable consensus.
+#[derive(Clone, Debug, Serialize)]
+pub struct Row {
+    pub strategy: &'static str,
+    pub num_agents: usize,
+    pub liar_ratio: f64,
+    pub rounds: usize,
+    pub correct: usize,
+    pub wrong: usize,
+    pub undecided: usize,
+    pub error_rate: f64,
+    pub undecided_rate: f64,
+    pub latency_p50_ms: f64,
+    pub latency_p90_ms: f64,
+    pub latency_p99_ms: f64,
+    pub latency_max_ms: f64,
+
+    /// Averages per round.
+    pub messages: f64,
+    pub bytes_sent: f64,
+    pub bytes_received: f64,
+    pub fleet_messages: f64,
+    pub fleet_bytes_sent: f64,
+
+    /// The size of a proof that the fleet agrees, as sent by an agent for
+    /// this configuration, as a party of certificates and as an aggregate
+    /// certificate, see module `aggregate`.
+    pub quorum_bytes: usize,
+    pub aggregate_bytes: usize,
+}
+impl Row {
+    pub const CSV_HEADER: &'static str = "strategy,num_agents,liar_ratio,rounds,correct,wrong,undecided,error_rate,undecided_rate,latency_p50_ms,latency_p90_ms,latency_p99_ms,latency_max_ms,messages,bytes_sent,bytes_received,fleet_messages,fleet_bytes_sent,quorum_bytes,aggregate_bytes";
+
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{},{}",
+            self.strategy,
+            self.num_agents,
+            self.liar_ratio,
+            self.rounds,
+            self.correct,
+            self.wrong,
+            self.undecided,
+            self.error_rate,
+            self.undecided_rate,
+            self.latency_p50_ms,
+            self.latency_p90_ms,
+            self.latency_p99_ms,
+            self.latency_max_ms,
+            self.messages,
+            self.bytes_sent,
+            self.bytes_received,
+            self.fleet_messages,
+            self.fleet_bytes_sent,
+            self.quorum_bytes,
+            self.aggregate_bytes
+        )
+    }
+}
+
+/// The `percentile`-th percentile of `sorted`, by nearest rank.
+fn percentile(sorted: &[f64], percentile: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.;
+    }
+    let rank = (percentile / 100. * sorted.len() as f64).ceil() as usize;
+    sorted[std::cmp::min(std::cmp::max(rank, 1), sorted.len()) - 1]
+}
+
+/// The traffic sent by `children` to other agents so far.
+async fn fleet_traffic(children: &[Child]) -> Traffic {
+    let mut total = Traffic::default();
+    for child in children {
+        match RemoteAgent::new(child.clone()).call(&Message::Stats).await {
+            Ok(Response::Stats(stats)) => total.add(&stats.outgoing.total),
+            other => {
+                warn!(target: "bench", "Could not get stats from child {}: {:?}", child.pid, other)
+            }
+        }
+    }
+    total
+}
+
+/// The size of the responses of `child` to a campaign among `children`, in
+/// bytes, with and without aggregation, see `Row::quorum_bytes`.
+async fn proof_sizes(child: &Child, children: &[Child]) -> (usize, usize) {
+    let remote = RemoteAgent::new(child.clone());
+    let mut sizes = [0; 2];
+    let messages = [
+        Message::Campaign(children.to_vec()),
+        Message::AggregateCampaign(children.to_vec()),
+    ];
+    for (size, message) in sizes.iter_mut().zip(&messages) {
+        match remote.call(message).await {
+            Ok(response) => *size = serde_json::to_string(&response).unwrap().len(),
+            Err(err) => {
+                warn!(target: "bench", "Could not get proof from child {}: {}", child.pid, err)
+            }
+        }
+    }
+    (sizes[0], sizes[1])
+}
+
+/// Implementation of command `bench`.
+///
+/// For each configuration, start a fleet, writing `agents.conf`, play
+/// `args.rounds` rounds with each strategy, then stop the fleet.
+pub async fn bench(args: &BenchArgs) -> Vec<Row> {
+    let path = Path::new("agents.conf");
+    let mut rows = vec![];
+    for &num_agents in &args.num_agents {
+        for &liar_ratio in &args.liar_ratios {
+            let value = rand::thread_rng().gen_bool(0.5);
+            let start_args = StartArgs {
+                exe: args.exe.clone(),
+                value,
+                num_agents,
+                liar_ratio,
+                weights: Weights::Uniform,
+                tls: None,
+                admin_token: None,
+                // Strategies such as `playsnowball` need the fleet to gossip.
+                gossip: true,
+                snowball: true,
+            };
+            let (conf, mut processes) = start::start(&start_args).await;
+            tokio::time::delay_for(args.warmup).await;
+            let (quorum_bytes, aggregate_bytes) =
+                proof_sizes(&conf.children[0], &conf.children).await;
+
+            for strategy in &args.strategies {
+                debug!(target: "bench", "Playing {} with {} agents, liar ratio {}",
+                    strategy.name(),
+                    num_agents,
+                    liar_ratio
+                );
+                let mut latencies = Vec::with_capacity(args.rounds);
+                let mut correct = 0;
+                let mut wrong = 0;
+                let mut undecided = 0;
+                let fleet_before = fleet_traffic(&conf.children).await;
+                let before = stats::traffic();
+                for _ in 0..args.rounds {
+                    let started = Instant::now();
+                    let result = strategy.play(path, liar_ratio).await;
+                    latencies.push(started.elapsed().as_secs_f64() * 1000.);
+                    match result {
+                        Some(v) if v == value => correct += 1,
+                        Some(_) => wrong += 1,
+                        None => undecided += 1,
+                    }
+                }
+                let traffic = stats::traffic().since(&before);
+                let fleet = fleet_traffic(&conf.children).await.since(&fleet_before);
+                latencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let rounds = std::cmp::max(args.rounds, 1) as f64;
+                rows.push(Row {
+                    strategy: strategy.name(),
+                    num_agents,
+                    liar_ratio,
+                    rounds: args.rounds,
+                    correct,
+                    wrong,
+                    undecided,
+                    error_rate: wrong as f64 / rounds,
+                    undecided_rate: undecided as f64 / rounds,
+                    latency_p50_ms: percentile(&latencies, 50.),
+                    latency_p90_ms: percentile(&latencies, 90.),
+                    latency_p99_ms: percentile(&latencies, 99.),
+                    latency_max_ms: latencies.last().cloned().unwrap_or(0.),
+                    messages: traffic.messages as f64 / rounds,
+                    bytes_sent: traffic.bytes_sent as f64 / rounds,
+                    bytes_received: traffic.bytes_received as f64 / rounds,
+                    fleet_messages: fleet.messages as f64 / rounds,
+                    fleet_bytes_sent: fleet.bytes_sent as f64 / rounds,
+                    quorum_bytes,
+                    aggregate_bytes,
+                });
+            }
+
+            for process in &mut processes {
+                let _ = process.kill();
+            }
+            for process in processes {
+                let _ = process.await;
+            }
+        }
+    }
+    let _ = std::fs::remove_file(path);
+    rows
+}
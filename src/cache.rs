@@ -0,0 +1,186 @@
+//! Caching of peer certificates and coalescing of campaigns.
+//!
+//! The value of an agent doesn't change during its lifetime, so an agent
+//! that campaigns repeatedly, e.g. for several clients, may reuse the
+//! certificates it has collected from its peers instead of asking every
+//! peer again. Such votes are bound to the epoch of the registry rather than
+//! to a round, see `Message::GetVote`, so they are reused across rounds, as
+//! long as the registry doesn't change. They are kept for `ttl`, and only for
+//! the process that issued them: a respawned agent has a new pid, hence a
+//! new entry.
+//!
+//! Concurrent campaigns for the same value among the same children, in the
+//! same round, are coalesced: the first one contacts the children, the
+//! others wait for its result.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::*;
+use tokio::sync::watch;
+
+use crate::agent::Certificate;
+use crate::conf::Child;
+use crate::metrics;
+use crate::trace;
+
+/// How long certificates are kept by default.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(5);
+
+/// Agents are told apart by pid and port, see `Child`.
+type Agent = (u32, u16);
+
+fn agent(child: &Child) -> Agent {
+    (child.pid, child.socket)
+}
+
+/// Certificates are cached by epoch and issuer.
+type Key = (u64, Agent);
+
+/// The cached certificates, and when expired ones were last removed.
+struct Certificates {
+    entries: HashMap<Key, (Certificate, Instant)>,
+    swept: Instant,
+}
+
+/// Campaigns are coalesced by round, value and children.
+type Id = (Option<u64>, bool, Vec<Agent>);
+
+/// The result of a campaign in progress, `None` until it is over.
+type Pending = watch::Receiver<Option<Vec<Certificate>>>;
+
+/// The campaigns in progress.
+type Campaigns = Mutex<HashMap<Id, Pending>>;
+
+/// The cache of an agent, shared between its connections.
+#[derive(Clone)]
+pub struct State {
+    /// If zero, don't cache certificates.
+    ttl: Duration,
+    certificates: Arc<Mutex<Certificates>>,
+    campaigns: Arc<Campaigns>,
+}
+impl State {
+    pub fn new(ttl: Duration) -> Self {
+        State {
+            ttl,
+            certificates: Arc::new(Mutex::new(Certificates {
+                entries: HashMap::new(),
+                swept: Instant::now(),
+            })),
+            campaigns: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The certificate last received from `child` for `epoch`, unless it
+    /// has expired.
+    pub fn get(&self, child: &Child, epoch: u64) -> Option<Certificate> {
+        let certificates = &mut self.certificates.lock().unwrap().entries;
+        let key = (epoch, agent(child));
+        match certificates.get(&key) {
+            Some((certificate, received)) if received.elapsed() < self.ttl => {
+                metrics::cache(true);
+                Some(certificate.clone())
+            }
+            Some(_) => {
+                certificates.remove(&key);
+                metrics::cache(false);
+                None
+            }
+            None => {
+                metrics::cache(false);
+                None
+            }
+        }
+    }
+
+    /// Remember a valid certificate received from `child` for `epoch`.
+    pub fn insert(&self, child: &Child, epoch: u64, certificate: Certificate) {
+        if self.ttl == Duration::ZERO {
+            return;
+        }
+        let mut certificates = self.certificates.lock().unwrap();
+        // Don't let certificates of dead agents or past epochs accumulate,
+        // but don't look for them more than once per `ttl` either.
+        if certificates.swept.elapsed() >= self.ttl {
+            let ttl = self.ttl;
+            certificates
+                .entries
+                .retain(|_, (_, received)| received.elapsed() < ttl);
+            certificates.swept = Instant::now();
+        }
+        certificates
+            .entries
+            .insert((epoch, agent(child)), (certificate, Instant::now()));
+    }
+
+    /// Run `campaign` for `value` among `children`, unless the same campaign is
+    /// already in progress in the current round, in which case wait for its result.
+    pub async fn coalesce<F>(
+        &self,
+        value: bool,
+        children: &[Child],
+        campaign: F,
+    ) -> Vec<Certificate>
+    where
+        F: Future<Output = Vec<Certificate>>,
+    {
+        let id = (
+            trace::current(),
+            value,
+            children.iter().map(agent).collect::<Vec<_>>(),
+        );
+        let joined = {
+            let mut campaigns = self.campaigns.lock().unwrap();
+            match campaigns.get(&id) {
+                Some(pending) => Err(pending.clone()),
+                None => {
+                    let (tresult, rresult) = watch::channel(None);
+                    campaigns.insert(id.clone(), rresult);
+                    Ok(tresult)
+                }
+            }
+        };
+        match joined {
+            Ok(tresult) => {
+                // Forget the campaign even if we're interrupted.
+                let _guard = Forget {
+                    campaigns: &self.campaigns,
+                    id,
+                };
+                let party = campaign.await;
+                let _ = tresult.broadcast(Some(party.clone()));
+                party
+            }
+            Err(mut pending) => {
+                debug!(target: "cache", "Joining campaign in progress among {} agents", children.len());
+                metrics::coalesced();
+                while let Some(result) = pending.recv().await {
+                    if let Some(party) = result {
+                        return party;
+                    }
+                }
+                // The campaign was interrupted, run ours.
+                campaign.await
+            }
+        }
+    }
+}
+impl Default for State {
+    fn default() -> Self {
+        State::new(DEFAULT_TTL)
+    }
+}
+
+/// Removes a campaign from `campaigns` once it is over.
+struct Forget<'a> {
+    campaigns: &'a Campaigns,
+    id: Id,
+}
+impl<'a> Drop for Forget<'a> {
+    fn drop(&mut self) {
+        self.campaigns.lock().unwrap().remove(&self.id);
+    }
+}
@@ -1,11 +1,146 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use log::*;
 use serde_derive::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+use crate::supervisor::{Command, RemoteSupervisor, Reply};
+use crate::util;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Child {
     pub pid: u32,
     pub socket: u16,
+
+    /// The number of votes of this agent, e.g. its stake.
+    #[serde(default = "default_weight")]
+    pub weight: usize,
+
+    /// The fingerprint of the TLS certificate of this agent, see module
+    /// `tls`. If specified, the agent only speaks TLS, and clients only
+    /// accept this certificate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+
+    /// The BLS public key of this agent, hex-encoded, which players check
+    /// aggregate certificates against, see module `aggregate`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+
+    /// The ed25519 public key of this agent, hex-encoded, which certificates
+    /// and proofs of equivocation are checked against, see module `accountability`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_key: Option<String>,
+}
+fn default_weight() -> usize {
+    1
+}
+impl Default for Child {
+    fn default() -> Self {
+        Child {
+            pid: 0,
+            socket: 0,
+            weight: default_weight(),
+            fingerprint: None,
+            key: None,
+            signing_key: None,
+        }
+    }
+}
+
+/// Two `Child`s are the same agent if they share pid and socket, whatever
+/// weight the registry gives them.
+impl PartialEq for Child {
+    fn eq(&self, other: &Self) -> bool {
+        self.pid == other.pid && self.socket == other.socket
+    }
+}
+
+/// The weight that must agree on a value among agents of total weight
+/// `total_weight`, e.g. a number of agents if all weights are 1.
+pub fn quorum(total_weight: usize) -> usize {
+    total_weight / 2 + 1
 }
-#[derive(Deserialize, Serialize, Debug)]
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct Conf {
+    /// The membership epoch, incremented by the supervisor whenever
+    /// agents join or leave the fleet.
+    #[serde(default)]
+    pub epoch: u64,
     pub children: Vec<Child>,
 }
+impl Conf {
+    /// Read the configuration from `path`.
+    ///
+    /// `path` is either a registry file or the control socket of a
+    /// supervisor, in which case we fetch the current membership.
+    pub async fn load(path: &Path) -> Result<Conf, std::io::Error> {
+        use std::os::unix::fs::FileTypeExt;
+        if std::fs::metadata(path)?.file_type().is_socket() {
+            return match RemoteSupervisor::new(path)
+                .call(&Command::Membership)
+                .await?
+            {
+                Reply::Membership(conf) => Ok(conf),
+                other => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Unexpected reply {:?}", other),
+                )),
+            };
+        }
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(&file)?)
+    }
+
+    /// The weight that must agree on a value for a client to accept it,
+    /// i.e. a strict majority of the weight of the current membership.
+    pub fn quorum(&self) -> usize {
+        quorum(self.weight())
+    }
+
+    /// The total weight of the current membership.
+    pub fn weight(&self) -> usize {
+        self.children.iter().map(|child| child.weight).sum()
+    }
+
+    /// The weight of agent `pid`, or 0 if it isn't a member.
+    pub fn weight_of(&self, pid: u32) -> usize {
+        self.children
+            .iter()
+            .find(|child| child.pid == pid)
+            .map(|child| child.weight)
+            .unwrap_or(0)
+    }
+
+    /// Write the configuration to `path`.
+    ///
+    /// The file is replaced atomically, so clients reading the registry
+    /// while it is being updated see either the old or the new version.
+    pub fn save(&self, path: &Path) -> Result<(), std::io::Error> {
+        let serialized = serde_json::to_string_pretty(self).unwrap();
+        util::write_atomic(path, serialized.as_bytes())
+    }
+}
+
+static REGISTRY: OnceLock<PathBuf> = OnceLock::new();
+
+/// Make `path` the registry of the agent running in this process, see
+/// `AgentArgs::registry`.
+pub fn set_registry(path: PathBuf) {
+    let _ = REGISTRY.set(path);
+}
+
+/// The current registry of the agent running in this process, if it has one.
+///
+/// We read it whenever we need it, since the supervisor may rewrite it.
+pub async fn registry() -> Option<Conf> {
+    let path = REGISTRY.get()?;
+    match Conf::load(path).await {
+        Ok(registry) => Some(registry),
+        Err(err) => {
+            warn!(target: "conf", "Could not read registry {:?}: {:?}", path, err);
+            None
+        }
+    }
+}
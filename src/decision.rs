@@ -0,0 +1,134 @@
+//! Decision certificates, i.e. compact proofs of the decision of a player,
+//! which anybody can check offline against the registry.
+//!
+//! A decision certificate is an aggregate certificate whose signers are
+//! relative to the agents of the registry, in order, see module `aggregate`.
+//! Players build it from the aggregate they have decided upon, if any, or
+//! by combining the votes of the parties they have received.
+
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::accountability;
+use crate::agent::{Certificate, Message, Response};
+use crate::aggregate::AggregateCertificate;
+use crate::conf::Conf;
+use crate::transcript::Entry;
+use crate::util;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DecisionCertificate {
+    pub value: bool,
+
+    /// The membership epoch of the registry the signers refer to.
+    ///
+    /// Votes are not bound to a round, so neither is the certificate.
+    pub epoch: u64,
+
+    /// The votes of a quorum of the registry for `value`.
+    pub aggregate: AggregateCertificate,
+}
+impl DecisionCertificate {
+    /// Build the certificate of decision `value`, among `entries`, with registry `conf`.
+    ///
+    /// Return `None` if the entries don't contain enough valid votes.
+    pub fn new(conf: &Conf, entries: &[Entry], value: bool) -> Option<DecisionCertificate> {
+        let certificate = |aggregate: AggregateCertificate| DecisionCertificate {
+            value,
+            epoch: conf.epoch,
+            aggregate,
+        };
+        // An aggregate among the agents of the registry is good as is.
+        let reusable = entries
+            .iter()
+            .find_map(|entry| match (&entry.response, &entry.request) {
+                (Some(Response::Aggregate(aggregate)), Message::AggregateCampaign(children))
+                    if aggregate.value == value && *children == conf.children =>
+                {
+                    Some(certificate(aggregate.clone()))
+                }
+                _ => None,
+            });
+        if let Some(decision) = reusable.filter(|decision| decision.verify(conf).is_ok()) {
+            return Some(decision);
+        }
+        // Otherwise, combine the votes of all parties, except those of equivocating agents.
+        let parties: Vec<&Certificate> = entries
+            .iter()
+            .flat_map(|entry| match entry.response {
+                Some(Response::Quorum(ref party)) => party.iter(),
+                _ => [].iter(),
+            })
+            .collect();
+        let culprits: Vec<_> = accountability::find(parties.iter().cloned(), &conf.children)
+            .iter()
+            .map(|proof| proof.culprit().pid)
+            .collect();
+        let votes: Vec<Certificate> = parties
+            .into_iter()
+            .filter(|certificate| !culprits.contains(&certificate.issuer.pid))
+            .cloned()
+            .collect();
+        let decision = certificate(AggregateCertificate::new(value, &conf.children, &votes)?);
+        decision.verify(conf).ok()?;
+        Some(decision)
+    }
+
+    /// Check the certificate against the registry `conf`.
+    ///
+    /// Return the weight of the signers.
+    pub fn verify(&self, conf: &Conf) -> Result<usize, String> {
+        if self.epoch != conf.epoch {
+            return Err(format!(
+                "Certificate refers to epoch {}, registry is at epoch {}",
+                self.epoch, conf.epoch
+            ));
+        }
+        if self.aggregate.value != self.value {
+            return Err("Votes are for another value".to_string());
+        }
+        let weight = self
+            .aggregate
+            .verify(&conf.children, conf)
+            .ok_or_else(|| "Invalid signature".to_string())?;
+        if weight < conf.quorum() {
+            return Err(format!(
+                "Signers have weight {}, quorum is {}",
+                weight,
+                conf.quorum()
+            ));
+        }
+        Ok(weight)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, std::io::Error> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    /// Write the certificate to `path`, atomically, see `util::write_atomic`.
+    pub fn save(&self, path: &Path) -> Result<(), std::io::Error> {
+        util::write_atomic(path, &serde_json::to_vec_pretty(self)?)
+    }
+}
+
+/// Implementation of command `verify`.
+///
+/// Check the certificate at `path` against the registry at `agents`, print the decision.
+pub async fn verify(path: &Path, agents: &Path) -> Result<(), String> {
+    let certificate = DecisionCertificate::load(path)
+        .map_err(|err| format!("Could not read certificate: {}", err))?;
+    let conf = Conf::load(agents)
+        .await
+        .map_err(|err| format!("Could not read registry: {}", err))?;
+    let weight = certificate.verify(&conf)?;
+    println!(
+        "{}: signed by weight {} out of {}, quorum {}",
+        certificate.value,
+        weight,
+        conf.weight(),
+        conf.quorum()
+    );
+    Ok(())
+}
@@ -0,0 +1,183 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::*;
+use rand::seq::SliceRandom;
+
+use crate::agent::{Message, RemoteAgent, Response};
+use crate::conf::Child;
+
+/// The most peers a `View` remembers, so that a peer gossiping garbage
+/// cannot grow it without bound. Matches the default `Limits::max_children`.
+pub const MAX_PEERS: usize = 1024;
+
+/// The peers known to an agent, including itself.
+#[derive(Clone)]
+pub struct View {
+    me: Child,
+    peers: Arc<Mutex<Vec<Child>>>,
+
+    /// The (pid, socket) of the peers we removed, so that gossip from
+    /// peers that haven't noticed yet doesn't bring them back. At most
+    /// `MAX_PEERS`, oldest first.
+    removed: Arc<Mutex<VecDeque<(u32, u16)>>>,
+}
+impl View {
+    pub fn new(me: Child) -> Self {
+        View {
+            peers: Arc::new(Mutex::new(vec![me.clone()])),
+            removed: Arc::new(Mutex::new(VecDeque::new())),
+            me,
+        }
+    }
+    pub fn me(&self) -> &Child {
+        &self.me
+    }
+    pub fn peers(&self) -> Vec<Child> {
+        self.peers.lock().unwrap().clone()
+    }
+
+    /// Add any peer we didn't know about yet, up to `MAX_PEERS`, unless
+    /// we have removed it.
+    pub fn merge(&self, peers: &[Child]) {
+        let mut known = self.peers.lock().unwrap();
+        let removed = self.removed.lock().unwrap();
+        for peer in peers {
+            if removed.contains(&(peer.pid, peer.socket)) {
+                continue;
+            }
+            if known.len() >= MAX_PEERS {
+                debug!(target: "gossip", "{} Too many peers, ignoring the others", self.me.pid);
+                break;
+            }
+            if !known.contains(peer) {
+                debug!(target: "gossip", "{} Discovered peer {} on port {}", self.me.pid, peer.pid, peer.socket);
+                known.push(peer.clone());
+            }
+        }
+    }
+
+    /// Forget about a peer, typically because it seems dead.
+    pub fn remove(&self, peer: &Child) {
+        if *peer == self.me {
+            return;
+        }
+        self.peers.lock().unwrap().retain(|known| known != peer);
+        let mut removed = self.removed.lock().unwrap();
+        if !removed.contains(&(peer.pid, peer.socket)) {
+            if removed.len() >= MAX_PEERS {
+                removed.pop_front();
+            }
+            removed.push_back((peer.pid, peer.socket));
+        }
+    }
+
+    /// Pick a random peer other than ourself.
+    fn choose(&self) -> Option<Child> {
+        let peers = self.peers.lock().unwrap();
+        let others: Vec<_> = peers.iter().filter(|peer| **peer != self.me).collect();
+        others
+            .choose(&mut rand::thread_rng())
+            .map(|peer| (*peer).clone())
+    }
+}
+
+#[derive(Clone)]
+pub struct GossipArgs {
+    /// A few agents we can contact to discover the fleet, see `parse_bootstrap`.
+    pub bootstrap: Vec<Child>,
+
+    /// Delay between two exchanges with a random peer.
+    pub period: Duration,
+}
+
+/// Parse a bootstrap agent, given as `PORT` or, if it speaks TLS,
+/// `PORT:FINGERPRINT`, see `format_bootstrap`.
+///
+/// We don't know its pid, it will tell us in its view.
+pub fn parse_bootstrap(s: &str) -> Result<Child, String> {
+    let mut parts = s.splitn(2, ':');
+    let socket = parts
+        .next()
+        .unwrap_or_default()
+        .parse::<u16>()
+        .map_err(|err| format!("Invalid port: {}", err))?;
+    Ok(Child {
+        pid: 0,
+        socket,
+        weight: 1,
+        fingerprint: parts.next().map(str::to_string),
+        key: None,
+        signing_key: None,
+    })
+}
+
+/// Format `child` as a bootstrap agent, see `parse_bootstrap`.
+pub fn format_bootstrap(child: &Child) -> String {
+    match child.fingerprint {
+        Some(ref fingerprint) => format!("{}:{}", child.socket, fingerprint),
+        None => format!("{}", child.socket),
+    }
+}
+
+/// Exchange our view with `peer`, merge its view into ours.
+async fn exchange(view: &View, peer: Child) -> Result<(), String> {
+    let remote = RemoteAgent::new(peer);
+    match remote.call(&Message::Gossip(view.peers())).await {
+        Ok(Response::Peers(peers)) => {
+            view.merge(&peers);
+            Ok(())
+        }
+        Ok(other) => Err(format!("Bad response {:?}", other)),
+        Err(err) => Err(format!("{:?}", err)),
+    }
+}
+
+/// Ask each of `known` for the peers they know, return all the agents
+/// we have heard of.
+pub async fn discover(known: &[Child]) -> Vec<Child> {
+    let mut discovered: Vec<Child> = vec![];
+    for child in known {
+        let remote = RemoteAgent::new(child.clone());
+        match remote.call(&Message::GetPeers).await {
+            Ok(Response::Peers(peers)) => {
+                for peer in peers {
+                    if !discovered.contains(&peer) {
+                        discovered.push(peer);
+                    }
+                }
+            }
+            other => {
+                debug!(target: "gossip", "Could not get peers from child {}: {:?}", child.pid, other)
+            }
+        }
+    }
+    discovered
+}
+
+/// Maintain `view` by periodically exchanging it with a random peer, forever.
+///
+/// Peers that cannot be reached are removed from the view.
+pub async fn gossip(view: View, args: GossipArgs) {
+    let mut interval = tokio::time::interval(args.period);
+    loop {
+        interval.tick().await;
+        match view.choose() {
+            Some(peer) => {
+                if let Err(err) = exchange(&view, peer.clone()).await {
+                    debug!(target: "gossip", "{} Could not gossip with {}, forgetting it: {}", view.me().pid, peer.pid, err);
+                    view.remove(&peer);
+                }
+            }
+            None => {
+                // We don't know anybody yet (or anymore), ask the bootstrap agents.
+                for bootstrap in &args.bootstrap {
+                    if let Err(err) = exchange(&view, bootstrap.clone()).await {
+                        warn!(target: "gossip", "{} Could not reach bootstrap agent on port {}: {}", view.me().pid, bootstrap.socket, err);
+                    }
+                }
+            }
+        }
+    }
+}
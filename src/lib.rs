@@ -5,9 +5,30 @@ extern crate serde;
 extern crate serde_derive;
 extern crate tokio;
 
+pub mod accountability;
+pub mod admin;
 pub mod agent;
+pub mod aggregate;
+pub mod agreement;
+pub mod bench;
+pub mod cache;
 pub mod conf;
+pub mod decision;
+pub mod gossip;
+pub mod limits;
+pub mod metrics;
 pub mod play;
+pub mod playagree;
 pub mod playexpert;
+pub mod playsample;
+pub mod playsnowball;
+pub mod reputation;
+pub mod snowball;
 pub mod start;
+pub mod stats;
+pub mod status;
+pub mod supervisor;
+pub mod tls;
+pub mod trace;
+pub mod transcript;
 pub mod util;
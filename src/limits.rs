@@ -0,0 +1,112 @@
+//! Limits on what an agent accepts from its clients, so that a single
+//! client can't exhaust the memory, file descriptors or peers of an agent.
+//!
+//! Rejected requests are refused with a `Response::Error` and counted in
+//! metrics, see `metrics::rejected`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// The maximal length of a request, in bytes, including the newline.
+    pub max_line: usize,
+
+    /// The maximal number of agents listed in a request, e.g. a `Campaign`.
+    pub max_children: usize,
+
+    /// The maximal number of connections served at once.
+    pub max_connections: usize,
+
+    /// If specified, the number of requests per second each connection may
+    /// send, on average.
+    ///
+    /// Budgets are per connection, so that a noisy client doesn't throttle
+    /// the others, e.g. other agents of a local fleet sharing its address.
+    /// `max_connections` bounds the total.
+    pub rate: Option<f64>,
+
+    /// The number of requests a connection may send in a burst, if rate-limited.
+    pub burst: usize,
+
+    /// Close connections that haven't sent a request for this long.
+    pub idle_timeout: Duration,
+}
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_line: 1 << 20,
+            max_children: 1024,
+            max_connections: 1024,
+            rate: None,
+            burst: 100,
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The connections being served by an agent.
+#[derive(Clone, Default)]
+pub struct Connections(Arc<AtomicUsize>);
+impl Connections {
+    /// Count a new connection, unless there are already `max` of them.
+    pub fn try_acquire(&self, max: usize) -> Option<ConnectionGuard> {
+        let previous = self.0.fetch_add(1, Ordering::SeqCst);
+        let guard = ConnectionGuard(self.0.clone());
+        if previous >= max {
+            // Dropping the guard releases the slot.
+            return None;
+        }
+        Some(guard)
+    }
+}
+
+/// A connection being served, counted until dropped.
+pub struct ConnectionGuard(Arc<AtomicUsize>);
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    /// Add the tokens earned since the last refill, up to `burst`.
+    fn refill(&mut self, now: Instant, rate: f64, burst: f64) {
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.refilled = now;
+    }
+}
+
+/// The token bucket of a connection, see `Limits::rate`.
+#[derive(Default)]
+pub struct RateLimiter {
+    bucket: Option<Bucket>,
+}
+impl RateLimiter {
+    /// Whether the connection may send one more request, given `limits`.
+    pub fn allow(&mut self, limits: &Limits) -> bool {
+        let rate = match limits.rate {
+            Some(rate) => rate,
+            None => return true,
+        };
+        let burst = limits.burst as f64;
+        let now = Instant::now();
+        let bucket = self.bucket.get_or_insert(Bucket {
+            tokens: burst,
+            refilled: now,
+        });
+        bucket.refill(now, rate, burst);
+        if bucket.tokens < 1. {
+            return false;
+        }
+        bucket.tokens -= 1.;
+        true
+    }
+}
@@ -0,0 +1,390 @@
+//! Health metrics for this process, exposed in the Prometheus text format
+//! over a minimal HTTP endpoint.
+
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use log::*;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+use crate::stats;
+
+/// Upper bounds of the buckets of the campaign latency histogram, in seconds.
+const BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1., 2.5, 5.];
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+static ACTIVE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+static ERRORS: Mutex<Vec<(&str, usize)>> = Mutex::new(vec![]);
+static REJECTED: Mutex<Vec<(&str, usize)>> = Mutex::new(vec![]);
+static CACHE_HITS: AtomicUsize = AtomicUsize::new(0);
+static CACHE_MISSES: AtomicUsize = AtomicUsize::new(0);
+static COALESCED: AtomicUsize = AtomicUsize::new(0);
+static CAMPAIGNS: Mutex<Histogram> = Mutex::new(Histogram {
+    buckets: [0; BUCKETS.len()],
+    sum: 0.,
+    count: 0,
+});
+
+struct Histogram {
+    /// Non-cumulative counts, per bucket.
+    buckets: [usize; BUCKETS.len()],
+    sum: f64,
+    count: usize,
+}
+
+/// Start measuring uptime.
+pub fn init() {
+    STARTED.get_or_init(Instant::now);
+}
+
+/// Time since `init`.
+pub fn uptime() -> Duration {
+    STARTED
+        .get()
+        .map(|started| started.elapsed())
+        .unwrap_or_default()
+}
+
+/// The number of connections being served, see `Connection`.
+pub fn active_connections() -> usize {
+    ACTIVE_CONNECTIONS.load(Ordering::Relaxed)
+}
+
+/// Record an error of kind `kind`, e.g. `"invalid_message"`.
+pub fn error(kind: &'static str) {
+    let mut errors = ERRORS.lock().unwrap();
+    match errors.iter_mut().find(|(k, _)| *k == kind) {
+        Some((_, count)) => *count += 1,
+        None => errors.push((kind, 1)),
+    }
+}
+
+/// Record a request rejected for reason `reason`, e.g. `"rate_limited"`,
+/// see module `limits`.
+pub fn rejected(reason: &'static str) {
+    let mut rejected = REJECTED.lock().unwrap();
+    match rejected.iter_mut().find(|(r, _)| *r == reason) {
+        Some((_, count)) => *count += 1,
+        None => rejected.push((reason, 1)),
+    }
+}
+
+/// Record a lookup in the certificate cache, see module `cache`.
+pub fn cache(hit: bool) {
+    if hit {
+        CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+    } else {
+        CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Record a campaign that joined another campaign in progress, see module `cache`.
+pub fn coalesced() {
+    COALESCED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Record the duration of a campaign.
+pub fn campaign(duration: Duration) {
+    let seconds = duration.as_secs_f64();
+    let mut histogram = CAMPAIGNS.lock().unwrap();
+    if let Some(index) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+        histogram.buckets[index] += 1;
+    }
+    histogram.sum += seconds;
+    histogram.count += 1;
+}
+
+/// A connection being served, counted as active until dropped.
+pub struct Connection(());
+impl Connection {
+    pub fn new() -> Self {
+        ACTIVE_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+        Connection(())
+    }
+}
+impl Default for Connection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Drop for Connection {
+    fn drop(&mut self) {
+        ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The metrics of this process, in the Prometheus text format.
+pub fn render() -> String {
+    let mut out = String::new();
+    let uptime = uptime().as_secs_f64();
+    writeln!(
+        out,
+        "# HELP liarslie_uptime_seconds Time since the agent started."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE liarslie_uptime_seconds gauge").unwrap();
+    writeln!(out, "liarslie_uptime_seconds {}", uptime).unwrap();
+
+    let stats = stats::stats();
+    writeln!(
+        out,
+        "# HELP liarslie_requests_total Requests served, by message type."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE liarslie_requests_total counter").unwrap();
+    for (message, traffic) in &stats.incoming.by_message {
+        writeln!(
+            out,
+            "liarslie_requests_total{{type=\"{}\"}} {}",
+            message, traffic.messages
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "# HELP liarslie_calls_total Calls to other agents, by message type."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE liarslie_calls_total counter").unwrap();
+    for (message, traffic) in &stats.outgoing.by_message {
+        writeln!(
+            out,
+            "liarslie_calls_total{{type=\"{}\"}} {}",
+            message, traffic.messages
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "# HELP liarslie_errors_total Errors, by kind.").unwrap();
+    writeln!(out, "# TYPE liarslie_errors_total counter").unwrap();
+    for (kind, count) in ERRORS.lock().unwrap().iter() {
+        writeln!(out, "liarslie_errors_total{{kind=\"{}\"}} {}", kind, count).unwrap();
+    }
+
+    writeln!(
+        out,
+        "# HELP liarslie_rejected_total Requests rejected by limits, by reason."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE liarslie_rejected_total counter").unwrap();
+    for (reason, count) in REJECTED.lock().unwrap().iter() {
+        writeln!(
+            out,
+            "liarslie_rejected_total{{reason=\"{}\"}} {}",
+            reason, count
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        out,
+        "# HELP liarslie_certificate_cache_total Lookups of peer certificates in the cache, by result."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE liarslie_certificate_cache_total counter").unwrap();
+    writeln!(
+        out,
+        "liarslie_certificate_cache_total{{result=\"hit\"}} {}",
+        CACHE_HITS.load(Ordering::Relaxed)
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "liarslie_certificate_cache_total{{result=\"miss\"}} {}",
+        CACHE_MISSES.load(Ordering::Relaxed)
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "# HELP liarslie_coalesced_campaigns_total Campaigns that joined an identical campaign in progress."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE liarslie_coalesced_campaigns_total counter").unwrap();
+    writeln!(
+        out,
+        "liarslie_coalesced_campaigns_total {}",
+        COALESCED.load(Ordering::Relaxed)
+    )
+    .unwrap();
+
+    writeln!(
+        out,
+        "# HELP liarslie_active_connections Connections being served."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE liarslie_active_connections gauge").unwrap();
+    writeln!(out, "liarslie_active_connections {}", active_connections()).unwrap();
+
+    let histogram = CAMPAIGNS.lock().unwrap();
+    writeln!(
+        out,
+        "# HELP liarslie_campaign_duration_seconds Time spent campaigning."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE liarslie_campaign_duration_seconds histogram").unwrap();
+    let mut cumulative = 0;
+    for (bound, count) in BUCKETS.iter().zip(histogram.buckets.iter()) {
+        cumulative += count;
+        writeln!(
+            out,
+            "liarslie_campaign_duration_seconds_bucket{{le=\"{}\"}} {}",
+            bound, cumulative
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "liarslie_campaign_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+        histogram.count
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "liarslie_campaign_duration_seconds_sum {}",
+        histogram.sum
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "liarslie_campaign_duration_seconds_count {}",
+        histogram.count
+    )
+    .unwrap();
+    out
+}
+
+/// Add label `name="value"` to every sample of `metrics`.
+pub fn relabel(metrics: &str, name: &str, value: &str) -> String {
+    let mut out = String::new();
+    for line in metrics.lines() {
+        if line.starts_with('#') || line.is_empty() {
+            out.push_str(line);
+        } else if let Some(brace) = line.find('{') {
+            write!(
+                out,
+                "{}{}=\"{}\",{}",
+                &line[..=brace],
+                name,
+                value,
+                &line[brace + 1..]
+            )
+            .unwrap();
+        } else if let Some(space) = line.find(' ') {
+            write!(
+                out,
+                "{}{{{}=\"{}\"}}{}",
+                &line[..space],
+                name,
+                value,
+                &line[space..]
+            )
+            .unwrap();
+        } else {
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Merge several outputs of `render`, so that each metric appears only once,
+/// with the samples of all outputs.
+pub fn merge(outputs: &[String]) -> String {
+    // Families, in order of appearance: (name, comments, samples).
+    let mut families: Vec<(String, Vec<String>, Vec<String>)> = vec![];
+    for output in outputs {
+        let mut current = None;
+        for line in output.lines() {
+            if let Some(rest) = line
+                .strip_prefix("# HELP ")
+                .or_else(|| line.strip_prefix("# TYPE "))
+            {
+                let name = rest.split(' ').next().unwrap_or("").to_string();
+                let index = match families.iter().position(|(n, _, _)| *n == name) {
+                    Some(index) => index,
+                    None => {
+                        families.push((name, vec![], vec![]));
+                        families.len() - 1
+                    }
+                };
+                if !families[index].1.iter().any(|comment| comment == line) {
+                    families[index].1.push(line.to_string());
+                }
+                current = Some(index);
+            } else if !line.is_empty() {
+                match current {
+                    Some(index) => families[index].2.push(line.to_string()),
+                    None => families.push((String::new(), vec![], vec![line.to_string()])),
+                }
+            }
+        }
+    }
+    let mut out = String::new();
+    for (_, comments, samples) in families {
+        for line in comments.iter().chain(samples.iter()) {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Serve `GET /metrics` with the output of `render`, forever.
+///
+/// Only requests to `/metrics` are supported, everything else gets a 404.
+pub async fn serve<F, P>(mut listener: TcpListener, render: F)
+where
+    F: Fn() -> P + Clone + Send + 'static,
+    P: Future<Output = String> + Send,
+{
+    loop {
+        let (mut conn, _) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!(target: "metrics", "Could not accept connection {:?}", err);
+                continue;
+            }
+        };
+        let render = render.clone();
+        tokio::spawn(async move {
+            let mut reader = BufReader::new(&mut conn);
+            let mut request = String::new();
+            if reader.read_line(&mut request).await.is_err() {
+                return;
+            }
+            // Skip headers.
+            loop {
+                let mut header = String::new();
+                match reader.read_line(&mut header).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) if header.trim().is_empty() => break,
+                    Ok(_) => {}
+                }
+            }
+            debug!(target: "metrics", "Request {}", request.trim_end());
+            let mut words = request.split_whitespace();
+            let response = match (words.next(), words.next()) {
+                (Some("GET"), Some("/metrics")) => {
+                    let body = render().await;
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                }
+                _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string(),
+            };
+            let _ = reader.get_mut().write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+/// Open a metrics endpoint on `port`, local connections only.
+pub async fn bind(port: u16) -> Result<TcpListener, std::io::Error> {
+    TcpListener::bind(("127.0.0.1", port)).await
+}
@@ -1,51 +1,122 @@
 use std::path::PathBuf;
+use std::time::Instant;
 
 use log::*;
+use tracing::instrument;
 
+use crate::accountability;
 use crate::agent;
 use crate::conf::*;
+use crate::reputation;
+use crate::trace;
+use crate::transcript::{Entry, Strategy, Transcript};
 
 pub struct PlayArgs {
     pub path: PathBuf,
+
+    /// If specified, record the round to this file, see module `transcript`.
+    pub transcript: Option<PathBuf>,
+
+    /// If specified, record which agents disagreed with the decision in
+    /// this store, see module `reputation`.
+    pub reputation: Option<PathBuf>,
+}
+impl Default for PlayArgs {
+    fn default() -> Self {
+        PlayArgs {
+            path: PathBuf::from("agents.conf"),
+            transcript: None,
+            reputation: None,
+        }
+    }
+}
+
+/// The certificate sent by the agent in `entry`, if any and if it isn't forged.
+fn certificate(entry: &Entry) -> Option<&agent::Certificate> {
+    match entry.response {
+        // Only the agent we asked may vouch for itself.
+        Some(agent::Response::Certificate(ref certificate))
+            if accountability::verify(certificate, &entry.child) =>
+        {
+            Some(certificate)
+        }
+        _ => None,
+    }
+}
+
+/// The value vouched for by the agent in `entry`, if any.
+fn vote(entry: &Entry) -> Option<bool> {
+    certificate(entry).map(|certificate| certificate.value)
+}
+
+/// The first value to reach a quorum of votes among `entries`, in order,
+/// each agent of `conf` voting with its weight.
+///
+/// Agents caught equivocating among `entries` are ignored.
+pub fn decide(conf: &Conf, entries: &[Entry]) -> Option<bool> {
+    let quorum = conf.quorum();
+    let culprits: Vec<_> =
+        accountability::find(entries.iter().filter_map(certificate), &conf.children)
+            .iter()
+            .map(|proof| proof.culprit().pid)
+            .collect();
+    let mut yeas = 0usize;
+    let mut nays = 0usize;
+    for (value, weight) in entries
+        .iter()
+        .filter(|entry| !culprits.contains(&entry.child.pid))
+        .filter_map(|entry| vote(entry).map(|value| (value, conf.weight_of(entry.child.pid))))
+    {
+        debug!(target: "collector", "Treating {} with weight {}", value, weight);
+        if value {
+            yeas += weight;
+            if yeas >= quorum {
+                // We have a quorum, no need to proceed.
+                return Some(true);
+            }
+        } else {
+            nays += weight;
+            if nays >= quorum {
+                // We have a quorum, no need to proceed.
+                return Some(false);
+            }
+        }
+        debug!(target: "play",
+            "Collector: yeas {}, nays {}, we should continue",
+            yeas, nays
+        );
+    }
+    None
 }
 
+#[instrument(name = "play", parent = None, skip_all, fields(trace = trace::new_id()))]
 pub async fn play(args: &PlayArgs) -> Option<bool> {
+    trace::round(play_round(args)).await
+}
+
+async fn play_round(args: &PlayArgs) -> Option<bool> {
+    let started = Instant::now();
     // Attempt to parse configuration.
-    let file = std::fs::File::open(&args.path).expect("Could not open file");
-    let conf: Conf = serde_json::from_reader(&file).expect("Could not read file");
-    let number_of_children = conf.children.len();
+    let conf = Conf::load(&args.path)
+        .await
+        .expect("Could not read configuration");
+    let quorum = conf.quorum();
+    debug!(target: "play", "Playing with {} agents at epoch {}, quorum is {}",
+        conf.children.len(),
+        conf.epoch,
+        quorum
+    );
     let (tcollect, mut rcollect) = tokio::sync::mpsc::channel(32);
 
-    // Collect responses.
-    let collector = tokio::spawn(async move {
-        let mut yeas = 0usize;
-        let mut nays = 0usize;
-        let mut result = None;
+    // Collect responses, in order of arrival.
+    let collector = trace::spawn(async move {
+        let mut entries = vec![];
         debug!(target: "collector", "Starting");
-        while let Some(msg) = rcollect.recv().await {
-            debug!(target: "collector", "Treating {}", msg);
-            if msg {
-                yeas += 1;
-                if yeas >= number_of_children / 2 {
-                    // We have a quorum, no need to proceed.
-                    result = Some(true);
-                    break;
-                }
-            } else {
-                nays += 1;
-                if nays >= number_of_children / 2 {
-                    // We have a quorum, no need to proceed.
-                    result = Some(false);
-                    break;
-                }
-            }
-            debug!(target: "play",
-                "Collector: yeas {}, nays {}, we should continue",
-                yeas, nays
-            );
+        while let Some(entry) = rcollect.recv().await {
+            entries.push(entry);
         }
         debug!(target: "collector", "Done");
-        return result;
+        entries
     });
 
     // Talk to each agent.
@@ -53,23 +124,29 @@ pub async fn play(args: &PlayArgs) -> Option<bool> {
         // Make sure that `tcollect` is fully dropped once all tasks are complete.
         let tcollect = tcollect;
         conf.children.iter().cloned().map(|child| {
-            let remote = agent::RemoteAgent::new(child.clone());
             let mut tcollect = tcollect.clone();
-            tokio::spawn(async move {
-                match remote.call(&agent::Message::GetValue).await {
-                    Ok(agent::Response::Certificate(agent::Certificate { value, .. })) => {
+            trace::spawn(async move {
+                let entry = Entry::call(child.clone(), agent::Message::GetValue, started).await;
+                match (&entry.response, &entry.error, entry.refused) {
+                    (Some(agent::Response::Certificate(agent::Certificate { value, .. })), _, _) => {
                         debug!(target: "play", "Play: Received value {} from remote agent", value);
-                        // Ignore errors: the collector may have finished already.
-                        let _ = tcollect.send(value).await;
                     }
-                    Ok(other) => {
+                    (None, Some(error), Some(code)) => {
+                        warn!(target: "play", "Child {pid} on port {port} refused the request ({code}): {error}",
+                            pid = child.pid,
+                            port = child.socket,
+                            code = code.name(),
+                            error = error
+                        );
+                    }
+                    (Some(other), _, _) => {
                         debug!(target: "play", "Bad response from child {pid} on port {port}: {response:?}",
                             pid = child.pid,
                             port = child.socket,
                             response = other
                         );
                     }
-                    Err(error) => {
+                    (None, error, _) => {
                         debug!(target: "play", "Could not communicate with child {pid} on port {port}: {error:?}, skipping child.",
                             pid = child.pid,
                             port = child.socket,
@@ -77,6 +154,7 @@ pub async fn play(args: &PlayArgs) -> Option<bool> {
                         );
                     }
                 }
+                let _ = tcollect.send(entry).await;
             })
         }).collect()
     };
@@ -84,11 +162,38 @@ pub async fn play(args: &PlayArgs) -> Option<bool> {
     for task in tasks.into_iter() {
         task.await.unwrap();
     }
-    let result = collector.await.unwrap();
+    let entries = collector.await.unwrap();
+    let result = decide(&conf, &entries);
     match result {
         Some(true) => debug!(target: "play", "The value was 'true'"),
         Some(false) => debug!(target: "play", "The value was 'false'"),
         None => debug!(target: "play", "Not enough participants to determine value"),
     };
+    for proof in accountability::find(entries.iter().filter_map(certificate), &conf.children) {
+        warn!(target: "play", "Agent {} has equivocated", proof.culprit().pid);
+        accountability::broadcast(conf.children.clone(), proof).await;
+    }
+    if let (Some(path), Some(decided)) = (&args.reputation, result) {
+        let votes: Vec<_> = entries
+            .iter()
+            .filter_map(|entry| vote(entry).map(|value| (entry.child.clone(), value)))
+            .collect();
+        if let Err(err) = reputation::update(path, decided, &votes) {
+            warn!(target: "play", "Could not update reputation {:?}: {:?}", path, err);
+        }
+    }
+    if let Some(ref path) = args.transcript {
+        let transcript = Transcript {
+            strategy: Strategy::Play,
+            trace: trace::current(),
+            conf,
+            quorum,
+            entries,
+            result,
+        };
+        if let Err(err) = transcript.save(path) {
+            warn!(target: "play", "Could not write transcript {:?}: {:?}", path, err);
+        }
+    }
     result
 }
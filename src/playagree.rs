@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+
+use log::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use tracing::instrument;
+
+use crate::agent;
+use crate::conf::*;
+use crate::trace;
+
+pub struct PlayAgreeArgs {
+    pub path: PathBuf,
+}
+
+/// Have the agents run a round of agreement, read the decision from a single agent.
+///
+/// If the agent cannot produce a valid certificate of commitment, try another one.
+#[instrument(name = "playagree", parent = None, skip_all, fields(trace = trace::new_id()))]
+pub async fn play(args: &PlayAgreeArgs) -> Option<bool> {
+    trace::round(play_round(args)).await
+}
+
+async fn play_round(args: &PlayAgreeArgs) -> Option<bool> {
+    // Attempt to parse configuration.
+    let conf = Conf::load(&args.path)
+        .await
+        .expect("Could not read configuration");
+    let round = rand::thread_rng().gen::<u64>();
+    debug!(target: "playagree", "Starting round {} with {} agents", round, conf.children.len());
+
+    let mut interlocutors = conf.children.clone();
+    interlocutors.shuffle(&mut rand::thread_rng());
+    for child in interlocutors {
+        let remote = agent::RemoteAgent::new(child.clone());
+        let message = agent::Message::Agree {
+            round,
+            children: conf.children.clone(),
+        };
+        match remote.call(&message).await {
+            Ok(agent::Response::Decision(commitment)) => {
+                if commitment.verify(round, &conf) {
+                    debug!(target: "playagree", "The value was '{}'", commitment.value);
+                    return Some(commitment.value);
+                }
+                warn!(target: "playagree", "Invalid commitment from child {pid} on port {port}",
+                    pid = child.pid,
+                    port = child.socket
+                );
+            }
+            Ok(other) => {
+                debug!(target: "playagree", "Bad response from child {pid} on port {port}: {response:?}",
+                    pid = child.pid,
+                    port = child.socket,
+                    response = other
+                );
+            }
+            Err(error) => {
+                debug!(target: "playagree", "Could not communicate with child {pid} on port {port}: {error:?}, skipping child.",
+                    pid = child.pid,
+                    port = child.socket,
+                    error = error
+                );
+            }
+        }
+    }
+    debug!(target: "playagree", "Not enough participants to determine value");
+    None
+}
@@ -1,99 +1,459 @@
+use std::collections::HashMap;
 use std::iter::Iterator;
 use std::path::PathBuf;
+use std::time::Instant;
 
 use log::*;
 use rand::seq::SliceRandom;
+use tracing::instrument;
 
-use crate::agent;
+use crate::accountability;
+use crate::agent::{self, Certificate};
 use crate::conf::*;
+use crate::decision::DecisionCertificate;
+use crate::gossip;
+use crate::reputation::{self, Reputation};
+use crate::trace;
+use crate::transcript::{Entry, Strategy, Transcript};
 
 pub struct PlayExpertArgs {
     pub liar_ratio: f64,
     pub path: PathBuf,
+
+    /// If `true`, let interlocutors campaign among the peers they know
+    /// rather than among the agents we list. We only talk to the agents of
+    /// the configuration that their peers know about, and only count the
+    /// votes of the agents of the configuration.
+    pub discover: bool,
+
+    /// If specified, record the round to this file, see module `transcript`.
+    pub transcript: Option<PathBuf>,
+
+    /// If specified, record which agents disagreed with the decision in
+    /// this store, see module `reputation`.
+    pub reputation: Option<PathBuf>,
+
+    /// If `true`, prefer interlocutors that are not suspected to be liars
+    /// by the reputation store.
+    pub avoid_suspects: bool,
+
+    /// If `true`, have interlocutors stream their allies as they find them,
+    /// and decide as soon as any of them has found a quorum, without
+    /// waiting for the others, see `Message::StreamCampaign`.
+    pub stream: bool,
+
+    /// If `true`, have interlocutors combine the votes of their allies into
+    /// a single certificate, which we check in one step, see module `aggregate`.
+    pub aggregate: bool,
+
+    /// If specified, write a certificate of the decision to this file, see
+    /// module `decision`.
+    pub certificate: Option<PathBuf>,
+}
+impl Default for PlayExpertArgs {
+    fn default() -> Self {
+        PlayExpertArgs {
+            liar_ratio: 0.1,
+            path: PathBuf::from("agents.conf"),
+            discover: false,
+            transcript: None,
+            reputation: None,
+            avoid_suspects: false,
+            stream: false,
+            aggregate: false,
+            certificate: None,
+        }
+    }
 }
 
+/// The certificates of all the parties among `entries`.
+fn certificates(entries: &[Entry]) -> impl Iterator<Item = &Certificate> {
+    entries.iter().flat_map(|entry| match entry.response {
+        Some(agent::Response::Quorum(ref party)) => party.iter(),
+        _ => [].iter(),
+    })
+}
+
+/// The first party among `entries`, in order, that forms a quorum.
+///
+/// Certificates that are not signed with the key published for their issuer
+/// in `conf`, duplicate certificates and certificates issued by agents
+/// caught equivocating among `entries` are ignored.
+///
+/// Aggregate certificates are taken into account before parties.
+pub fn decide(conf: &Conf, entries: &[Entry]) -> Option<bool> {
+    let quorum = conf.quorum();
+    for entry in entries {
+        if let (
+            Some(agent::Response::Aggregate(ref aggregate)),
+            agent::Message::AggregateCampaign(ref children),
+        ) = (&entry.response, &entry.request)
+        {
+            match aggregate.verify(children, conf) {
+                Some(weight) if weight >= quorum => {
+                    debug!(target: "playexpert", "got an aggregate of weight {} for {}, that's a quorum", weight, aggregate.value);
+                    return Some(aggregate.value);
+                }
+                Some(weight) => {
+                    debug!(target: "playexpert", "Aggregate of weight {} is too small to be a quorum", weight)
+                }
+                None => {
+                    warn!(target: "playexpert", "Child {} sent an invalid aggregate, ignoring it", entry.child.pid)
+                }
+            }
+        }
+    }
+    let parties: Vec<&[Certificate]> = entries
+        .iter()
+        .filter_map(|entry| match entry.response {
+            Some(agent::Response::Quorum(ref party)) => Some(party.as_slice()),
+            _ => None,
+        })
+        .collect();
+    decide_among(conf, &parties, &mut HashMap::new())
+}
+
+/// The first of `parties`, in order, that forms a quorum, see `decide`.
+///
+/// `verified` remembers the certificates checked so far, by signature.
+fn decide_among(
+    conf: &Conf,
+    parties: &[&[Certificate]],
+    verified: &mut HashMap<String, bool>,
+) -> Option<bool> {
+    let quorum = conf.quorum();
+    let culprits: Vec<_> = accountability::find(
+        parties.iter().flat_map(|party| party.iter()),
+        &conf.children,
+    )
+    .iter()
+    .map(|proof| proof.culprit().pid)
+    .collect();
+    for party in parties {
+        let mut issuers = vec![];
+        let party: Vec<_> = party
+            .iter()
+            .filter(|certificate| {
+                // Votes bound to an epoch only count for the epoch of our registry.
+                if culprits.contains(&certificate.issuer.pid)
+                    || issuers.contains(&certificate.issuer.pid)
+                    || certificate.epoch.is_some_and(|epoch| epoch != conf.epoch)
+                {
+                    return false;
+                }
+                issuers.push(certificate.issuer.pid);
+                // The same certificates appear in many parties, only check each of them once.
+                *verified
+                    .entry(certificate.signature.clone())
+                    .or_insert_with(|| certificate.verify(&conf.children))
+            })
+            .collect();
+        // Weights are those of the registry, not those claimed by issuers.
+        let weight = |certificates: &[&Certificate]| -> usize {
+            certificates
+                .iter()
+                .map(|certificate| conf.weight_of(certificate.issuer.pid))
+                .sum()
+        };
+        debug!(target: "playexpert", "Received a party of {} certificates", party.len());
+        if weight(&party) < quorum {
+            // The party is too small to be a quorum, ignore.
+            debug!(target: "playexpert", "Party is too small to be a quorum");
+            continue;
+        }
+        // Let's check that the quorum *is* a quorum.
+        let (yeas, nays): (Vec<_>, Vec<_>) =
+            party.into_iter().partition(|certificate| certificate.value);
+        if weight(&yeas) >= quorum {
+            debug!(target: "playexpert", "got {} voters for yea that's a quorum", yeas.len());
+            return Some(true);
+        }
+        if weight(&nays) >= quorum {
+            debug!(target: "playexpert", "got {} voters for nay that's a quorum", nays.len());
+            return Some(false);
+        }
+    }
+    None
+}
+
+/// Whether `party` claims a quorum for either value, before checking it.
+fn claims_quorum(conf: &Conf, party: &[Certificate]) -> bool {
+    let weight = |value: bool| -> usize {
+        party
+            .iter()
+            .filter(|certificate| certificate.value == value)
+            .map(|certificate| conf.weight_of(certificate.issuer.pid))
+            .sum()
+    };
+    weight(true) >= conf.quorum() || weight(false) >= conf.quorum()
+}
+
+/// Log what went wrong with an interlocutor, if anything.
+fn report(entry: &Entry) {
+    match (&entry.response, &entry.error) {
+        (Some(agent::Response::Quorum(_)), _) | (Some(agent::Response::Aggregate(_)), _) => {}
+        (Some(other), _) => {
+            debug!(target: "playexpert", "Bad response from child {pid} on port {port}: {response:?}",
+                pid = entry.child.pid,
+                port = entry.child.socket,
+                response = other
+            );
+        }
+        (None, error) => {
+            debug!(target: "playexpert", "Could not communicate with child {pid} on port {port}: {error:?}, skipping child.",
+                pid = entry.child.pid,
+                port = entry.child.socket,
+                error = error
+            );
+        }
+    }
+}
+
+#[instrument(name = "playexpert", parent = None, skip_all, fields(trace = trace::new_id()))]
 pub async fn play(args: &PlayExpertArgs) -> Option<bool> {
+    trace::round(play_round(args)).await
+}
+
+async fn play_round(args: &PlayExpertArgs) -> Option<bool> {
+    let started = Instant::now();
     // Attempt to parse configuration.
-    let file = std::fs::File::open(&args.path).expect("Could not open file");
-    let conf: Conf = serde_json::from_reader(&file).expect("Could not read file");
+    let conf = Conf::load(&args.path)
+        .await
+        .expect("Could not read configuration");
+    // The agents we may talk to.
+    let mut candidates = conf.clone();
+    let message = if args.discover {
+        let discovered = gossip::discover(&conf.children).await;
+        // Peers may claim any weight or key, only trust the configuration with them.
+        candidates
+            .children
+            .retain(|child| discovered.contains(child));
+        if args.aggregate {
+            // The signers of an aggregate are relative to the agents we list.
+            agent::Message::AggregateCampaign(conf.children.clone())
+        } else if args.stream {
+            agent::Message::StreamCampaignPeers
+        } else {
+            agent::Message::CampaignPeers
+        }
+    } else if args.aggregate {
+        agent::Message::AggregateCampaign(conf.children.clone())
+    } else if args.stream {
+        agent::Message::StreamCampaign(conf.children.clone())
+    } else {
+        agent::Message::Campaign(conf.children.clone())
+    };
     let number_of_children = conf.children.len();
-    let (tcollect, mut rcollect) = tokio::sync::mpsc::channel::<Vec<agent::Certificate>>(32);
-
-    // Collect responses.
-    let collector = tokio::spawn(async move {
-        debug!(target: "playexpert", "Starting");
-        while let Some(party) = rcollect.recv().await {
-            debug!(target: "playexpert", "Received a party of {} certificates (from {} processes)", party.len(), number_of_children);
-            if party.len() < number_of_children / 2 {
-                // The party is too small to be a quorum, ignore.
-                debug!(target: "playexpert", "Party is too small to be a quorum");
-                continue;
+    let quorum = conf.quorum();
+    debug!(target: "playexpert", "Playing with {} agents at epoch {}, quorum is {}",
+        number_of_children,
+        conf.epoch,
+        quorum
+    );
+    let interlocutors = pick_interlocutors(args, &candidates);
+    let (entries, result) = if args.stream && !args.aggregate {
+        stream_round(&conf, message, interlocutors, started).await
+    } else {
+        let entries = call_round(message, interlocutors, started).await;
+        let result = decide(&conf, &entries);
+        (entries, result)
+    };
+    match result {
+        Some(true) => debug!(target: "playexpert", "The value was 'true'"),
+        Some(false) => debug!(target: "playexpert", "The value was 'false'"),
+        None => debug!(target: "playexpert", "Not enough participants to determine value"),
+    };
+    for proof in accountability::find(certificates(&entries), &conf.children) {
+        warn!(target: "playexpert", "Agent {} has equivocated", proof.culprit().pid);
+        accountability::broadcast(conf.children.clone(), proof).await;
+    }
+    if let (Some(path), Some(decided)) = (&args.certificate, result) {
+        match DecisionCertificate::new(&conf, &entries, decided) {
+            Some(certificate) => {
+                if let Err(err) = certificate.save(path) {
+                    warn!(target: "playexpert", "Could not write certificate {:?}: {:?}", path, err);
+                }
             }
-            // Let's check that the quorum *is* a quorum.
-            // FIXME: This is where we should check that the messages haven't been forged
-            // and/or double-check with issuer.
-            let (yeas, nays): (Vec<_>, Vec<_>) =
-                party.into_iter().partition(|certificate| certificate.value);
-            if yeas.len() >= (number_of_children + 1) / 2 {
-                debug!(target: "playexpert", "got {} voters for yea that's a quorum", yeas.len());
-                return Some(true);
+            None => {
+                warn!(target: "playexpert", "Not enough votes among the registry to certify the decision")
             }
-            if nays.len() >= (number_of_children + 1) / 2 {
-                debug!(target: "playexpert", "got {} voters for nay that's a quorum", nays.len());
-                return Some(false);
+        }
+    }
+    if let (Some(path), Some(decided)) = (&args.reputation, result) {
+        // Every agent that has issued a certificate, once.
+        let mut votes: Vec<(Child, bool)> = vec![];
+        for entry in &entries {
+            if let Some(agent::Response::Quorum(ref party)) = entry.response {
+                for certificate in party {
+                    if certificate.verify(&conf.children)
+                        && !votes
+                            .iter()
+                            .any(|(child, _)| child.pid == certificate.issuer.pid)
+                    {
+                        votes.push((certificate.issuer.clone(), certificate.value));
+                    }
+                }
             }
         }
+        if let Err(err) = reputation::update(path, decided, &votes) {
+            warn!(target: "playexpert", "Could not update reputation {:?}: {:?}", path, err);
+        }
+    }
+    if let Some(ref path) = args.transcript {
+        let transcript = Transcript {
+            strategy: Strategy::PlayExpert,
+            trace: trace::current(),
+            conf,
+            quorum,
+            entries,
+            result,
+        };
+        if let Err(err) = transcript.save(path) {
+            warn!(target: "playexpert", "Could not write transcript {:?}: {:?}", path, err);
+        }
+    }
+    result
+}
+
+/// Pick the agents to talk to, enough of them that at least one is honest.
+fn pick_interlocutors(args: &PlayExpertArgs, conf: &Conf) -> Vec<Child> {
+    let number_of_interlocutors =
+        (conf.children.len() as f64 * (1.0 - args.liar_ratio)) as usize + 1;
+    let mut interlocutors = conf.children.clone();
+    interlocutors.shuffle(&mut rand::thread_rng());
+    if args.avoid_suspects {
+        if let Some(ref path) = args.reputation {
+            match Reputation::load(path) {
+                Ok(reputation) => {
+                    // Suspects last, so that they are only picked if we run out of other agents.
+                    interlocutors.sort_by_key(|child| reputation.is_suspect(child));
+                }
+                Err(err) => {
+                    warn!(target: "playexpert", "Could not read reputation {:?}: {:?}", path, err)
+                }
+            }
+        }
+    }
+    interlocutors.truncate(number_of_interlocutors);
+    interlocutors
+}
+
+/// Send `message` to each of `interlocutors`, return the entries in order of arrival.
+async fn call_round(
+    message: agent::Message,
+    interlocutors: Vec<Child>,
+    started: Instant,
+) -> Vec<Entry> {
+    let (tcollect, mut rcollect) = tokio::sync::mpsc::channel::<Entry>(32);
+
+    // Collect responses, in order of arrival.
+    let collector = trace::spawn(async move {
+        debug!(target: "playexpert", "Starting");
+        let mut entries = vec![];
+        while let Some(entry) = rcollect.recv().await {
+            entries.push(entry);
+        }
         debug!(target: "playexpert", "Done");
-        None
+        entries
     });
 
-    // Pick a number of agents and talk to them.
     let tasks: Vec<_> = {
         // Make sure that `tcollect` is fully dropped once all tasks are complete.
         let tcollect = tcollect;
-        let children = conf.children.clone();
-        let number_of_interlocutors =
-            (number_of_children as f64 * (1.0 - args.liar_ratio)) as usize + 1;
-        let interlocutors = conf
-            .children
-            .choose_multiple(&mut rand::thread_rng(), number_of_interlocutors);
-        interlocutors.cloned().map(|child| {
-            let children = children.clone();
-            let remote = agent::RemoteAgent::new(child.clone());
-            let mut tcollect = tcollect.clone();
-            tokio::spawn(async move {
-                match remote.call(&agent::Message::Campaign(children)).await {
-                    Ok(agent::Response::Quorum(party)) => {
-                        // Ignore errors: the collector may have finished already.
-                        let _ = tcollect.send(party).await;
-                    }
-                    Ok(other) => {
-                        debug!(target: "playexpert", "Bad response from child {pid} on port {port}: {response:?}",
-                            pid = child.pid,
-                            port = child.socket,
-                            response = other
-                        );
-                    }
-                    Err(error) => {
-                        debug!(target: "playexpert", "Could not communicate with child {pid} on port {port}: {error:?}, skipping child.",
-                            pid = child.pid,
-                            port = child.socket,
-                            error = error
-                        );
-                    }
-                }
+        interlocutors
+            .into_iter()
+            .map(|child| {
+                let message = message.clone();
+                let mut tcollect = tcollect.clone();
+                trace::spawn(async move {
+                    let entry = Entry::call(child, message, started).await;
+                    report(&entry);
+                    let _ = tcollect.send(entry).await;
+                })
             })
-        }).collect()
+            .collect()
     };
 
     for task in tasks.into_iter() {
         task.await.unwrap();
     }
-    let result = collector.await.unwrap();
-    match result {
-        Some(true) => debug!(target: "playexpert", "The value was 'true'"),
-        Some(false) => debug!(target: "playexpert", "The value was 'false'"),
-        None => debug!(target: "playexpert", "Not enough participants to determine value"),
-    };
-    result
+    collector.await.unwrap()
+}
+
+/// What happened to an interlocutor during a streaming round.
+enum Event {
+    Vote(usize, Certificate),
+    Done(usize, Box<Entry>),
+}
+
+/// Send a streaming `message` to each of `interlocutors`, decide as soon as
+/// the votes streamed by any of them form a quorum.
+///
+/// Return the entries in order of arrival, followed by the interlocutors
+/// that were still streaming when we decided, with their votes so far.
+async fn stream_round(
+    conf: &Conf,
+    message: agent::Message,
+    interlocutors: Vec<Child>,
+    started: Instant,
+) -> (Vec<Entry>, Option<bool>) {
+    let (tevents, mut revents) = tokio::sync::mpsc::unbounded_channel();
+    let sent_ms = started.elapsed().as_secs_f64() * 1000.;
+    for (index, child) in interlocutors.iter().cloned().enumerate() {
+        let message = message.clone();
+        let tevents = tevents.clone();
+        trace::spawn(async move {
+            let tvotes = tevents.clone();
+            let entry = Entry::stream(child, message, started, move |certificate| {
+                // We may have decided already.
+                let _ = tvotes.send(Event::Vote(index, certificate.clone()));
+            })
+            .await;
+            report(&entry);
+            let _ = tevents.send(Event::Done(index, Box::new(entry)));
+        });
+    }
+    drop(tevents);
+
+    let mut votes: Vec<Vec<Certificate>> = vec![vec![]; interlocutors.len()];
+    let mut streaming = vec![true; interlocutors.len()];
+    let mut entries = vec![];
+    let mut verified = HashMap::new();
+    let mut result = None;
+    while let Some(event) = revents.recv().await {
+        match event {
+            Event::Vote(index, certificate) => {
+                votes[index].push(certificate);
+                // Only check certificates once they may make a difference.
+                if !claims_quorum(conf, &votes[index]) {
+                    continue;
+                }
+                let parties: Vec<&[Certificate]> = votes.iter().map(Vec::as_slice).collect();
+                result = decide_among(conf, &parties, &mut verified);
+                if result.is_some() {
+                    break;
+                }
+            }
+            Event::Done(index, entry) => {
+                streaming[index] = false;
+                entries.push(*entry);
+            }
+        }
+    }
+    let received_ms = started.elapsed().as_secs_f64() * 1000.;
+    for (index, child) in interlocutors.into_iter().enumerate() {
+        if streaming[index] {
+            debug!(target: "playexpert", "Decided without waiting for child {}", child.pid);
+            entries.push(Entry {
+                child,
+                request: message.clone(),
+                response: Some(agent::Response::Quorum(std::mem::take(&mut votes[index]))),
+                error: None,
+                refused: None,
+                sent_ms,
+                received_ms,
+            });
+        }
+    }
+    (entries, result)
 }
@@ -0,0 +1,157 @@
+use std::path::PathBuf;
+
+use log::*;
+use rand::seq::SliceRandom;
+use tracing::instrument;
+
+use crate::agent;
+use crate::conf::*;
+use crate::stats::{self, Traffic};
+use crate::trace;
+
+pub struct PlaySampleArgs {
+    pub path: PathBuf,
+
+    /// An upper bound on the ratio of liars.
+    pub liar_ratio: f64,
+
+    /// Stop sampling once the probability that the majority value is
+    /// the original value exceeds this threshold.
+    pub confidence: f64,
+
+    /// The number of agents to sample before re-evaluating confidence.
+    pub batch: usize,
+}
+
+#[derive(Debug)]
+pub struct Outcome {
+    /// The value, if we have reached the requested confidence.
+    pub value: Option<bool>,
+
+    /// The probability that the majority value among samples is the original value.
+    pub confidence: f64,
+
+    /// The traffic generated by sampling.
+    pub traffic: Traffic,
+}
+
+/// `ln(C(n, k))`, or `None` if `k > n`.
+fn ln_binomial(n: usize, k: usize) -> Option<f64> {
+    if k > n {
+        return None;
+    }
+    Some((1..=k).map(|i| ((n - k + i) as f64 / i as f64).ln()).sum())
+}
+
+/// The probability that the original value is `true`, having sampled without
+/// replacement `yeas + nays` agents among `number_of_children`, of which
+/// exactly `number_of_liars` are liars, assuming both values are equally
+/// likely a priori.
+///
+/// Since the odds only get closer to even as the number of liars grows,
+/// using an upper bound on the number of liars yields a lower bound on
+/// confidence.
+pub fn probability_true(
+    number_of_children: usize,
+    number_of_liars: usize,
+    yeas: usize,
+    nays: usize,
+) -> f64 {
+    let honest = number_of_children - number_of_liars;
+    // Likelihoods, up to the common factor `C(n, yeas + nays)`.
+    let if_true =
+        ln_binomial(honest, yeas).and_then(|a| ln_binomial(number_of_liars, nays).map(|b| a + b));
+    let if_false =
+        ln_binomial(number_of_liars, yeas).and_then(|a| ln_binomial(honest, nays).map(|b| a + b));
+    match (if_true, if_false) {
+        (None, None) => 0.5,
+        (Some(_), None) => 1.,
+        (None, Some(_)) => 0.,
+        (Some(if_true), Some(if_false)) => 1. / (1. + (if_false - if_true).exp()),
+    }
+}
+
+/// Sample agents at random until we are confident enough about the original value.
+#[instrument(name = "playsample", parent = None, skip_all, fields(trace = trace::new_id()))]
+pub async fn play(args: &PlaySampleArgs) -> Outcome {
+    trace::round(play_round(args)).await
+}
+
+async fn play_round(args: &PlaySampleArgs) -> Outcome {
+    // Attempt to parse configuration.
+    let conf = Conf::load(&args.path)
+        .await
+        .expect("Could not read configuration");
+    let number_of_children = conf.children.len();
+    let number_of_liars = (number_of_children as f64 * args.liar_ratio) as usize;
+
+    let mut candidates = conf.children.clone();
+    candidates.shuffle(&mut rand::thread_rng());
+
+    let before = stats::traffic();
+    let mut yeas = 0;
+    let mut nays = 0;
+    let mut outcome = Outcome {
+        value: None,
+        confidence: 0.5,
+        traffic: Traffic::default(),
+    };
+    for batch in candidates.chunks(std::cmp::max(args.batch, 1)) {
+        let tasks: Vec<_> = batch
+            .iter()
+            .cloned()
+            .map(|child| {
+                trace::spawn(async move {
+                    let remote = agent::RemoteAgent::new(child.clone());
+                    match remote.call(&agent::Message::GetValue).await {
+                        Ok(agent::Response::Certificate(agent::Certificate { value, .. })) => Some(value),
+                        other => {
+                            debug!(target: "playsample", "Could not get value from child {pid} on port {port}: {other:?}",
+                                pid = child.pid,
+                                port = child.socket,
+                                other = other
+                            );
+                            None
+                        }
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            match task.await.unwrap() {
+                Some(true) => yeas += 1,
+                Some(false) => nays += 1,
+                None => {}
+            }
+        }
+        // If the samples contradict our bound, there are fewer liars than we thought.
+        let number_of_liars = std::cmp::min(
+            number_of_liars,
+            number_of_children - std::cmp::max(yeas, nays),
+        );
+        let probability = probability_true(number_of_children, number_of_liars, yeas, nays);
+        let (leader, confidence) = if probability >= 0.5 {
+            (true, probability)
+        } else {
+            (false, 1. - probability)
+        };
+        debug!(target: "playsample", "yeas {}, nays {}, confidence in {} is {}", yeas, nays, leader, confidence);
+        outcome = Outcome {
+            value: if confidence >= args.confidence {
+                Some(leader)
+            } else {
+                None
+            },
+            confidence,
+            traffic: stats::traffic().since(&before),
+        };
+        if outcome.value.is_some() {
+            break;
+        }
+    }
+    match outcome.value {
+        Some(value) => debug!(target: "playsample", "The value was '{}'", value),
+        None => debug!(target: "playsample", "Not enough participants to determine value"),
+    };
+    outcome
+}
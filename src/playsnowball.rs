@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+
+use log::*;
+use rand::seq::SliceRandom;
+use tracing::instrument;
+
+use crate::agent;
+use crate::conf::*;
+use crate::trace;
+
+pub struct PlaySnowballArgs {
+    pub path: PathBuf,
+
+    /// The number of decided agents to ask for their preference.
+    pub ask: usize,
+}
+
+/// Ask a few agents for the value they have converged upon, see module `snowball`.
+///
+/// Agents that haven't decided yet are skipped.
+#[instrument(name = "playsnowball", parent = None, skip_all, fields(trace = trace::new_id()))]
+pub async fn play(args: &PlaySnowballArgs) -> Option<bool> {
+    trace::round(play_round(args)).await
+}
+
+async fn play_round(args: &PlaySnowballArgs) -> Option<bool> {
+    // Attempt to parse configuration.
+    let conf = Conf::load(&args.path)
+        .await
+        .expect("Could not read configuration");
+
+    let mut interlocutors = conf.children.clone();
+    interlocutors.shuffle(&mut rand::thread_rng());
+
+    let mut yeas = 0;
+    let mut nays = 0;
+    for child in interlocutors {
+        if yeas + nays >= args.ask {
+            break;
+        }
+        let remote = agent::RemoteAgent::new(child.clone());
+        match remote.call(&agent::Message::GetPreference).await {
+            Ok(agent::Response::Preference {
+                value,
+                decided: true,
+            }) => {
+                if value {
+                    yeas += 1;
+                } else {
+                    nays += 1;
+                }
+            }
+            other => {
+                debug!(target: "playsnowball", "No decision from child {pid} on port {port}: {other:?}",
+                    pid = child.pid,
+                    port = child.socket,
+                    other = other
+                );
+            }
+        }
+    }
+    debug!(target: "playsnowball", "yeas {}, nays {}", yeas, nays);
+    let result = if yeas > nays {
+        Some(true)
+    } else if nays > yeas {
+        Some(false)
+    } else {
+        None
+    };
+    match result {
+        Some(value) => debug!(target: "playsnowball", "The value was '{}'", value),
+        None => debug!(target: "playsnowball", "Not enough participants to determine value"),
+    };
+    result
+}
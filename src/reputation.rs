@@ -0,0 +1,131 @@
+//! Reputation of agents, as observed by players across rounds.
+//!
+//! Once a round is decided, the agents that vouched for the other value
+//! are most likely liars. The store remembers, for each agent, how often
+//! it agreed and disagreed with decided outcomes.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::conf::Child;
+use crate::util;
+
+/// What we know of an agent.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Record {
+    pub child: Child,
+
+    /// The number of decided rounds in which the agent vouched for the decided value.
+    pub agreed: usize,
+
+    /// The number of decided rounds in which the agent vouched for the other value.
+    pub disagreed: usize,
+}
+impl Record {
+    /// An estimate of the probability that the agent lies, in ]0, 1[.
+    ///
+    /// An agent we know nothing about gets 0.5.
+    pub fn suspicion(&self) -> f64 {
+        (self.disagreed as f64 + 1.) / ((self.agreed + self.disagreed) as f64 + 2.)
+    }
+
+    pub fn is_suspect(&self) -> bool {
+        self.suspicion() > 0.5
+    }
+}
+
+/// A file-backed reputation store, indexed by pid.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Reputation {
+    pub agents: BTreeMap<u32, Record>,
+}
+impl Reputation {
+    /// Load the store at `path`, or an empty store if there is no such file.
+    pub fn load(path: &Path) -> Result<Self, std::io::Error> {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err),
+        };
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    /// Write the reputation to `path`, atomically, see `util::write_atomic`.
+    pub fn save(&self, path: &Path) -> Result<(), std::io::Error> {
+        util::write_atomic(path, &serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Record the `votes` of a round that was decided as `decided`.
+    pub fn record<'a>(
+        &mut self,
+        decided: bool,
+        votes: impl IntoIterator<Item = (&'a Child, bool)>,
+    ) {
+        for (child, value) in votes {
+            let record = self.agents.entry(child.pid).or_insert_with(|| Record {
+                child: child.clone(),
+                agreed: 0,
+                disagreed: 0,
+            });
+            // The agent may have been respawned on another port.
+            record.child = child.clone();
+            if value == decided {
+                record.agreed += 1;
+            } else {
+                record.disagreed += 1;
+            }
+        }
+    }
+
+    pub fn suspicion(&self, child: &Child) -> f64 {
+        self.agents
+            .get(&child.pid)
+            .map(Record::suspicion)
+            .unwrap_or(0.5)
+    }
+
+    pub fn is_suspect(&self, child: &Child) -> bool {
+        self.suspicion(child) > 0.5
+    }
+
+    /// The suspects, most suspicious first.
+    pub fn suspects(&self) -> Vec<&Record> {
+        let mut suspects: Vec<_> = self
+            .agents
+            .values()
+            .filter(|record| record.is_suspect())
+            .collect();
+        suspects.sort_by(|a, b| b.suspicion().partial_cmp(&a.suspicion()).unwrap());
+        suspects
+    }
+}
+
+/// Record the `votes` of a round decided as `decided` in the store at `path`.
+pub fn update(path: &Path, decided: bool, votes: &[(Child, bool)]) -> Result<(), std::io::Error> {
+    let mut reputation = Reputation::load(path)?;
+    reputation.record(decided, votes.iter().map(|(child, value)| (child, *value)));
+    reputation.save(path)
+}
+
+/// Implementation of command `suspects`.
+pub fn suspects(path: &Path) -> Result<(), String> {
+    let reputation =
+        Reputation::load(path).map_err(|err| format!("Could not read reputation: {}", err))?;
+    println!(
+        "{:>8} {:>6} {:>8} {:>10} {:>10}",
+        "pid", "port", "agreed", "disagreed", "suspicion"
+    );
+    for record in reputation.suspects() {
+        println!(
+            "{:>8} {:>6} {:>8} {:>10} {:>10.3}",
+            record.child.pid,
+            record.child.socket,
+            record.agreed,
+            record.disagreed,
+            record.suspicion()
+        );
+    }
+    Ok(())
+}
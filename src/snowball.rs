@@ -0,0 +1,167 @@
+//! Metastable consensus, after the Snowball protocol.
+//!
+//! Each agent repeatedly asks a small random sample of its peers for their
+//! preference. Whenever a supermajority of the sample agrees on a value, the
+//! agent's confidence in that value increases and the agent adopts the value
+//! it has the most confidence in. Once the same value has won enough
+//! consecutive samples, the agent considers it decided and stops sampling.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::*;
+use rand::seq::SliceRandom;
+
+use crate::agent::{Message, RemoteAgent, Response};
+use crate::gossip::View;
+use crate::trace;
+
+#[derive(Clone)]
+pub struct SnowballArgs {
+    /// The number of peers to sample at each step.
+    pub sample: usize,
+
+    /// The number of peers in a sample that must agree for the sample to count.
+    pub alpha: usize,
+
+    /// The number of consecutive successful samples needed to decide.
+    pub beta: usize,
+
+    /// Delay between two samples.
+    pub period: Duration,
+
+    /// If `true`, never change preference. This models liars that don't
+    /// play along with consensus.
+    pub stubborn: bool,
+}
+impl SnowballArgs {
+    /// Check that a sample can only be conclusive for one value, that a
+    /// full sample can be conclusive and that we need at least one.
+    pub fn check(&self) -> Result<(), String> {
+        if self.alpha <= self.sample / 2 || self.alpha > self.sample {
+            return Err(format!(
+                "Alpha must be more than half the sample and at most the sample, got alpha {} for sample {}",
+                self.alpha, self.sample
+            ));
+        }
+        if self.beta == 0 {
+            return Err("Beta must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+struct Preference {
+    value: bool,
+    decided: bool,
+
+    /// The number of successful samples for `false` and `true`.
+    confidence: [usize; 2],
+
+    /// The value that has won the latest successful samples.
+    last: bool,
+
+    /// The number of consecutive successful samples for `last`.
+    count: usize,
+}
+
+/// The preference of an agent, shared between its connections.
+#[derive(Clone)]
+pub struct State {
+    preference: Arc<Mutex<Preference>>,
+}
+impl State {
+    pub fn new(value: bool) -> Self {
+        State {
+            preference: Arc::new(Mutex::new(Preference {
+                value,
+                decided: false,
+                confidence: [0, 0],
+                last: value,
+                count: 0,
+            })),
+        }
+    }
+
+    /// The current preference and whether it is decided.
+    pub fn preference(&self) -> (bool, bool) {
+        let preference = self.preference.lock().unwrap();
+        (preference.value, preference.decided)
+    }
+
+    /// Take into account a sample of `yeas + nays` preferences.
+    fn update(&self, yeas: usize, nays: usize, args: &SnowballArgs) {
+        let mut preference = self.preference.lock().unwrap();
+        let winner = if yeas >= args.alpha {
+            true
+        } else if nays >= args.alpha {
+            false
+        } else {
+            // Inconclusive sample.
+            preference.count = 0;
+            return;
+        };
+        preference.confidence[winner as usize] += 1;
+        if preference.confidence[winner as usize] > preference.confidence[preference.value as usize]
+        {
+            preference.value = winner;
+        }
+        if winner != preference.last {
+            preference.last = winner;
+            preference.count = 1;
+        } else {
+            preference.count += 1;
+        }
+        if preference.count >= args.beta {
+            preference.decided = true;
+        }
+    }
+}
+
+/// Sample peers from `view` until we have decided on a value.
+pub async fn snowball(state: State, view: View, args: SnowballArgs) {
+    if args.stubborn {
+        // Pretend that we have decided from the start.
+        state.preference.lock().unwrap().decided = true;
+        return;
+    }
+    let mut interval = tokio::time::interval(args.period);
+    while !state.preference().1 {
+        interval.tick().await;
+        let peers: Vec<_> = view
+            .peers()
+            .into_iter()
+            .filter(|peer| peer != view.me())
+            .collect();
+        if peers.len() < args.sample {
+            debug!(target: "snowball", "{} Not enough peers yet", view.me().pid);
+            continue;
+        }
+        // Each sample is a round of its own.
+        let step = Some(trace::new_id());
+        let tasks: Vec<_> = peers
+            .choose_multiple(&mut rand::thread_rng(), args.sample)
+            .cloned()
+            .map(|peer| {
+                tokio::spawn(trace::scope(step, async move {
+                    let remote = RemoteAgent::new(peer);
+                    match remote.call(&Message::GetPreference).await {
+                        Ok(Response::Preference { value, .. }) => Some(value),
+                        _ => None,
+                    }
+                }))
+            })
+            .collect();
+        let mut yeas = 0;
+        let mut nays = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                Some(true) => yeas += 1,
+                Some(false) => nays += 1,
+                None => {}
+            }
+        }
+        state.update(yeas, nays, &args);
+    }
+    debug!(target: "snowball", "{} Decided {}", view.me().pid, state.preference().0);
+}
@@ -1,24 +1,92 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use log::*;
 
+use crate::admin;
 use crate::conf::*;
-use crate::util;
+use crate::gossip;
+use crate::tls::{Issuer, TlsArgs};
+
+/// How to distribute voting weights among agents.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Weights {
+    /// Every agent has weight 1.
+    Uniform,
+
+    /// Each agent has a weight picked uniformly in [1, 10].
+    Random,
+
+    /// The agent of rank `r` (in random order) has weight `n / r`, so
+    /// that a few agents hold much of the weight.
+    Zipf,
+}
+impl Weights {
+    pub const ALL: [Weights; 3] = [Weights::Uniform, Weights::Random, Weights::Zipf];
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Weights::Uniform => "uniform",
+            Weights::Random => "random",
+            Weights::Zipf => "zipf",
+        }
+    }
+}
+impl FromStr for Weights {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Weights::ALL
+            .iter()
+            .find(|weights| weights.name() == s)
+            .cloned()
+            .ok_or_else(|| format!("Unknown weights {}", s))
+    }
+}
 
 pub struct StartArgs {
     pub exe: PathBuf,
     pub value: bool,
     pub num_agents: usize,
     pub liar_ratio: f64,
+    pub weights: Weights,
+
+    /// If specified, generate a self-signed certificate per agent in this
+    /// directory, agents only speak TLS, see module `tls`.
+    pub tls: Option<PathBuf>,
+
+    /// If specified, generate an admin token and store it in this private
+    /// file, agents require it on administrative messages, see module `admin`.
+    pub admin_token: Option<PathBuf>,
+
+    /// If `true`, agents discover their peers by gossip, see module `gossip`.
+    pub gossip: bool,
+
+    /// If `true`, agents also converge on a value with their peers, see
+    /// module `snowball`, and liars stubbornly stick to theirs.
+    ///
+    /// Requires `gossip`.
+    pub snowball: bool,
+}
+impl Default for StartArgs {
+    fn default() -> Self {
+        StartArgs {
+            exe: PathBuf::from("liarslie"),
+            value: true,
+            num_agents: 10,
+            liar_ratio: 0.1,
+            weights: Weights::Uniform,
+            tls: None,
+            admin_token: None,
+            gossip: false,
+            snowball: false,
+        }
+    }
 }
 
-/// Implementation of command `start`.
-///
-/// Start `args.num_agents` processes with `args.liar_ratio` liars.
-pub async fn start(args: &StartArgs) -> (Conf, Vec<tokio::process::Child>) {
+/// Prepare the values to distribute among `args.num_agents` agents,
+/// including `args.liar_ratio` liars, in a random order.
+pub fn distribute_values(args: &StartArgs) -> Vec<bool> {
     use crate::rand::prelude::SliceRandom;
-    use std::io::Write;
-    use tokio::io::{AsyncBufReadExt, BufReader};
     let num_liars = ((args.num_agents as f64) * args.liar_ratio) as usize;
     debug!(target: "start", "Preparing {} agents including {} liars",
         args.num_agents,
@@ -26,62 +94,216 @@ pub async fn start(args: &StartArgs) -> (Conf, Vec<tokio::process::Child>) {
 
     // Initialize the values we're about to distribute among agents.
     // Initially, everybody is a reliable.
-    let mut values = Vec::with_capacity(args.num_agents);
-    for _ in 0..args.num_agents {
-        values.push(args.value);
-    }
+    let mut values = vec![args.value; args.num_agents];
     // Introduce exactly `num_liars` liars.
-    for i in 0..num_liars {
-        values[i] = !args.value;
+    for value in values.iter_mut().take(num_liars) {
+        *value = !args.value;
     }
     values.shuffle(&mut rand::thread_rng());
+    values
+}
 
-    // Spawn agents.
-    let mut processes = Vec::with_capacity(args.num_agents);
-    for v in values {
-        let mut cmd = tokio::process::Command::new(&args.exe);
-        cmd.arg("agent")
-            .arg("--value")
-            .arg(if v { "true" } else { "false" })
-            .stdout(std::process::Stdio::piped());
-
-        // We may need several attempts to spawn processes, if the machine is a bit stressed.
-        let child = cmd.spawn().expect("Could not spawn process");
-        processes.push(child);
+/// Prepare the weights to distribute among `args.num_agents` agents, in a random order.
+pub fn distribute_weights(args: &StartArgs) -> Vec<usize> {
+    use crate::rand::prelude::SliceRandom;
+    use crate::rand::Rng;
+    let mut rng = rand::thread_rng();
+    let mut weights: Vec<usize> = match args.weights {
+        Weights::Uniform => vec![1; args.num_agents],
+        Weights::Random => (0..args.num_agents).map(|_| rng.gen_range(1, 11)).collect(),
+        Weights::Zipf => (1..=args.num_agents)
+            .map(|rank| std::cmp::max(1, args.num_agents / rank))
+            .collect(),
+    };
+    weights.shuffle(&mut rng);
+    weights
+}
+
+/// How `spawn_agent` sets up an agent, whatever its value.
+pub struct SpawnArgs<'a> {
+    /// If `true`, the agent discovers its peers by gossip, starting with
+    /// the agents `bootstrap`.
+    pub gossip: bool,
+
+    /// If `true`, the agent converges on a value with the peers it has
+    /// discovered, see `StartArgs::snowball`.
+    pub snowball: bool,
+
+    /// The agents to contact first to discover the fleet, see `bootstrap`.
+    pub bootstrap: &'a [Child],
+
+    /// If specified, the agent only speaks TLS.
+    pub tls: Option<&'a TlsArgs>,
+
+    /// If specified, the agent requires the token stored in this file on
+    /// administrative messages.
+    pub admin_token: Option<&'a Path>,
+
+    /// The registry of the fleet, see `AgentArgs::registry`.
+    pub registry: &'a Path,
+}
+impl Default for SpawnArgs<'_> {
+    fn default() -> Self {
+        SpawnArgs {
+            gossip: false,
+            snowball: false,
+            bootstrap: &[],
+            tls: None,
+            admin_token: None,
+            registry: Path::new("agents.conf"),
+        }
+    }
+}
+
+/// Spawn a single agent process carrying `value`, wait until it has
+/// printed its socket.
+///
+/// If `args.gossip`, the agent discovers its peers by gossip, starting
+/// with the agents `args.bootstrap`. If `args.snowball`, it also converges
+/// with them on a value, unless it is a `liar`, in which case it sticks to
+/// `value`.
+pub async fn spawn_agent(
+    exe: &Path,
+    value: bool,
+    liar: bool,
+    stderr: std::process::Stdio,
+    args: &SpawnArgs<'_>,
+) -> Result<(tokio::process::Child, Child), std::io::Error> {
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    let tls = args.tls;
+    let mut cmd = tokio::process::Command::new(exe);
+    cmd.arg("agent")
+        .arg("--value")
+        .arg(if value { "true" } else { "false" })
+        .arg("--registry")
+        .arg(args.registry)
+        .stdout(std::process::Stdio::piped())
+        .stderr(stderr);
+    if args.gossip {
+        cmd.arg("--gossip");
+        for agent in args.bootstrap {
+            cmd.arg("--bootstrap").arg(gossip::format_bootstrap(agent));
+        }
+    }
+    if args.snowball {
+        cmd.arg("--snowball");
+        if liar {
+            cmd.arg("--stubborn");
+        }
+    }
+    if let Some(tls) = tls {
+        cmd.arg("--tls-cert")
+            .arg(&tls.cert)
+            .arg("--tls-key")
+            .arg(&tls.key);
+    }
+    if let Some(path) = args.admin_token {
+        cmd.arg("--admin-token").arg(path);
+    }
+    let mut proc = cmd.spawn()?;
+
+    let stdout = proc
+        .stdout
+        .as_mut()
+        .expect("Could not access child process stdout");
+    let mut reader = BufReader::new(stdout);
+    let mut received = String::new();
+    reader.read_line(&mut received).await?;
+    let socket = received.trim_end().parse::<u16>().map_err(|err| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Did not receive a socket: {:?}", err),
+        )
+    })?;
+    // The registry publishes the key that signs the votes of the agent, see module `aggregate`,
+    // and the key that signs its certificates, see module `accountability`.
+    received.clear();
+    reader.read_line(&mut received).await?;
+    let key = received.trim_end().to_string();
+    received.clear();
+    reader.read_line(&mut received).await?;
+    let child = Child {
+        pid: proc.id(),
+        socket,
+        weight: 1,
+        fingerprint: tls.map(TlsArgs::fingerprint).transpose()?,
+        key: Some(key),
+        signing_key: Some(received.trim_end().to_string()),
+    };
+    Ok((proc, child))
+}
+
+/// Pick the agents a new agent should contact first to discover the fleet.
+pub fn bootstrap(children: &[Child]) -> Vec<Child> {
+    use crate::rand::prelude::SliceRandom;
+    children
+        .choose_multiple(&mut rand::thread_rng(), 2)
+        .cloned()
+        .collect()
+}
+
+/// Implementation of command `start`.
+///
+/// Start `args.num_agents` processes with `args.liar_ratio` liars.
+pub async fn start(args: &StartArgs) -> (Conf, Vec<tokio::process::Child>) {
+    let values = distribute_values(args);
+    let weights = distribute_weights(args);
+    let num_liars = values.iter().filter(|v| **v != args.value).count();
+    let liar_weight: usize = values
+        .iter()
+        .zip(weights.iter())
+        .filter(|(v, _)| **v != args.value)
+        .map(|(_, weight)| weight)
+        .sum();
+
+    let issuer = args
+        .tls
+        .as_ref()
+        .map(|dir| Issuer::create(dir).expect("Could not create certificate directory"));
+
+    if let Some(ref path) = args.admin_token {
+        admin::generate(path).expect("Could not write admin token");
     }
 
-    // Wait to know their socket. We're in no hurry here, so let's do it sequentially.
+    // Spawn agents. We're in no hurry here, so let's do it sequentially.
+    let mut processes = Vec::with_capacity(args.num_agents);
     let mut children = Vec::with_capacity(args.num_agents);
-    for proc in &mut processes {
-        let stdout = proc
-            .stdout
-            .as_mut()
-            .expect("Could not access child process stdout");
-        let mut reader = BufReader::new(stdout);
-        let mut received = String::new();
-        reader
-            .read_line(&mut received)
-            .await
-            .expect("Could not communicate with child process");
-        let socket = received[0..received.len() - 1]
-            .parse::<u16>()
-            .expect("Did not receive a socket");
-        children.push(Child {
-            pid: proc.id(),
-            socket,
-        });
+    for (v, weight) in values.into_iter().zip(weights) {
+        let bootstrap = bootstrap(&children);
+        let tls = issuer
+            .as_ref()
+            .map(|issuer| issuer.issue().expect("Could not issue certificate"));
+        let (proc, mut child) = spawn_agent(
+            &args.exe,
+            v,
+            v != args.value,
+            std::process::Stdio::inherit(),
+            &SpawnArgs {
+                gossip: args.gossip,
+                snowball: args.snowball,
+                bootstrap: &bootstrap,
+                tls: tls.as_ref(),
+                admin_token: args.admin_token.as_deref(),
+                registry: Path::new("agents.conf"),
+            },
+        )
+        .await
+        .expect("Could not spawn agent");
+        child.weight = weight;
+        processes.push(proc);
+        children.push(child);
     }
 
+    // Write `agents.conf`
+    let config = Conf { epoch: 0, children };
     debug!(target: "start",
-        "Value is {}, spawned {} processes, {} of which are lying.\n{:?}",
-        args.value, args.num_agents, num_liars, children
+        "Value is {}, spawned {} processes, {} of which are lying, with weight {} out of {}.\n{:?}",
+        args.value, args.num_agents, num_liars, liar_weight, config.weight(), config.children
     );
 
-    // Write `agents.conf`
-    let config = Conf { children };
-    let serialized = serde_json::to_string_pretty(&config).unwrap();
-    let mut file = std::fs::File::create("agents.conf").expect("Cannot create agents.conf");
-    write!(file, "{}", serialized).expect("Cannot write agents.conf");
+    config
+        .save(Path::new("agents.conf"))
+        .expect("Cannot write agents.conf");
 
     debug!(target: "start", "Ready");
 
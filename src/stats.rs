@@ -0,0 +1,146 @@
+//! Traffic accounting for this process.
+//!
+//! Calls to remote agents are recorded per message type and per peer,
+//! requests served by the agent running in this process (if any) per
+//! message type and, if they come from another agent, per peer. Agents
+//! identify themselves by their port in `Request::from`, which nobody
+//! checks: this is only good for accounting.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Mutex;
+
+use serde_derive::{Deserialize, Serialize};
+
+static STATS: Mutex<Stats> = Mutex::new(Stats {
+    outgoing: Ledger::new(),
+    incoming: Ledger::new(),
+});
+
+/// The port of the agent running in this process, 0 if none, see `identify`.
+static PORT: AtomicU16 = AtomicU16::new(0);
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Traffic {
+    pub messages: usize,
+    pub bytes_sent: usize,
+    pub bytes_received: usize,
+
+    /// The number of connections opened or accepted.
+    pub connections: usize,
+}
+impl Traffic {
+    const ZERO: Traffic = Traffic {
+        messages: 0,
+        bytes_sent: 0,
+        bytes_received: 0,
+        connections: 0,
+    };
+
+    /// The traffic since `earlier`.
+    ///
+    /// Saturates, in case `earlier` was recorded by an agent that has since been replaced.
+    pub fn since(&self, earlier: &Traffic) -> Traffic {
+        Traffic {
+            messages: self.messages.saturating_sub(earlier.messages),
+            bytes_sent: self.bytes_sent.saturating_sub(earlier.bytes_sent),
+            bytes_received: self.bytes_received.saturating_sub(earlier.bytes_received),
+            connections: self.connections.saturating_sub(earlier.connections),
+        }
+    }
+
+    pub fn add(&mut self, other: &Traffic) {
+        self.messages += other.messages;
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+        self.connections += other.connections;
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Ledger {
+    pub total: Traffic,
+
+    /// Traffic per message type, e.g. `"GetValue"`.
+    pub by_message: BTreeMap<String, Traffic>,
+
+    /// Traffic per peer, by port.
+    pub by_peer: BTreeMap<u16, Traffic>,
+}
+impl Ledger {
+    const fn new() -> Self {
+        Ledger {
+            total: Traffic::ZERO,
+            by_message: BTreeMap::new(),
+            by_peer: BTreeMap::new(),
+        }
+    }
+
+    fn record(&mut self, message: Option<&str>, peer: Option<u16>, traffic: &Traffic) {
+        self.total.add(traffic);
+        if let Some(message) = message {
+            self.by_message
+                .entry(message.to_string())
+                .or_default()
+                .add(traffic);
+        }
+        if let Some(peer) = peer {
+            self.by_peer.entry(peer).or_default().add(traffic);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Stats {
+    /// Calls to remote agents.
+    pub outgoing: Ledger,
+
+    /// Requests served by the agent.
+    pub incoming: Ledger,
+}
+
+/// All the traffic recorded by this process so far.
+pub fn stats() -> Stats {
+    STATS.lock().unwrap().clone()
+}
+
+/// The traffic generated by calls to remote agents so far.
+pub fn traffic() -> Traffic {
+    STATS.lock().unwrap().outgoing.total
+}
+
+/// Record traffic to remote agent listening on `peer`.
+///
+/// Connections are not specific to a message type, so `message` may be `None`.
+pub fn outgoing(message: Option<&str>, peer: u16, traffic: Traffic) {
+    STATS
+        .lock()
+        .unwrap()
+        .outgoing
+        .record(message, Some(peer), &traffic);
+}
+
+/// Record traffic served by the agent, to the agent listening on `peer`, if known.
+///
+/// Connections are accepted before we know the message type or the peer,
+/// so `message` and `peer` may be `None`.
+pub fn incoming(message: Option<&str>, peer: Option<u16>, traffic: Traffic) {
+    STATS
+        .lock()
+        .unwrap()
+        .incoming
+        .record(message, peer, &traffic);
+}
+
+/// Tell the agents we call that the agent running in this process listens on `port`.
+pub fn identify(port: u16) {
+    PORT.store(port, Ordering::SeqCst);
+}
+
+/// The port of the agent running in this process, if any, see `identify`.
+pub fn port() -> Option<u16> {
+    match PORT.load(Ordering::SeqCst) {
+        0 => None,
+        port => Some(port),
+    }
+}
@@ -0,0 +1,134 @@
+//! Introspection of agents, for operators.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::agent::{Message, RemoteAgent, Response};
+use crate::conf::Conf;
+use crate::metrics;
+use crate::stats;
+
+/// How long `status` waits for each agent.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How an agent behaves, see `AgentArgs`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Strategy {
+    Honest,
+
+    /// The agent doesn't listen to its peers in metastable consensus, see module `snowball`.
+    Stubborn,
+
+    /// The agent campaigns for the opposite of its value, see module `accountability`.
+    Equivocating,
+}
+impl Strategy {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Strategy::Honest => "honest",
+            Strategy::Stubborn => "stubborn",
+            Strategy::Equivocating => "equivocating",
+        }
+    }
+}
+
+/// What an agent tells about itself, see `Message::Status`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Status {
+    pub pid: u32,
+    pub uptime_ms: u64,
+
+    /// The version of the agent, i.e. of this crate.
+    pub version: String,
+    pub value: bool,
+
+    /// How the agent behaves, if it accepts to tell, see `AgentArgs::debug_status`.
+    pub strategy: Option<Strategy>,
+
+    /// The epoch of the registry of the agent, if it could read it, see `conf::registry`.
+    #[serde(default)]
+    pub epoch: Option<u64>,
+
+    /// The number of connections being served.
+    pub connections: usize,
+
+    /// The number of requests served, by type of message.
+    pub requests: BTreeMap<String, usize>,
+}
+impl Status {
+    /// The status of the agent running in this process.
+    pub async fn current(value: bool, strategy: Option<Strategy>) -> Self {
+        Status {
+            pid: std::process::id(),
+            uptime_ms: metrics::uptime().as_millis() as u64,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            value,
+            strategy,
+            epoch: crate::conf::registry().await.map(|registry| registry.epoch),
+            connections: metrics::active_connections(),
+            requests: stats::stats()
+                .incoming
+                .by_message
+                .into_iter()
+                .map(|(message, traffic)| (message, traffic.messages))
+                .collect(),
+        }
+    }
+}
+
+/// Implementation of command `status`.
+///
+/// Print the status of each agent of the registry at `path`, return an
+/// error if some of them could not be reached.
+pub async fn status(path: &Path) -> Result<(), String> {
+    let conf = Conf::load(path)
+        .await
+        .map_err(|err| format!("Could not read registry: {}", err))?;
+    println!("epoch {}, {} agents", conf.epoch, conf.children.len());
+    println!(
+        "{:>8} {:>6} {:>10} {:>8} {:>6} {:>12} {:>6} {:>6} {:>9}  state",
+        "pid", "port", "uptime s", "version", "value", "strategy", "epoch", "conns", "requests"
+    );
+    let mut unreachable = 0;
+    for child in &conf.children {
+        let remote = RemoteAgent::new(child.clone());
+        let state = match tokio::time::timeout(TIMEOUT, remote.call(&Message::Status)).await {
+            Ok(Ok(Response::Status(status))) if status.pid == child.pid => {
+                println!(
+                    "{:>8} {:>6} {:>10.1} {:>8} {:>6} {:>12} {:>6} {:>6} {:>9}  ok",
+                    child.pid,
+                    child.socket,
+                    status.uptime_ms as f64 / 1000.,
+                    status.version,
+                    status.value,
+                    status
+                        .strategy
+                        .map(|strategy| strategy.name())
+                        .unwrap_or("-"),
+                    status
+                        .epoch
+                        .map_or_else(|| "-".to_string(), |epoch| epoch.to_string()),
+                    status.connections,
+                    status.requests.values().sum::<usize>()
+                );
+                continue;
+            }
+            Ok(Ok(Response::Status(status))) => format!("pid mismatch: {}", status.pid),
+            Ok(Ok(other)) => format!("unexpected response: {:?}", other),
+            Ok(Err(err)) => format!("{}", err),
+            Err(_) => "timeout".to_string(),
+        };
+        unreachable += 1;
+        println!(
+            "{:>8} {:>6} {:>10} {:>8} {:>6} {:>12} {:>6} {:>6} {:>9}  UNREACHABLE ({})",
+            child.pid, child.socket, "-", "-", "-", "-", "-", "-", "-", state
+        );
+    }
+    if unreachable > 0 {
+        return Err(format!("{} agents unreachable", unreachable));
+    }
+    Ok(())
+}
@@ -0,0 +1,915 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::*;
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::admin;
+use crate::agent::{Message, RemoteAgent, Response};
+use crate::conf::*;
+use crate::metrics;
+use crate::start::{self, StartArgs};
+use crate::tls::{Issuer, TlsArgs};
+use crate::util;
+
+/// How often the supervisor checks that guests are alive, see `Command::Join`.
+const HEALTH_PERIOD: Duration = Duration::from_secs(1);
+
+/// How long a guest may take to respond to a health check.
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The number of consecutive health checks a guest may fail before it is
+/// removed from the fleet.
+const MAX_HEALTH_FAILURES: usize = 3;
+
+/// The most agents a single `Command::AddAgents` may spawn, as the
+/// supervisor doesn't handle other commands in the meantime.
+pub const MAX_ADD_AGENTS: usize = 32;
+
+/// A command sent to the supervisor through its control socket.
+#[derive(Debug, Deserialize, Serialize)]
+pub enum Command {
+    /// Describe the fleet.
+    ///
+    /// Response is `Reply::Status(...)`.
+    Status,
+
+    /// Kill every agent, stop the supervisor.
+    Stop,
+
+    /// Kill and respawn the agent with the given pid.
+    Restart(u32),
+
+    /// Spawn a number of additional reliable agents, at most `MAX_ADD_AGENTS`.
+    AddAgents(usize),
+
+    /// Respawn agents as needed so that exactly this number of agents are liars.
+    SetLiars(usize),
+
+    /// Get the current membership of the fleet.
+    ///
+    /// Response is `Reply::Membership(...)`.
+    Membership,
+
+    /// Add an agent that was started independently to the fleet.
+    ///
+    /// The supervisor can't respawn such an agent, it removes it from the
+    /// fleet once it stops responding. Whatever weight the agent claims,
+    /// it joins with weight 1.
+    Join(Child),
+
+    /// Remove an agent from the fleet. If the agent was spawned by the
+    /// supervisor, it is killed and won't be respawned.
+    Leave(Child),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum Reply {
+    Ok,
+    Status(Vec<AgentStatus>),
+    Membership(Conf),
+    Error(String),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AgentStatus {
+    /// The slot of the agent. Slots of agents that have left are reused.
+    pub slot: usize,
+    pub child: Child,
+    pub value: bool,
+
+    /// The number of times the agent in this slot has been respawned.
+    pub restarts: usize,
+}
+
+/// Randomly kill agents, to check that clients tolerate churn.
+pub struct ChaosArgs {
+    /// Delay between two rounds of chaos.
+    pub period: Duration,
+
+    /// Probability for each agent to be killed during a round of chaos.
+    pub kill_probability: f64,
+}
+
+pub struct SuperviseArgs {
+    /// The registry, rewritten whenever an agent is respawned.
+    pub path: PathBuf,
+
+    /// If specified, listen to `Command`s on this unix socket.
+    pub control: Option<PathBuf>,
+
+    pub chaos: Option<ChaosArgs>,
+
+    /// If specified, serve the metrics of the whole fleet over HTTP on this local port.
+    pub metrics: Option<u16>,
+}
+impl Default for SuperviseArgs {
+    fn default() -> Self {
+        SuperviseArgs {
+            path: PathBuf::from("agents.conf"),
+            control: None,
+            chaos: None,
+            metrics: None,
+        }
+    }
+}
+
+/// An agent process monitored by the supervisor.
+struct Slot {
+    value: bool,
+    child: Child,
+    restarts: usize,
+
+    /// `false` once the agent has left the fleet.
+    member: bool,
+
+    /// Send a message to kill the process. Dropping it kills the process, too.
+    kill: Option<oneshot::Sender<()>>,
+}
+
+/// An agent that has joined the fleet on its own, see `Command::Join`.
+struct Guest {
+    child: Child,
+
+    /// The number of consecutive health checks it has failed.
+    failures: usize,
+}
+
+/// A supervisor, in charge of keeping a fleet of agents alive.
+pub struct Supervisor {
+    exe: PathBuf,
+
+    /// The value carried by reliable agents.
+    value: bool,
+    args: SuperviseArgs,
+    slots: Vec<Slot>,
+
+    /// Agents that have joined the fleet on their own.
+    guests: Vec<Guest>,
+
+    /// Results of health checks of guests, whether each of them has responded.
+    thealth: mpsc::Sender<Vec<(Child, bool)>>,
+    rhealth: mpsc::Receiver<Vec<(Child, bool)>>,
+
+    /// `true` while a health check is in progress.
+    checking: bool,
+
+    /// Incremented whenever the membership changes.
+    epoch: u64,
+
+    /// Notifications that the process in slot `usize` with pid `u32` has died.
+    texit: mpsc::Sender<(usize, u32)>,
+    rexit: mpsc::Receiver<(usize, u32)>,
+
+    /// Notifications that the process with pid `u32` in slot `usize` has been replaced.
+    trespawned: mpsc::Sender<(usize, u32, Spawned)>,
+    rrespawned: mpsc::Receiver<(usize, u32, Spawned)>,
+
+    /// The number of processes being respawned.
+    respawning: usize,
+
+    /// The number of processes that haven't been reported dead yet.
+    watched: usize,
+
+    /// If specified, issue a TLS certificate to each agent we spawn.
+    issuer: Option<Issuer>,
+
+    /// If specified, the file holding the admin token of the agents we spawn.
+    admin_token: Option<PathBuf>,
+
+    /// Whether the agents we spawn gossip, see `StartArgs::gossip`.
+    gossip: bool,
+
+    /// Whether the agents we spawn converge on a value, see `StartArgs::snowball`.
+    snowball: bool,
+}
+impl Supervisor {
+    /// Start `args.num_agents` processes with `args.liar_ratio` liars,
+    /// write the registry and take charge of the processes.
+    pub async fn start(args: &StartArgs, supervise: SuperviseArgs) -> Self {
+        let (texit, rexit) = mpsc::channel(32);
+        let (trespawned, rrespawned) = mpsc::channel(32);
+        let (thealth, rhealth) = mpsc::channel(1);
+        let mut supervisor = Supervisor {
+            exe: args.exe.clone(),
+            value: args.value,
+            args: supervise,
+            slots: Vec::with_capacity(args.num_agents),
+            guests: vec![],
+            thealth,
+            rhealth,
+            checking: false,
+            epoch: 0,
+            texit,
+            rexit,
+            trespawned,
+            rrespawned,
+            respawning: 0,
+            watched: 0,
+            issuer: args
+                .tls
+                .as_ref()
+                .map(|dir| Issuer::create(dir).expect("Could not create certificate directory")),
+            admin_token: args.admin_token.clone(),
+            gossip: args.gossip,
+            snowball: args.snowball,
+        };
+        if let Some(ref path) = args.admin_token {
+            admin::generate(path).expect("Could not write admin token");
+        }
+        let values = start::distribute_values(args);
+        let weights = start::distribute_weights(args);
+        for (value, weight) in values.into_iter().zip(weights) {
+            supervisor
+                .add(value, weight)
+                .await
+                .expect("Could not spawn agent");
+        }
+        supervisor.publish();
+        debug!(target: "supervisor", "Ready with {} agents", supervisor.slots.len());
+        supervisor
+    }
+
+    /// The current state of the registry.
+    pub fn conf(&self) -> Conf {
+        Conf {
+            epoch: self.epoch,
+            children: self
+                .slots
+                .iter()
+                .filter(|slot| slot.member)
+                .map(|slot| slot.child.clone())
+                .chain(self.guests.iter().map(|guest| guest.child.clone()))
+                .collect(),
+        }
+    }
+
+    /// Keep the fleet alive until `shutdown` is triggered or we receive
+    /// `Command::Stop`, then kill every agent.
+    pub async fn run(mut self, mut shutdown: oneshot::Receiver<()>) {
+        let mut chaos = self
+            .args
+            .chaos
+            .as_ref()
+            .map(|chaos| tokio::time::interval(chaos.period));
+        let mut health = tokio::time::interval(HEALTH_PERIOD);
+        let (tcommand, mut rcommand) = mpsc::channel(32);
+        // Dropping `_tstop` stops listening on the control socket.
+        let (_tstop, rstop) = oneshot::channel::<()>();
+        // Dropping `_tstop_metrics` closes the metrics endpoint.
+        let (_tstop_metrics, rstop_metrics) = oneshot::channel::<()>();
+        if let Some(port) = self.args.metrics {
+            match metrics::bind(port).await {
+                Ok(listener) => {
+                    let tcommand = tcommand.clone();
+                    tokio::spawn(async move {
+                        tokio::select! {
+                            _ = metrics::serve(listener, move || render(tcommand.clone())) => {},
+                            _ = rstop_metrics => {},
+                        }
+                    });
+                }
+                Err(err) => {
+                    error!(target: "supervisor", "Could not open metrics endpoint on port {}: {:?}", port, err)
+                }
+            }
+        }
+        if let Some(ref path) = self.args.control {
+            // Remove any socket left behind by a previous supervisor.
+            let _ = std::fs::remove_file(path);
+            match bind(path) {
+                Ok((listener, owner)) => {
+                    tokio::spawn(listen(listener, owner, tcommand, rstop));
+                }
+                Err(err) => {
+                    error!(target: "supervisor", "Could not open control socket {:?}: {:?}", path, err)
+                }
+            }
+        }
+        loop {
+            let tick = async {
+                match chaos {
+                    Some(ref mut interval) => interval.tick().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                Some((index, pid)) = self.rexit.recv() => {
+                    self.watched -= 1;
+                    self.respawn(index, pid)
+                }
+                Some((index, pid, spawned)) = self.rrespawned.recv() => {
+                    self.respawning -= 1;
+                    self.replace(index, pid, spawned)
+                }
+                Some((command, treply)) = rcommand.recv() => {
+                    debug!(target: "supervisor", "Received command {:?}", command);
+                    let stop = matches!(command, Command::Stop);
+                    let reply = self.handle(command).await;
+                    let _ = treply.send(reply);
+                    if stop {
+                        break;
+                    }
+                }
+                _ = tick => self.chaos(),
+                _ = health.tick() => self.check_guests(),
+                Some(results) = self.rhealth.recv() => {
+                    self.checking = false;
+                    self.prune_guests(results)
+                }
+                _ = &mut shutdown => break,
+            }
+        }
+        debug!(target: "supervisor", "Shutting down");
+        for slot in &mut self.slots {
+            if let Some(kill) = slot.kill.take() {
+                let _ = kill.send(());
+            }
+        }
+        while self.respawning > 0 {
+            match self.rrespawned.recv().await {
+                Some((index, _, Ok((proc, _)))) => {
+                    // Dropping the sender kills the process.
+                    drop(self.watch(index, proc));
+                }
+                Some((_, _, Err(_))) => {}
+                None => break,
+            }
+            self.respawning -= 1;
+        }
+        while self.watched > 0 {
+            if self.rexit.recv().await.is_none() {
+                break;
+            }
+            self.watched -= 1;
+        }
+        if let Some(ref path) = self.args.control {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    /// Handle `command`. The caller stops the supervisor after `Command::Stop`.
+    async fn handle(&mut self, command: Command) -> Reply {
+        match command {
+            Command::Status => Reply::Status(
+                self.slots
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, slot)| slot.member)
+                    .map(|(index, slot)| AgentStatus {
+                        slot: index,
+                        child: slot.child.clone(),
+                        value: slot.value,
+                        restarts: slot.restarts,
+                    })
+                    .collect(),
+            ),
+            Command::Stop => Reply::Ok,
+            Command::Restart(pid) => {
+                match self
+                    .slots
+                    .iter_mut()
+                    .find(|slot| slot.member && slot.child.pid == pid)
+                {
+                    None => Reply::Error(format!("No agent with pid {}", pid)),
+                    Some(slot) => {
+                        // The agent will be respawned once we receive news of its death.
+                        if let Some(kill) = slot.kill.take() {
+                            let _ = kill.send(());
+                        }
+                        Reply::Ok
+                    }
+                }
+            }
+            Command::AddAgents(num_agents) if num_agents > MAX_ADD_AGENTS => Reply::Error(format!(
+                "Cannot add more than {} agents at once",
+                MAX_ADD_AGENTS
+            )),
+            Command::AddAgents(num_agents) => {
+                let mut reply = Reply::Ok;
+                for added in 0..num_agents {
+                    if let Err(err) = self.add(self.value, 1).await {
+                        reply = Reply::Error(format!("Could only add {} agents: {}", added, err));
+                        break;
+                    }
+                }
+                self.publish();
+                reply
+            }
+            Command::Membership => Reply::Membership(self.conf()),
+            Command::Join(child) => {
+                if self.conf().children.contains(&child) {
+                    return Reply::Error(format!("Agent {} is already a member", child.pid));
+                }
+                info!(target: "supervisor", "Agent {} on port {} joins the fleet", child.pid, child.socket);
+                let child = Child { weight: 1, ..child };
+                self.guests.push(Guest { child, failures: 0 });
+                self.publish();
+                Reply::Ok
+            }
+            Command::Leave(child) => {
+                if let Some(index) = self.guests.iter().position(|guest| guest.child == child) {
+                    self.guests.remove(index);
+                } else if let Some(slot) = self
+                    .slots
+                    .iter_mut()
+                    .find(|slot| slot.member && slot.child == child)
+                {
+                    slot.member = false;
+                    if let Some(kill) = slot.kill.take() {
+                        let _ = kill.send(());
+                    }
+                } else {
+                    return Reply::Error(format!("Agent {} is not a member", child.pid));
+                }
+                info!(target: "supervisor", "Agent {} on port {} leaves the fleet", child.pid, child.socket);
+                self.publish();
+                Reply::Ok
+            }
+            Command::SetLiars(num_liars) => {
+                let num_agents = self.slots.iter().filter(|slot| slot.member).count();
+                if num_liars > num_agents {
+                    return Reply::Error(format!(
+                        "Cannot have {} liars among {} agents",
+                        num_liars, num_agents
+                    ));
+                }
+                let truth = self.value;
+                let liars = self
+                    .slots
+                    .iter()
+                    .filter(|slot| slot.member && slot.value != truth)
+                    .count();
+                // Pick the agents that need to change sides.
+                let (from, count) = if liars < num_liars {
+                    (truth, num_liars - liars)
+                } else {
+                    (!truth, liars - num_liars)
+                };
+                let mut candidates: Vec<_> = self
+                    .slots
+                    .iter_mut()
+                    .filter(|slot| slot.member && slot.value == from)
+                    .collect();
+                use rand::seq::SliceRandom;
+                candidates.shuffle(&mut rand::thread_rng());
+                for slot in candidates.into_iter().take(count) {
+                    // The agent will be respawned with its new value once we receive news of its death.
+                    slot.value = !from;
+                    if let Some(kill) = slot.kill.take() {
+                        let _ = kill.send(());
+                    }
+                }
+                Reply::Ok
+            }
+        }
+    }
+
+    /// Ask each guest whether it is alive, in the background, see `prune_guests`.
+    fn check_guests(&mut self) {
+        if self.checking || self.guests.is_empty() {
+            return;
+        }
+        self.checking = true;
+        let guests: Vec<_> = self
+            .guests
+            .iter()
+            .map(|guest| guest.child.clone())
+            .collect();
+        let mut thealth = self.thealth.clone();
+        tokio::spawn(async move {
+            let mut results = Vec::with_capacity(guests.len());
+            for guest in guests {
+                let remote = RemoteAgent::new(guest.clone());
+                let alive = matches!(
+                    tokio::time::timeout(HEALTH_TIMEOUT, remote.call(&Message::Status)).await,
+                    Ok(Ok(Response::Status(_)))
+                );
+                results.push((guest, alive));
+            }
+            let _ = thealth.send(results).await;
+        });
+    }
+
+    /// Remove the guests that have failed too many health checks in a row.
+    fn prune_guests(&mut self, results: Vec<(Child, bool)>) {
+        for (child, alive) in results {
+            // The guest may have left in the meantime.
+            if let Some(guest) = self.guests.iter_mut().find(|guest| guest.child == child) {
+                guest.failures = if alive { 0 } else { guest.failures + 1 };
+            }
+        }
+        let before = self.guests.len();
+        self.guests.retain(|guest| {
+            if guest.failures < MAX_HEALTH_FAILURES {
+                return true;
+            }
+            info!(target: "supervisor", "Agent {} on port {} doesn't respond, removing it from the fleet",
+                guest.child.pid,
+                guest.child.socket
+            );
+            false
+        });
+        if self.guests.len() != before {
+            self.publish();
+        }
+    }
+
+    /// Randomly kill agents.
+    fn chaos(&mut self) {
+        let probability = match self.args.chaos {
+            Some(ref chaos) => chaos.kill_probability,
+            None => return,
+        };
+        let mut rng = rand::thread_rng();
+        for slot in self.slots.iter_mut().filter(|slot| slot.member) {
+            if !rng.gen_bool(probability) {
+                continue;
+            }
+            if let Some(kill) = slot.kill.take() {
+                info!(target: "supervisor", "Chaos: killing agent {}", slot.child.pid);
+                let _ = kill.send(());
+            }
+        }
+    }
+
+    /// Spawn an agent with weight `weight` in the slot of an agent that
+    /// has left, if any, otherwise in a new slot.
+    async fn add(&mut self, value: bool, weight: usize) -> Result<(), std::io::Error> {
+        let (proc, mut child) = self.prepare(value)?.run().await?;
+        child.weight = weight;
+        let index = self
+            .slots
+            .iter()
+            .position(|slot| !slot.member)
+            .unwrap_or(self.slots.len());
+        // If the previous process of the slot is still around, `respawn`
+        // and `replace` tell it apart by its pid.
+        let kill = self.watch(index, proc);
+        let slot = Slot {
+            value,
+            child,
+            restarts: 0,
+            member: true,
+            kill: Some(kill),
+        };
+        if index == self.slots.len() {
+            self.slots.push(slot);
+        } else {
+            self.slots[index] = slot;
+        }
+        Ok(())
+    }
+
+    /// Start replacing the process with pid `pid` that has died in slot `index`.
+    ///
+    /// Spawning may take a while if we need to retry, so it happens in the
+    /// background, see `replace`.
+    fn respawn(&mut self, index: usize, pid: u32) {
+        if !self.slots[index].member || self.slots[index].child.pid != pid {
+            // We have already replaced this process or it has left the fleet.
+            return;
+        }
+        let spawn = match self.prepare(self.slots[index].value) {
+            Ok(spawn) => spawn,
+            Err(err) => {
+                error!(target: "supervisor", "Could not respawn agent {}: {:?}", pid, err);
+                return;
+            }
+        };
+        let mut trespawned = self.trespawned.clone();
+        self.respawning += 1;
+        tokio::spawn(async move {
+            let spawned = spawn.run().await;
+            let _ = trespawned.send((index, pid, spawned)).await;
+        });
+    }
+
+    /// Put the process `spawned` in slot `index`, in place of the process with pid `pid`.
+    fn replace(&mut self, index: usize, pid: u32, spawned: Spawned) {
+        let (proc, mut child) = match spawned {
+            Ok(spawned) => spawned,
+            Err(err) => {
+                error!(target: "supervisor", "Could not respawn agent {}: {:?}", pid, err);
+                return;
+            }
+        };
+        if !self.slots[index].member || self.slots[index].child.pid != pid {
+            // The agent has left the fleet in the meantime. Dropping the sender kills the process.
+            drop(self.watch(index, proc));
+            return;
+        }
+        // The new process takes over the weight of the old one.
+        child.weight = self.slots[index].child.weight;
+        info!(target: "supervisor", "Agent {} has died, replaced with agent {} on port {}",
+            pid,
+            child.pid,
+            child.socket
+        );
+        let kill = self.watch(index, proc);
+        let slot = &mut self.slots[index];
+        slot.child = child;
+        slot.restarts += 1;
+        slot.kill = Some(kill);
+        self.publish();
+    }
+
+    /// Prepare to spawn an agent carrying `value`.
+    fn prepare(&self, value: bool) -> Result<Spawn, std::io::Error> {
+        Ok(Spawn {
+            exe: self.exe.clone(),
+            value,
+            liar: value != self.value,
+            gossip: self.gossip,
+            snowball: self.snowball,
+            bootstrap: start::bootstrap(&self.conf().children),
+            tls: self.issuer.as_ref().map(Issuer::issue).transpose()?,
+            admin_token: self.admin_token.clone(),
+            registry: self.args.path.clone(),
+        })
+    }
+
+    /// Wait for the process in slot `index` to die, notify the supervisor.
+    ///
+    /// In the meantime, log whatever the process writes on stderr.
+    fn watch(&mut self, index: usize, mut proc: tokio::process::Child) -> oneshot::Sender<()> {
+        let (tkill, rkill) = oneshot::channel();
+        self.watched += 1;
+        let pid = proc.id();
+        if let Some(stderr) = proc.stderr.take() {
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    info!(target: "agent-stderr", "{} {}", pid, line);
+                }
+            });
+        }
+        let mut texit = self.texit.clone();
+        tokio::spawn(async move {
+            let status = tokio::select! {
+                status = &mut proc => status,
+                _ = rkill => {
+                    let _ = proc.kill();
+                    proc.await
+                }
+            };
+            debug!(target: "supervisor", "Agent {} exited with {:?}", pid, status);
+            // Ignore errors: the supervisor may have stopped already.
+            let _ = texit.send((index, pid)).await;
+        });
+        tkill
+    }
+
+    /// Start a new membership epoch, write the registry.
+    fn publish(&mut self) {
+        self.epoch += 1;
+        if let Err(err) = self.conf().save(&self.args.path) {
+            error!(target: "supervisor", "Could not write registry {:?}: {:?}", self.args.path, err);
+        }
+    }
+}
+
+/// A process spawned by `Spawn::run`.
+type Spawned = Result<(tokio::process::Child, Child), std::io::Error>;
+
+/// Everything needed to spawn an agent, without borrowing the supervisor.
+struct Spawn {
+    exe: PathBuf,
+    value: bool,
+    liar: bool,
+    gossip: bool,
+    snowball: bool,
+    bootstrap: Vec<Child>,
+    tls: Option<TlsArgs>,
+    admin_token: Option<PathBuf>,
+    registry: PathBuf,
+}
+impl Spawn {
+    async fn run(self) -> Spawned {
+        let args = start::SpawnArgs {
+            gossip: self.gossip,
+            snowball: self.snowball,
+            bootstrap: &self.bootstrap,
+            tls: self.tls.as_ref(),
+            admin_token: self.admin_token.as_deref(),
+            registry: &self.registry,
+        };
+        // We may need several attempts to spawn processes, if the machine is a bit stressed.
+        util::retry_future(|| {
+            start::spawn_agent(
+                &self.exe,
+                self.value,
+                self.liar,
+                std::process::Stdio::piped(),
+                &args,
+            )
+        })
+        .await
+    }
+}
+
+type CommandSender = mpsc::Sender<(Command, oneshot::Sender<Reply>)>;
+
+/// Open the control socket at `path`, only accessible to its owner, whose
+/// uid we return.
+///
+/// Commands can kill agents, so nobody else should be able to send them.
+fn bind(path: &Path) -> Result<(UnixListener, u32), std::io::Error> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    let owner = std::fs::metadata(path)?.uid();
+    Ok((listener, owner))
+}
+
+/// Accept connections from processes of user `owner` on the control socket
+/// until `stop` is triggered.
+///
+/// Checking the user of our peers also covers connections made before
+/// `bind` has restricted access to the socket.
+async fn listen(
+    mut listener: UnixListener,
+    owner: u32,
+    tcommand: CommandSender,
+    mut stop: oneshot::Receiver<()>,
+) {
+    loop {
+        let mut conn = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((conn, _)) => conn,
+                Err(err) => {
+                    warn!(target: "supervisor", "Could not accept connection {:?}", err);
+                    continue;
+                }
+            },
+            _ = &mut stop => return,
+        };
+        match conn.peer_cred() {
+            Ok(cred) if cred.uid == owner => {}
+            other => {
+                warn!(target: "supervisor", "Refusing control connection from {:?}", other);
+                continue;
+            }
+        }
+        let mut tcommand = tcommand.clone();
+        tokio::spawn(async move {
+            let mut reader = BufReader::new(&mut conn);
+            loop {
+                let mut line = String::new();
+                match reader.read_line(&mut line).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(_) => {}
+                }
+                let reply = match serde_json::from_str(&line) {
+                    Err(err) => Reply::Error(format!("Invalid command: {}", err)),
+                    Ok(command) => {
+                        let (treply, rreply) = oneshot::channel();
+                        if tcommand.send((command, treply)).await.is_err() {
+                            return;
+                        }
+                        match rreply.await {
+                            Ok(reply) => reply,
+                            Err(_) => return,
+                        }
+                    }
+                };
+                let mut serialized = serde_json::to_string(&reply).unwrap();
+                serialized.push('\n');
+                if reader
+                    .get_mut()
+                    .write_all(serialized.as_bytes())
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+/// Collect the metrics of every agent, labelled by pid, along with the
+/// health of the fleet.
+async fn render(mut tcommand: CommandSender) -> String {
+    let (treply, rreply) = oneshot::channel();
+    let agents = match tcommand.send((Command::Status, treply)).await {
+        Ok(()) => match rreply.await {
+            Ok(Reply::Status(agents)) => agents,
+            _ => vec![],
+        },
+        Err(_) => vec![],
+    };
+    let mut up = String::new();
+    let mut restarts = String::new();
+    let mut info = String::new();
+    let mut outputs = vec![];
+    for agent in &agents {
+        let pid = format!("{}", agent.child.pid);
+        let remote = RemoteAgent::new(agent.child.clone());
+        let alive = match remote.call(&Message::Metrics).await {
+            Ok(Response::Metrics(agent_metrics)) => {
+                outputs.push(metrics::relabel(&agent_metrics, "agent", &pid));
+                1
+            }
+            other => {
+                debug!(target: "supervisor", "Could not get metrics from agent {}: {:?}", pid, other);
+                0
+            }
+        };
+        up.push_str(&format!(
+            "liarslie_agent_up{{agent=\"{}\"}} {}\n",
+            pid, alive
+        ));
+        // Respawned agents get a new pid, so count restarts by slot.
+        restarts.push_str(&format!(
+            "liarslie_agent_restarts_total{{slot=\"{}\"}} {}\n",
+            agent.slot, agent.restarts
+        ));
+        info.push_str(&format!(
+            "liarslie_agent_info{{slot=\"{}\",agent=\"{}\"}} 1\n",
+            agent.slot, pid
+        ));
+    }
+    let fleet = format!(
+        "# HELP liarslie_fleet_agents Agents managed by the supervisor.\n\
+         # TYPE liarslie_fleet_agents gauge\n\
+         liarslie_fleet_agents {}\n\
+         # HELP liarslie_agent_up Whether the agent responds.\n\
+         # TYPE liarslie_agent_up gauge\n\
+         {}\
+         # HELP liarslie_agent_restarts_total Times the agent in this slot has been respawned.\n\
+         # TYPE liarslie_agent_restarts_total counter\n\
+         {}\
+         # HELP liarslie_agent_info The agent currently in each slot.\n\
+         # TYPE liarslie_agent_info gauge\n\
+         {}",
+        agents.len(),
+        up,
+        restarts,
+        info
+    );
+    outputs.insert(0, fleet);
+    metrics::merge(&outputs)
+}
+
+/// A supervisor running in another process.
+pub struct RemoteSupervisor {
+    control: PathBuf,
+}
+impl RemoteSupervisor {
+    pub fn new(control: &Path) -> Self {
+        RemoteSupervisor {
+            control: control.to_path_buf(),
+        }
+    }
+    pub async fn call(&self, command: &Command) -> Result<Reply, std::io::Error> {
+        let mut stream = UnixStream::connect(&self.control).await?;
+        let mut buffer = serde_json::to_string(command).unwrap();
+        buffer.push('\n');
+        stream.write_all(buffer.as_bytes()).await?;
+        stream.flush().await?;
+
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+pub struct ControlArgs {
+    pub control: PathBuf,
+    pub command: Command,
+}
+
+/// Implementation of command `control`.
+///
+/// Send a single command to a running supervisor, print the reply.
+pub async fn control(args: &ControlArgs) -> Result<(), String> {
+    let supervisor = RemoteSupervisor::new(&args.control);
+    match supervisor.call(&args.command).await {
+        Err(err) => Err(format!("Could not reach supervisor: {}", err)),
+        Ok(Reply::Error(err)) => Err(err),
+        Ok(Reply::Ok) => Ok(()),
+        Ok(Reply::Membership(conf)) => {
+            println!("{}", serde_json::to_string_pretty(&conf).unwrap());
+            Ok(())
+        }
+        Ok(Reply::Status(agents)) => {
+            println!(
+                "{:>8} {:>6} {:>6} {:>8}",
+                "pid", "port", "value", "restarts"
+            );
+            for agent in agents {
+                println!(
+                    "{:>8} {:>6} {:>6} {:>8}",
+                    agent.child.pid, agent.child.socket, agent.value, agent.restarts
+                );
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Optional TLS between clients and agents.
+//!
+//! `start --tls DIR` generates a self-signed certificate per agent in `DIR`.
+//! Such agents only speak TLS, and the registry publishes the fingerprint of
+//! their certificate, see `Child::fingerprint`. Clients, including agents
+//! contacting their peers, accept an agent only if it presents the
+//! certificate published in the registry, so they don't need to trust
+//! anybody but the registry, and there is no certificate authority.
+//!
+//! Agents don't authenticate clients.
+
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use log::*;
+use rcgen::{CertificateParams, DistinguishedName, DnType, SanType};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{
+    self, Certificate, ClientConfig, NoClientAuth, PrivateKey, RootCertStore, ServerCertVerified,
+    ServerCertVerifier, ServerConfig, TLSError,
+};
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use crate::util;
+
+/// A connection, with or without TLS.
+pub trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Stream for T {}
+
+/// The certificate and private key of an agent, PEM-encoded.
+#[derive(Clone, Debug)]
+pub struct TlsArgs {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+impl TlsArgs {
+    fn certificate(&self) -> Result<Certificate, std::io::Error> {
+        let file = std::fs::File::open(&self.cert)?;
+        rustls::internal::pemfile::certs(&mut BufReader::new(file))
+            .ok()
+            .and_then(|certs| certs.into_iter().next())
+            .ok_or_else(|| invalid(format!("No certificate in {:?}", self.cert)))
+    }
+
+    fn private_key(&self) -> Result<PrivateKey, std::io::Error> {
+        let file = std::fs::File::open(&self.key)?;
+        rustls::internal::pemfile::pkcs8_private_keys(&mut BufReader::new(file))
+            .ok()
+            .and_then(|keys| keys.into_iter().next())
+            .ok_or_else(|| invalid(format!("No PKCS#8 private key in {:?}", self.key)))
+    }
+
+    /// The fingerprint of the certificate, as published in the registry.
+    pub fn fingerprint(&self) -> Result<String, std::io::Error> {
+        Ok(fingerprint(&self.certificate()?))
+    }
+
+    /// Prepare to accept TLS connections with this certificate.
+    pub fn acceptor(&self) -> Result<TlsAcceptor, std::io::Error> {
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config
+            .set_single_cert(vec![self.certificate()?], self.private_key()?)
+            .map_err(invalid)?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+fn invalid(err: impl ToString) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string())
+}
+
+/// The SHA-256 of a DER-encoded certificate, hex-encoded.
+pub fn fingerprint(certificate: &Certificate) -> String {
+    util::to_hex(ring::digest::digest(&ring::digest::SHA256, &certificate.0).as_ref())
+}
+
+/// Accept the server certificate with a given fingerprint, and only that one.
+struct Pinned {
+    fingerprint: String,
+}
+impl ServerCertVerifier for Pinned {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        match presented_certs.first() {
+            Some(certificate) if fingerprint(certificate) == self.fingerprint => {
+                Ok(ServerCertVerified::assertion())
+            }
+            _ => Err(TLSError::General(format!(
+                "Certificate does not match fingerprint {}",
+                self.fingerprint
+            ))),
+        }
+    }
+}
+
+/// Open a TLS session on `stream`, with an agent whose certificate has fingerprint `fingerprint`.
+pub async fn connect(
+    stream: TcpStream,
+    fingerprint: &str,
+) -> Result<tokio_rustls::client::TlsStream<TcpStream>, std::io::Error> {
+    let mut config = ClientConfig::new();
+    config
+        .dangerous()
+        .set_certificate_verifier(Arc::new(Pinned {
+            fingerprint: fingerprint.to_string(),
+        }));
+    // Agents only listen on localhost, and we don't check names anyway.
+    let name = DNSNameRef::try_from_ascii_str("localhost").unwrap();
+    TlsConnector::from(Arc::new(config))
+        .connect(name, stream)
+        .await
+}
+
+/// Issues self-signed certificates to agents, in a directory.
+pub struct Issuer {
+    dir: PathBuf,
+
+    /// The number of certificates issued so far.
+    issued: AtomicUsize,
+}
+impl Issuer {
+    /// Prepare to issue certificates in `dir`, creating it if needed.
+    pub fn create(dir: &Path) -> Result<Self, std::io::Error> {
+        std::fs::create_dir_all(dir)?;
+        debug!(target: "tls", "Issuing certificates in {:?}", dir);
+        Ok(Issuer {
+            dir: dir.to_path_buf(),
+            issued: AtomicUsize::new(0),
+        })
+    }
+
+    /// Issue a self-signed certificate for an agent listening on localhost,
+    /// write it and its private key to our directory.
+    pub fn issue(&self) -> Result<TlsArgs, std::io::Error> {
+        let serial = self.issued.fetch_add(1, Ordering::SeqCst);
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]);
+        params
+            .subject_alt_names
+            .push(SanType::IpAddress([127, 0, 0, 1].into()));
+        params.distinguished_name = DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, format!("liarslie agent {}", serial));
+        let certificate = rcgen::Certificate::from_params(params).map_err(invalid)?;
+        let args = TlsArgs {
+            cert: self.dir.join(format!("agent-{}.pem", serial)),
+            key: self.dir.join(format!("agent-{}.key", serial)),
+        };
+        std::fs::write(&args.cert, certificate.serialize_pem().map_err(invalid)?)?;
+        util::write_private(
+            &args.key,
+            certificate.serialize_private_key_pem().as_bytes(),
+        )?;
+        Ok(args)
+    }
+}
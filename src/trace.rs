@@ -0,0 +1,303 @@
+//! Trace ids, to correlate the messages and logs of a round across agents.
+//!
+//! A player picks a trace id for each round. The id is carried by every
+//! request sent during the round, including requests sent by agents on
+//! behalf of the round, and included in every log line.
+//!
+//! Within a round, the work of each process is divided into `tracing` spans:
+//! the round itself, each call to a remote agent, each request handled by an
+//! agent. Spans that start a trace have a `trace` field, spans that continue
+//! a trace started by another process also have a `remote_parent` field,
+//! other spans inherit the trace of their parent. Requests carry the trace
+//! and span ids of the call, so that the spans of all processes form a
+//! single tree. If `LIARSLIE_TRACE_FILE` is set, spans are appended to that
+//! file, one OTLP/JSON `ExportTraceServiceRequest` per line, as understood
+//! e.g. by the `otlpjsonfile` receiver of the OpenTelemetry collector.
+//!
+//! Trace ids are only tracked once `init_logging` has installed our subscriber.
+
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::Write;
+use std::sync::{Mutex, Once, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::*;
+use rand::Rng;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Instrument, Subscriber};
+use tracing_subscriber::layer::{self, Layer, SubscriberExt};
+use tracing_subscriber::registry::{LookupSpan, Registry};
+
+/// Where we stand in a trace.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Context {
+    pub trace: u64,
+
+    /// The innermost span, if any.
+    pub span: Option<u64>,
+}
+
+/// A fresh trace or span id.
+pub fn new_id() -> u64 {
+    rand::thread_rng().gen()
+}
+
+/// The context of the current span, if it belongs to a trace.
+pub fn context() -> Option<Context> {
+    tracing::Span::current()
+        .with_subscriber(|(id, dispatch)| {
+            let registry = dispatch.downcast_ref::<Registry>()?;
+            let span = registry.span(id)?;
+            let extensions = span.extensions();
+            extensions.get::<Context>().copied()
+        })
+        .flatten()
+}
+
+/// The trace id of the current span, if any.
+pub fn current() -> Option<u64> {
+    context().map(|context| context.trace)
+}
+
+/// Run `f` in a new trace `trace`, or outside of any trace if `None`.
+pub async fn scope<F: Future>(trace: Option<u64>, f: F) -> F::Output {
+    f.instrument(tracing::info_span!(parent: None, "scope", trace))
+        .await
+}
+
+/// Run `f`, the body of a round, in a span starting a new trace, e.g.
+/// `#[instrument(name = "play", parent = None, skip_all, fields(trace = trace::new_id()))]`.
+pub async fn round<F: Future>(f: F) -> F::Output {
+    debug!(target: "trace", "Starting round {}", format(current()));
+    // The future of a round is large, keep it off the stack of the caller.
+    Box::pin(f).await
+}
+
+/// Spawn a task in the current span.
+pub fn spawn<F>(f: F) -> tokio::task::JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(f.in_current_span())
+}
+
+/// Environment variable naming the file to which spans are appended.
+///
+/// Being an environment variable, it is inherited by agents spawned by `start`.
+pub const TRACE_FILE: &str = "LIARSLIE_TRACE_FILE";
+
+fn exporter() -> Option<&'static Mutex<File>> {
+    static EXPORTER: OnceLock<Option<Mutex<File>>> = OnceLock::new();
+    EXPORTER
+        .get_or_init(|| {
+            let path = std::env::var_os(TRACE_FILE)?;
+            match OpenOptions::new().create(true).append(true).open(&path) {
+                Ok(file) => Some(Mutex::new(file)),
+                Err(err) => {
+                    warn!(target: "trace", "Could not open trace file {:?}: {:?}", path, err);
+                    None
+                }
+            }
+        })
+        .as_ref()
+}
+
+/// The fields of a span, as understood by `Spans`.
+#[derive(Default)]
+struct Fields {
+    trace: Option<u64>,
+    remote_parent: Option<u64>,
+
+    /// The name to export, if not the name of the span, as with `tracing-opentelemetry`.
+    name: Option<String>,
+    attributes: Vec<(&'static str, String)>,
+}
+impl Visit for Fields {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "trace" => self.trace = Some(value),
+            "remote_parent" => self.remote_parent = Some(value),
+            name => self.attributes.push((name, format!("{}", value))),
+        }
+    }
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "otel.name" => self.name = Some(value.to_string()),
+            name => self.attributes.push((name, value.to_string())),
+        }
+    }
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value))
+    }
+}
+
+/// A span to export once closed.
+struct Exported {
+    parent: Option<u64>,
+    name: String,
+    attributes: Vec<(&'static str, String)>,
+    start: SystemTime,
+}
+
+/// Assign trace and span ids to spans, export them if `LIARSLIE_TRACE_FILE` is set.
+struct Spans;
+impl<S> Layer<S> for Spans
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: layer::Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        let inherited = span
+            .parent()
+            .and_then(|parent| parent.extensions().get::<Context>().copied());
+        let (trace, parent) = match (fields.trace, inherited) {
+            (Some(trace), _) => (trace, fields.remote_parent),
+            (None, Some(inherited)) => (inherited.trace, inherited.span),
+            (None, None) => return,
+        };
+        let context = Context {
+            trace,
+            span: Some(new_id()),
+        };
+        let mut extensions = span.extensions_mut();
+        extensions.insert(context);
+        if exporter().is_some() {
+            extensions.insert(Exported {
+                parent,
+                name: fields.name.unwrap_or_else(|| span.name().to_string()),
+                attributes: fields.attributes,
+                start: SystemTime::now(),
+            });
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: layer::Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => return,
+        };
+        let extensions = span.extensions();
+        if let (Some(context), Some(exported)) =
+            (extensions.get::<Context>(), extensions.get::<Exported>())
+        {
+            export(context, exported, SystemTime::now());
+        }
+    }
+}
+
+fn export(context: &Context, exported: &Exported, end: SystemTime) {
+    let exporter = match exporter() {
+        Some(exporter) => exporter,
+        None => return,
+    };
+    let nanos = |time: SystemTime| {
+        format!(
+            "{}",
+            time.duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_nanos())
+                .unwrap_or(0)
+        )
+    };
+    let attributes: Vec<_> = exported
+        .attributes
+        .iter()
+        .map(|(key, value)| serde_json::json!({ "key": key, "value": { "stringValue": value } }))
+        .collect();
+    let span = serde_json::json!({
+        "traceId": format!("{:032x}", context.trace),
+        "spanId": context.span.map(|span| format!("{:016x}", span)).unwrap_or_default(),
+        "parentSpanId": exported.parent.map(|span| format!("{:016x}", span)).unwrap_or_default(),
+        "name": exported.name,
+        "kind": 1,
+        "startTimeUnixNano": nanos(exported.start),
+        "endTimeUnixNano": nanos(end),
+        "attributes": attributes,
+    });
+    let request = serde_json::json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    { "key": "service.name", "value": { "stringValue": "liarslie" } },
+                    { "key": "process.pid", "value": { "intValue": format!("{}", std::process::id()) } },
+                ]
+            },
+            "scopeSpans": [{
+                "scope": { "name": "liarslie" },
+                "spans": [span],
+            }],
+        }]
+    });
+    let mut line = request.to_string();
+    line.push('\n');
+    // A single write per span, so that processes appending to the same file don't interleave.
+    if let Err(err) = exporter.lock().unwrap().write_all(line.as_bytes()) {
+        warn!(target: "trace", "Could not export span: {:?}", err);
+    }
+}
+
+/// Format `trace` for humans.
+pub fn format(trace: Option<u64>) -> String {
+    match trace {
+        Some(trace) => format!("{:016x}", trace),
+        None => "-".to_string(),
+    }
+}
+
+/// Environment variable selecting the log format. If it is `json`, log one
+/// JSON object per line, otherwise log text.
+///
+/// Being an environment variable, it is inherited by agents spawned by `start`.
+pub const LOG_FORMAT: &str = "LIARSLIE_LOG_FORMAT";
+
+/// Initialize logging, as configured by `RUST_LOG` and `LIARSLIE_LOG_FORMAT`,
+/// and install the subscriber that tracks spans. Only the first call has an effect.
+pub fn init_logging() {
+    static INIT: Once = Once::new();
+    INIT.call_once(init);
+}
+
+fn init() {
+    let json = std::env::var(LOG_FORMAT)
+        .map(|format| format == "json")
+        .unwrap_or(false);
+    let mut builder = env_logger::Builder::from_default_env();
+    if json {
+        builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "ts": format!("{}", buf.timestamp_millis()),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "pid": std::process::id(),
+                "trace": current().map(|trace| format!("{:016x}", trace)),
+                "msg": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        });
+    } else {
+        builder.format(|buf, record| {
+            writeln!(
+                buf,
+                "[{} {} {} {} {}] {}",
+                buf.timestamp_millis(),
+                record.level(),
+                std::process::id(),
+                format(current()),
+                record.target(),
+                record.args()
+            )
+        });
+    }
+    builder.init();
+    let subscriber = Registry::default().with(Spans);
+    if let Err(err) = tracing::subscriber::set_global_default(subscriber) {
+        warn!(target: "trace", "Could not install trace subscriber: {}", err);
+    }
+}
@@ -0,0 +1,162 @@
+//! Transcripts of rounds, to replay the decision of a player offline.
+
+use std::path::Path;
+use std::time::Instant;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::agent::{CallError, Certificate, ErrorCode, Message, Response};
+use crate::conf::{Child, Conf};
+use crate::play;
+use crate::playexpert;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Strategy {
+    Play,
+    PlayExpert,
+}
+
+/// A request sent during a round and what came of it.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Entry {
+    pub child: Child,
+    pub request: Message,
+
+    /// The response, unless the call failed.
+    pub response: Option<Response>,
+    pub error: Option<String>,
+
+    /// If the agent refused the request, why.
+    #[serde(default)]
+    pub refused: Option<ErrorCode>,
+
+    /// Milliseconds since the start of the round.
+    pub sent_ms: f64,
+    pub received_ms: f64,
+}
+impl Entry {
+    /// Call `child` with `request`, record the exchange.
+    pub async fn call(child: Child, request: Message, round: Instant) -> Entry {
+        let remote = crate::agent::RemoteAgent::new(child.clone());
+        let sent_ms = round.elapsed().as_secs_f64() * 1000.;
+        let (response, error, refused) = match remote.call(&request).await {
+            Ok(response) => (Some(response), None, None),
+            Err(CallError::Refused { code, message }) => (None, Some(message), Some(code)),
+            Err(err) => (None, Some(format!("{}", err)), None),
+        };
+        Entry {
+            child,
+            request,
+            response,
+            error,
+            refused,
+            sent_ms,
+            received_ms: round.elapsed().as_secs_f64() * 1000.,
+        }
+    }
+
+    /// Like `call`, for a streaming campaign: call `on_vote` with each vote
+    /// as it arrives, record all the votes as a `Response::Quorum`.
+    pub async fn stream<F>(child: Child, request: Message, round: Instant, on_vote: F) -> Entry
+    where
+        F: FnMut(&Certificate),
+    {
+        let remote = crate::agent::RemoteAgent::new(child.clone());
+        let sent_ms = round.elapsed().as_secs_f64() * 1000.;
+        let (response, error, refused) = match remote.stream(&request, on_vote).await {
+            Ok(votes) => (Some(Response::Quorum(votes)), None, None),
+            Err(CallError::Refused { code, message }) => (None, Some(message), Some(code)),
+            Err(err) => (None, Some(format!("{}", err)), None),
+        };
+        Entry {
+            child,
+            request,
+            response,
+            error,
+            refused,
+            sent_ms,
+            received_ms: round.elapsed().as_secs_f64() * 1000.,
+        }
+    }
+}
+
+/// Everything a player has seen during a round, in the order in which it
+/// has taken it into account.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Transcript {
+    pub strategy: Strategy,
+
+    /// The trace id of the round, see module `trace`.
+    pub trace: Option<u64>,
+
+    /// The registry, including the weights of agents.
+    pub conf: Conf,
+
+    /// The weight needed to decide.
+    pub quorum: usize,
+    pub entries: Vec<Entry>,
+
+    /// The decision of the player.
+    pub result: Option<bool>,
+}
+impl Transcript {
+    pub fn load(path: &Path) -> Result<Self, std::io::Error> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), std::io::Error> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    /// Run the decision logic of the player again, against the recorded entries.
+    pub fn replay(&self) -> Option<bool> {
+        match self.strategy {
+            Strategy::Play => play::decide(&self.conf, &self.entries),
+            Strategy::PlayExpert => playexpert::decide(&self.conf, &self.entries),
+        }
+    }
+}
+
+/// Implementation of command `replay`.
+///
+/// Print the transcript at `path`, replay it, return an error if the decision differs.
+pub fn replay(path: &Path) -> Result<(), String> {
+    let transcript =
+        Transcript::load(path).map_err(|err| format!("Could not read transcript: {}", err))?;
+    println!(
+        "{:?} with {} agents, quorum {}",
+        transcript.strategy,
+        transcript.conf.children.len(),
+        transcript.quorum
+    );
+    println!(
+        "{:>8} {:>6} {:>10} {:>10}  response",
+        "pid", "port", "sent ms", "recv ms"
+    );
+    for entry in &transcript.entries {
+        let response = match (&entry.response, &entry.error) {
+            (Some(response), _) => format!("{:?}", response),
+            (None, Some(err)) => match entry.refused {
+                Some(code) => format!("refused ({}): {}", code.name(), err),
+                None => format!("error: {}", err),
+            },
+            (None, None) => "-".to_string(),
+        };
+        println!(
+            "{:>8} {:>6} {:>10.3} {:>10.3}  {}",
+            entry.child.pid, entry.child.socket, entry.sent_ms, entry.received_ms, response
+        );
+    }
+    let replayed = transcript.replay();
+    println!(
+        "recorded: {:?}, replayed: {:?}",
+        transcript.result, replayed
+    );
+    if replayed != transcript.result {
+        return Err("Replayed decision differs from recorded decision".to_string());
+    }
+    Ok(())
+}
@@ -1,14 +1,13 @@
 use std::future::Future;
+use std::path::Path;
 
 use rand::Rng;
 
 const MAX_RETRIES: u64 = 10;
 
 async fn sleep(hint: u64) {
-    let delay = std::time::Duration::new(
-        hint * hint as u64,
-        rand::thread_rng().gen_range(0, 1_000_000_000),
-    );
+    let delay =
+        std::time::Duration::new(hint * hint, rand::thread_rng().gen_range(0, 1_000_000_000));
     tokio::time::delay_for(delay).await;
 }
 
@@ -71,3 +70,47 @@ where
 {
     retry_closure_if(f, |_| true).await
 }
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Write `contents` to a new file at `path` that only we can read,
+/// replacing any previous file.
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<(), std::io::Error> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    let _ = std::fs::remove_file(path);
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents)
+}
+
+/// Replace the contents of `path` with `contents`, so that readers see
+/// either the previous contents or the new ones, never a partial file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), std::io::Error> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    // Concurrent writers, in this process or another, each need their own file.
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::SeqCst)
+    ));
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)
+}
@@ -0,0 +1,254 @@
+extern crate liars;
+extern crate rand;
+extern crate tokio_test;
+
+mod common;
+
+use rand::Rng;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use liars::accountability::{self, EquivocationProof};
+use liars::agent::{Message, RemoteAgent, Response};
+use liars::conf::Child;
+use liars::playexpert::PlayExpertArgs;
+use liars::start::*;
+use liars::transcript::{Entry, Transcript};
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// The pids of the party of `child` when campaigning among `children`.
+async fn party(child: &Child, children: &[Child]) -> Vec<u32> {
+    match RemoteAgent::new(child.clone())
+        .call(&Message::Campaign(children.to_vec()))
+        .await
+    {
+        Ok(Response::Quorum(party)) => party
+            .iter()
+            .map(|certificate| certificate.issuer.pid)
+            .collect(),
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
+
+/// Catch an equivocating agent with `playexpert`, check that honest agents exclude it.
+async fn test_impl() {
+    // Proofs, locally.
+    let me = Child {
+        pid: std::process::id(),
+        socket: 0,
+        signing_key: Some(accountability::public_key()),
+        ..Default::default()
+    };
+    let registry = vec![me.clone()];
+    let yea = accountability::sign(true, &me, Some(1));
+    let nay = accountability::sign(false, &me, Some(1));
+    assert!(yea.verify(&registry) && nay.verify(&registry));
+    let proofs = accountability::find(vec![&yea, &yea, &nay], &registry);
+    assert_eq!(proofs.len(), 1);
+    assert_eq!(proofs[0].culprit(), &me);
+    assert!(proofs[0].verify(&registry));
+    let other_round = accountability::sign(false, &me, Some(2));
+    assert!(accountability::find(vec![&yea, &other_round], &registry).is_empty());
+    // A vote bound to an epoch holds in every round of it.
+    let epoch_nay = accountability::sign_in_epoch(false, &me, 1);
+    assert!(epoch_nay.verify(&registry));
+    assert_eq!(
+        accountability::find(vec![&yea, &epoch_nay], &registry).len(),
+        1
+    );
+    let other_epoch = accountability::sign_in_epoch(true, &me, 2);
+    assert!(accountability::find(vec![&epoch_nay, &other_epoch], &registry).is_empty());
+    let mut forged = EquivocationProof {
+        first: yea.clone(),
+        second: nay.clone(),
+    };
+    forged.second.signature = yea.signature.clone();
+    assert!(!forged.verify(&registry));
+
+    // Certificates only count if signed with the key published for their issuer.
+    let unpublished = vec![Child {
+        signing_key: None,
+        ..me.clone()
+    }];
+    assert!(!yea.verify(&unpublished));
+    assert!(accountability::find(vec![&yea, &nay], &unpublished).is_empty());
+    let impersonated = vec![Child {
+        signing_key: Some("00".repeat(32)),
+        ..me.clone()
+    }];
+    assert!(!yea.verify(&impersonated));
+    assert!(!proofs[0].verify(&impersonated));
+
+    // A fleet of reliable agents, plus one equivocator.
+    let value = rand::thread_rng().gen_bool(0.5);
+    let start_args = StartArgs {
+        value,
+        liar_ratio: 0.,
+        num_agents: 6,
+        ..common::start_args()
+    };
+    let (mut conf, mut processes) = start(&start_args).await;
+    let mut equivocator = tokio::process::Command::new(&start_args.exe)
+        .arg("agent")
+        .arg("--value")
+        .arg(if value { "true" } else { "false" })
+        .arg("--equivocate")
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .expect("Could not spawn equivocator");
+    let mut lines = BufReader::new(equivocator.stdout.as_mut().unwrap()).lines();
+    let port = lines.next_line().await.unwrap().unwrap();
+    let key = lines.next_line().await.unwrap().unwrap();
+    let signing_key = lines.next_line().await.unwrap().unwrap();
+    let culprit = Child {
+        pid: equivocator.id(),
+        socket: port.parse().unwrap(),
+        key: Some(key),
+        signing_key: Some(signing_key),
+        ..Default::default()
+    };
+    conf.children.push(culprit.clone());
+    conf.save(std::path::Path::new("agents.conf")).unwrap();
+    processes.push(equivocator);
+
+    // Outside of a round, nobody can tell.
+    let honest = conf.children[0].clone();
+    assert!(party(&honest, &conf.children).await.contains(&culprit.pid));
+
+    // Nobody can frame an honest agent with certificates signed by another key.
+    let framed = EquivocationProof {
+        first: accountability::sign(true, &honest, Some(1)),
+        second: accountability::sign(false, &honest, Some(1)),
+    };
+    assert!(!framed.verify(&conf.children));
+    for child in &conf.children {
+        match RemoteAgent::new(child.clone())
+            .call(&Message::Accuse(Box::new(framed.clone())))
+            .await
+        {
+            Ok(Response::Excluded(excluded)) => assert!(excluded.is_empty()),
+            other => panic!("Unexpected response {:?}", other),
+        }
+    }
+    assert_eq!(
+        party(&honest, &conf.children).await.len(),
+        conf.children.len()
+    );
+
+    // Nor by claiming that the agent listens elsewhere, with another key,
+    // and vouching for that key when asked.
+    let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Could not bind");
+    let impostor = Child {
+        socket: listener.local_addr().unwrap().port(),
+        signing_key: Some(accountability::public_key()),
+        ..honest.clone()
+    };
+    let certificate = accountability::sign(true, &impostor, None);
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let mut stream = BufReader::new(stream);
+            let mut line = String::new();
+            let _ = stream.read_line(&mut line).await;
+            let mut response =
+                serde_json::to_string(&Response::Certificate(certificate.clone())).unwrap();
+            response.push('\n');
+            let _ = stream.get_mut().write_all(response.as_bytes()).await;
+        }
+    });
+    let forged_identity = EquivocationProof {
+        first: accountability::sign(true, &impostor, Some(1)),
+        second: accountability::sign(false, &impostor, Some(1)),
+    };
+    assert!(forged_identity.verify(std::slice::from_ref(&impostor)));
+    for child in &conf.children {
+        match RemoteAgent::new(child.clone())
+            .call(&Message::Accuse(Box::new(forged_identity.clone())))
+            .await
+        {
+            Ok(Response::Excluded(excluded)) => assert!(excluded.is_empty()),
+            other => panic!("Unexpected response {:?}", other),
+        }
+    }
+    assert_eq!(
+        party(&honest, &conf.children).await.len(),
+        conf.children.len()
+    );
+
+    // `play` only counts the certificate of the agent it asked, not one it relays.
+    let relayed = match RemoteAgent::new(honest.clone())
+        .call(&Message::GetValue)
+        .await
+    {
+        Ok(Response::Certificate(certificate)) => certificate,
+        other => panic!("Unexpected response {:?}", other),
+    };
+    let entries: Vec<_> = conf
+        .children
+        .iter()
+        .map(|child| Entry {
+            child: child.clone(),
+            request: Message::GetValue,
+            response: Some(Response::Certificate(relayed.clone())),
+            error: None,
+            refused: None,
+            sent_ms: 0.,
+            received_ms: 0.,
+        })
+        .collect();
+    assert_eq!(liars::play::decide(&conf, &entries), None);
+
+    let path = std::env::temp_dir().join(format!(
+        "liarslie-accountability-{}.json",
+        std::process::id()
+    ));
+    let play_expert_args = PlayExpertArgs {
+        liar_ratio: 0.,
+        transcript: Some(path.clone()),
+        ..Default::default()
+    };
+    assert_eq!(
+        liars::playexpert::play(&play_expert_args).await,
+        Some(value)
+    );
+
+    // The transcript holds a proof against the equivocator.
+    let transcript = Transcript::load(&path).expect("Could not load transcript");
+    let certificates: Vec<_> = transcript
+        .entries
+        .iter()
+        .filter_map(|entry| match entry.response {
+            Some(Response::Quorum(ref party)) => Some(party.iter()),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    let proofs = accountability::find(certificates, &conf.children);
+    assert_eq!(proofs.len(), 1);
+    assert_eq!(proofs[0].culprit(), &culprit);
+
+    // Honest agents have been told, they don't vouch for the equivocator anymore.
+    for child in &conf.children[..conf.children.len() - 1] {
+        match RemoteAgent::new(child.clone())
+            .call(&Message::Accuse(Box::new(forged.clone())))
+            .await
+        {
+            Ok(Response::Excluded(excluded)) => {
+                assert_eq!(excluded, vec![culprit.clone()]);
+                assert_eq!(excluded[0].signing_key, culprit.signing_key);
+            }
+            other => panic!("Unexpected response {:?}", other),
+        }
+    }
+    let party = party(&honest, &conf.children).await;
+    assert!(!party.contains(&culprit.pid));
+    assert_eq!(party.len(), conf.children.len() - 1);
+
+    common::stop(processes).await;
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file("agents.conf");
+}
@@ -0,0 +1,84 @@
+extern crate liars;
+extern crate rand;
+extern crate tokio_test;
+
+mod common;
+
+use rand::Rng;
+
+use liars::admin;
+use liars::agent::{CallError, ErrorCode, Message, RemoteAgent, Response};
+use liars::play::PlayArgs;
+use liars::start::*;
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// Check that administrative messages require the admin token, while other messages remain public.
+async fn test_impl() {
+    use std::os::unix::fs::PermissionsExt;
+    let path = std::env::temp_dir().join(format!("liarslie-admin-{}.token", std::process::id()));
+    let value = rand::thread_rng().gen_bool(0.5);
+    let start_args = StartArgs {
+        value,
+        liar_ratio: 0.,
+        num_agents: 4,
+        admin_token: Some(path.clone()),
+        ..common::start_args()
+    };
+    let (conf, processes) = start(&start_args).await;
+    let mode = std::fs::metadata(&path)
+        .expect("Missing admin token")
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o077, 0);
+    let token = admin::load(&path).expect("Could not read admin token");
+    assert_eq!(token.len(), 64);
+
+    // Anybody can play.
+    let play_args = PlayArgs::default();
+    assert_eq!(liars::play::play(&play_args).await, Some(value));
+
+    // Only admins can stop.
+    let child = conf.children[0].clone();
+    match RemoteAgent::new(child.clone()).call(&Message::Stop).await {
+        Err(CallError::Refused {
+            code: ErrorCode::Unauthorized,
+            ..
+        }) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+    match RemoteAgent::new(child.clone())
+        .with_admin_token("not the token".to_string())
+        .call(&Message::Stop)
+        .await
+    {
+        Err(CallError::Refused {
+            code: ErrorCode::Unauthorized,
+            ..
+        }) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+    match RemoteAgent::new(child.clone())
+        .with_admin_token(token.clone())
+        .call(&Message::Stop)
+        .await
+    {
+        Ok(Response::Stop) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+    match RemoteAgent::new(child)
+        .with_admin_token(token)
+        .call(&Message::GetValue)
+        .await
+    {
+        Ok(Response::Certificate(certificate)) => assert_eq!(certificate.value, value),
+        other => panic!("Unexpected response {:?}", other),
+    }
+
+    common::stop(processes).await;
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file("agents.conf");
+}
@@ -0,0 +1,98 @@
+extern crate liars;
+extern crate rand;
+extern crate tokio_test;
+
+mod common;
+
+use rand::Rng;
+
+use liars::agent::{Message, RemoteAgent, Response};
+use liars::playexpert::PlayExpertArgs;
+use liars::start::*;
+use liars::transcript::Transcript;
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// Check that agents combine the votes of their allies into a certificate
+/// that players check against the registry, and that forgeries are caught.
+async fn test_impl() {
+    let value = rand::thread_rng().gen_bool(0.5);
+    let num_agents = 8;
+    let start_args = StartArgs {
+        value,
+        liar_ratio: 0.2,
+        num_agents,
+        ..common::start_args()
+    };
+    let (conf, processes) = start(&start_args).await;
+    assert!(conf.children.iter().all(|child| child.key.is_some()));
+
+    // Liars have their own, smaller, party.
+    let mut weights = vec![];
+    for child in &conf.children {
+        let aggregate = match RemoteAgent::new(child.clone())
+            .call(&Message::AggregateCampaign(conf.children.clone()))
+            .await
+        {
+            Ok(Response::Aggregate(aggregate)) => aggregate,
+            other => panic!("Unexpected response {:?}", other),
+        };
+        let weight = aggregate
+            .verify(&conf.children, &conf)
+            .expect("Invalid aggregate");
+        assert_eq!(weight >= conf.quorum(), aggregate.value == value);
+        weights.push(weight);
+
+        // Signatures don't match another value.
+        let mut forged = aggregate.clone();
+        forged.value = !forged.value;
+        assert_eq!(forged.verify(&conf.children, &conf), None);
+
+        // Signers must be members, with the keys of the registry.
+        assert_eq!(aggregate.verify(&conf.children[1..], &conf), None);
+        let mut registry = liars::conf::Conf {
+            epoch: conf.epoch,
+            children: conf.children.clone(),
+        };
+        let culprit = aggregate.signers(&conf.children).unwrap()[0].clone();
+        for child in &mut registry.children {
+            if *child == culprit {
+                child.key = conf
+                    .children
+                    .iter()
+                    .find(|c| **c != culprit)
+                    .unwrap()
+                    .key
+                    .clone();
+            }
+        }
+        assert_eq!(aggregate.verify(&conf.children, &registry), None);
+    }
+    assert!(weights.iter().any(|weight| *weight >= conf.quorum()));
+
+    // Players decide on a single aggregate, and can replay their decision.
+    let path = std::env::temp_dir().join(format!("liarslie-aggregate-{}.json", std::process::id()));
+    let play_expert_args = PlayExpertArgs {
+        liar_ratio: 0.2,
+        transcript: Some(path.clone()),
+        aggregate: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        liars::playexpert::play(&play_expert_args).await,
+        Some(value)
+    );
+    let transcript = Transcript::load(&path).expect("Could not load transcript");
+    assert!(transcript.entries.iter().all(|entry| matches!(
+        entry.response,
+        Some(Response::Aggregate(_)) | Some(Response::Undecided)
+    )));
+    assert_eq!(transcript.replay(), Some(value));
+
+    common::stop(processes).await;
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file("agents.conf");
+}
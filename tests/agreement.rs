@@ -0,0 +1,136 @@
+extern crate liars;
+extern crate rand;
+extern crate tokio_test;
+
+mod common;
+
+use rand::Rng;
+
+use liars::agent::{Message, RemoteAgent, Response};
+use liars::agreement::{Commitment, Prepared};
+use liars::playagree::PlayAgreeArgs;
+use liars::start::*;
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+async fn test_impl() {
+    let value = rand::thread_rng().gen_bool(0.5);
+    let start_args = StartArgs {
+        value,
+        liar_ratio: 0.4,
+        num_agents: 12,
+        ..common::start_args()
+    };
+    let (conf, processes) = start(&start_args).await;
+
+    // The agents agree on the honest value.
+    let play_args = PlayAgreeArgs {
+        path: std::path::PathBuf::from("agents.conf"),
+    };
+    assert_eq!(liars::playagree::play(&play_args).await, Some(value));
+
+    // Once an agent has committed, the decision can be read from any other agent.
+    let round = rand::thread_rng().gen::<u64>();
+    let remote = RemoteAgent::new(conf.children[0].clone());
+    let message = Message::Agree {
+        round,
+        children: conf.children.clone(),
+    };
+    let commitment = match remote.call(&message).await {
+        Ok(Response::Decision(commitment)) => commitment,
+        other => panic!("Unexpected response {:?}", other),
+    };
+    assert!(commitment.verify(round, &conf));
+    assert_eq!(commitment.value, value);
+
+    let remote = RemoteAgent::new(conf.children[conf.children.len() - 1].clone());
+    let mut attempts = 0;
+    let decision = loop {
+        match remote.call(&Message::GetDecision(round)).await {
+            Ok(Response::Decision(commitment)) => break commitment,
+            Ok(Response::Undecided) => {}
+            other => panic!("Unexpected response {:?}", other),
+        }
+        attempts += 1;
+        assert!(attempts < 100, "The commitment should be broadcast");
+        tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+    };
+    assert!(decision.verify(round, &conf));
+    assert_eq!(decision.value, value);
+
+    // A tampered commitment doesn't check out.
+    let mut tampered = decision.clone();
+    tampered.value = !value;
+    assert!(!tampered.verify(round, &conf));
+    assert!(!decision.verify(round + 1, &conf));
+
+    // A Byzantine client forges a commitment for the value of liars, out of
+    // their certificates, pretending that the fleet is only made of liars.
+    let mut certificates = vec![];
+    for child in &conf.children {
+        match RemoteAgent::new(child.clone())
+            .call(&Message::GetValue)
+            .await
+        {
+            Ok(Response::Certificate(certificate)) if certificate.value != value => {
+                certificates.push(certificate)
+            }
+            Ok(Response::Certificate(_)) => {}
+            other => panic!("Unexpected response {:?}", other),
+        }
+    }
+    assert!(!certificates.is_empty());
+    let round = rand::thread_rng().gen::<u64>();
+    let forged = Commitment {
+        round,
+        value: !value,
+        prepared: certificates
+            .iter()
+            .map(|certificate| Prepared {
+                round,
+                value: !value,
+                issuer: certificate.issuer.clone(),
+                proof: certificates.clone(),
+                signature: "00".repeat(64),
+            })
+            .collect(),
+    };
+    assert!(!forged.verify(round, &conf));
+    let honest = conf
+        .children
+        .iter()
+        .find(|child| {
+            !certificates
+                .iter()
+                .any(|certificate| certificate.issuer == **child)
+        })
+        .unwrap();
+    let remote = RemoteAgent::new(honest.clone());
+    match remote.call(&Message::Commit(forged)).await {
+        Ok(Response::Undecided) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+    match remote.call(&Message::GetDecision(round)).await {
+        Ok(Response::Undecided) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+
+    // The round can still be decided, for the honest value.
+    let message = Message::Agree {
+        round,
+        children: conf.children.clone(),
+    };
+    match remote.call(&message).await {
+        Ok(Response::Decision(commitment)) => {
+            assert!(commitment.verify(round, &conf));
+            assert_eq!(commitment.value, value);
+        }
+        other => panic!("Unexpected response {:?}", other),
+    }
+
+    common::stop(processes).await;
+    let _ = std::fs::remove_file("agents.conf");
+}
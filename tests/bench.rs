@@ -0,0 +1,43 @@
+extern crate liars;
+extern crate tokio_test;
+
+mod common;
+
+use liars::bench::*;
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+async fn test_impl() {
+    let args = BenchArgs {
+        exe: std::path::PathBuf::from(env!("CARGO_BIN_EXE_liarslie")),
+        num_agents: vec![5, 8],
+        liar_ratios: vec![0.2],
+        strategies: vec![Strategy::Play, Strategy::PlayExpert],
+        rounds: 3,
+        warmup: std::time::Duration::from_millis(100),
+    };
+    let rows = bench(&args).await;
+    assert_eq!(rows.len(), 4);
+    for row in rows {
+        assert_eq!(row.correct + row.wrong + row.undecided, 3);
+        assert_eq!(row.wrong, 0, "{:?}", row);
+        assert!(row.messages > 0.);
+        assert!(row.bytes_received > 0.);
+        if row.strategy == "playexpert" {
+            // Agents campaign among themselves.
+            assert!(row.fleet_messages > 0.);
+        }
+        assert!(row.latency_p50_ms <= row.latency_max_ms);
+        // An aggregate certificate is smaller than the party it stands for.
+        assert!(row.aggregate_bytes > 0);
+        assert!(row.aggregate_bytes < row.quorum_bytes, "{:?}", row);
+        assert_eq!(
+            row.to_csv().split(',').count(),
+            Row::CSV_HEADER.split(',').count()
+        );
+    }
+    assert!(!std::path::Path::new("agents.conf").exists());
+}
@@ -0,0 +1,121 @@
+extern crate liars;
+extern crate tokio_test;
+
+mod common;
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use liars::agent::{Certificate, Message, RemoteAgent, Response};
+use liars::conf::Child;
+use liars::playexpert::PlayExpertArgs;
+use liars::start::*;
+use liars::trace;
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// The number of `GetVote` sent by `child` so far.
+async fn get_vote_sent(child: &Child) -> usize {
+    match RemoteAgent::new(child.clone()).call(&Message::Stats).await {
+        Ok(Response::Stats(stats)) => stats
+            .outgoing
+            .by_message
+            .get("GetVote")
+            .map(|traffic| traffic.messages)
+            .unwrap_or(0),
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
+
+async fn campaign(child: &Child, children: &[Child]) -> Vec<Certificate> {
+    match RemoteAgent::new(child.clone())
+        .call(&Message::Campaign(children.to_vec()))
+        .await
+    {
+        Ok(Response::Quorum(party)) => party,
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
+
+async fn quorum(child: &Child, children: &[Child]) -> usize {
+    campaign(child, children).await.len()
+}
+
+/// Check that agents reuse the votes of their peers across rounds, and that concurrent campaigns among the same agents only ask each agent once.
+async fn test_impl() {
+    let num_agents = 4;
+    let start_args = StartArgs {
+        value: true,
+        liar_ratio: 0.,
+        num_agents,
+        ..common::start_args()
+    };
+    let (conf, processes) = start(&start_args).await;
+
+    // Repeated campaigns reuse certificates.
+    let first = conf.children[0].clone();
+    for _ in 0..3 {
+        assert_eq!(quorum(&first, &conf.children).await, num_agents);
+    }
+    assert_eq!(get_vote_sent(&first).await, num_agents);
+
+    // Votes are bound to the epoch of the registry, so they are reused
+    // across rounds, and they count for clients of that epoch.
+    for _ in 0..2 {
+        let party = trace::scope(Some(trace::new_id()), campaign(&first, &conf.children)).await;
+        assert_eq!(party.len(), num_agents);
+        assert!(party.iter().all(
+            |certificate| certificate.epoch == Some(conf.epoch) && certificate.round.is_none()
+        ));
+        assert!(party
+            .iter()
+            .all(|certificate| certificate.verify(&conf.children)));
+    }
+    assert_eq!(get_vote_sent(&first).await, num_agents);
+
+    // A slow agent, which never responds, keeps campaigns in progress for a while.
+    let mut slow = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Could not bind");
+    let mut children = conf.children.clone();
+    children.push(Child {
+        pid: 0,
+        socket: slow.local_addr().unwrap().port(),
+        ..Default::default()
+    });
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = slow.accept().await {
+            tokio::spawn(async move {
+                let mut stream = BufReader::new(stream);
+                let mut line = String::new();
+                let _ = stream.read_line(&mut line).await;
+                tokio::time::delay_for(std::time::Duration::from_secs(1)).await;
+            });
+        }
+    });
+
+    // Concurrent campaigns are coalesced.
+    let second = conf.children[1].clone();
+    let (a, b) = tokio::join!(quorum(&second, &children), quorum(&second, &children));
+    assert_eq!(a, num_agents);
+    assert_eq!(b, num_agents);
+    assert_eq!(get_vote_sent(&second).await, num_agents + 1);
+    match RemoteAgent::new(second).call(&Message::Metrics).await {
+        Ok(Response::Metrics(metrics)) => {
+            assert!(metrics.contains("liarslie_coalesced_campaigns_total 1"))
+        }
+        other => panic!("Unexpected response {:?}", other),
+    }
+
+    // Clients accept votes bound to the epoch of their registry.
+    let args = PlayExpertArgs {
+        liar_ratio: 0.,
+        ..Default::default()
+    };
+    assert_eq!(liars::playexpert::play(&args).await, Some(true));
+
+    common::stop(processes).await;
+    let _ = std::fs::remove_file("agents.conf");
+}
@@ -0,0 +1,81 @@
+extern crate liars;
+extern crate rand;
+extern crate tokio_test;
+
+mod common;
+
+use rand::Rng;
+
+use liars::conf::Conf;
+use liars::play::PlayArgs;
+use liars::playexpert::PlayExpertArgs;
+use liars::start::StartArgs;
+use liars::supervisor::*;
+
+const REGISTRY: &str = "chaos.conf";
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// Play while agents are being killed and respawned.
+async fn test_impl() {
+    let value = rand::thread_rng().gen_bool(0.5);
+    let liar_ratio = 0.1;
+    let start_args = StartArgs {
+        value,
+        liar_ratio,
+        num_agents: 20,
+        ..common::start_args()
+    };
+    let supervise_args = SuperviseArgs {
+        path: std::path::PathBuf::from(REGISTRY),
+        chaos: Some(ChaosArgs {
+            period: std::time::Duration::from_millis(100),
+            kill_probability: 0.1,
+        }),
+        ..Default::default()
+    };
+    let supervisor = Supervisor::start(&start_args, supervise_args).await;
+    let initial = supervisor.conf();
+    let (tshutdown, rshutdown) = tokio::sync::oneshot::channel();
+    let supervisor = tokio::spawn(supervisor.run(rshutdown));
+
+    for i in 0..20 {
+        eprintln!("Round {}", i);
+        // Churn may leave us undecided, but we should never pick the wrong value.
+        let result = if i % 2 == 0 {
+            let play_args = PlayArgs {
+                path: std::path::PathBuf::from(REGISTRY),
+                ..Default::default()
+            };
+            liars::play::play(&play_args).await
+        } else {
+            let play_expert_args = PlayExpertArgs {
+                path: std::path::PathBuf::from(REGISTRY),
+                liar_ratio,
+                ..Default::default()
+            };
+            liars::playexpert::play(&play_expert_args).await
+        };
+        assert_ne!(
+            result,
+            Some(!value),
+            "Churn should not cause a wrong result"
+        );
+    }
+
+    // By now, chaos should have replaced a few agents.
+    let file = std::fs::File::open(REGISTRY).expect("Could not open registry");
+    let current: Conf = serde_json::from_reader(&file).expect("Could not read registry");
+    assert_eq!(current.children.len(), initial.children.len());
+    assert_ne!(
+        current.children, initial.children,
+        "Chaos should have respawned agents"
+    );
+
+    tshutdown.send(()).unwrap();
+    supervisor.await.unwrap();
+    let _ = std::fs::remove_file(REGISTRY);
+}
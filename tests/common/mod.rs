@@ -0,0 +1,34 @@
+//! Helpers shared by integration tests.
+//!
+//! Each test binary only uses some of them.
+#![allow(dead_code)]
+
+use std::future::Future;
+use std::path::PathBuf;
+
+use liars::start::StartArgs;
+
+/// Run the body of a test.
+pub fn run<F: Future>(test: F) -> F::Output {
+    liars::trace::init_logging();
+    tokio_test::block_on(test)
+}
+
+/// Start arguments spawning the agent binary built for this test.
+pub fn start_args() -> StartArgs {
+    StartArgs {
+        exe: PathBuf::from(env!("CARGO_BIN_EXE_liarslie")),
+        ..StartArgs::default()
+    }
+}
+
+/// Kill the agents started by a test and wait until they are dead.
+pub async fn stop(processes: Vec<tokio::process::Child>) {
+    let mut processes = processes;
+    for process in &mut processes {
+        process.kill().unwrap();
+    }
+    for process in processes {
+        process.await.expect("Could not wait for process");
+    }
+}
@@ -0,0 +1,96 @@
+extern crate liars;
+extern crate rand;
+extern crate tokio_test;
+
+mod common;
+
+use rand::Rng;
+
+use liars::decision::DecisionCertificate;
+use liars::playexpert::PlayExpertArgs;
+use liars::start::*;
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// Run `liarslie verify` on the certificate at `path`, return its output if it succeeds.
+fn verify(path: &std::path::Path) -> Option<String> {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_liarslie"))
+        .arg("verify")
+        .arg(path)
+        .arg("--agents")
+        .arg("agents.conf")
+        .output()
+        .expect("Could not run verify");
+    if output.status.success() {
+        Some(String::from_utf8(output.stdout).unwrap())
+    } else {
+        None
+    }
+}
+
+/// Check that players certify their decisions, and that anybody can check
+/// the certificate against the registry, once the agents are gone.
+async fn test_impl() {
+    let value = rand::thread_rng().gen_bool(0.5);
+    let start_args = StartArgs {
+        value,
+        liar_ratio: 0.2,
+        num_agents: 8,
+        ..common::start_args()
+    };
+    let (conf, processes) = start(&start_args).await;
+
+    // With parties of certificates, then with aggregates.
+    let mut paths = vec![];
+    for aggregate in &[false, true] {
+        let path = std::env::temp_dir().join(format!(
+            "liarslie-decision-{}-{}.json",
+            aggregate,
+            std::process::id()
+        ));
+        let play_expert_args = PlayExpertArgs {
+            liar_ratio: 0.2,
+            aggregate: *aggregate,
+            certificate: Some(path.clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            liars::playexpert::play(&play_expert_args).await,
+            Some(value)
+        );
+        paths.push(path);
+    }
+
+    common::stop(processes).await;
+
+    for path in &paths {
+        let certificate = DecisionCertificate::load(path).expect("Could not load certificate");
+        assert_eq!(certificate.value, value);
+        assert!(certificate.verify(&conf).unwrap() >= conf.quorum());
+        // Votes are not bound to a round, so certificates don't mention one.
+        let saved: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+        assert!(saved.get("round").is_none());
+        let output = verify(path).expect("Certificate should be valid");
+        assert!(output.starts_with(&format!("{}:", value)), "{}", output);
+
+        // Forged certificates are rejected.
+        let mut forged = certificate.clone();
+        forged.value = !value;
+        forged.aggregate.value = !value;
+        forged.save(path).unwrap();
+        assert_eq!(verify(path), None);
+
+        // So are certificates for another membership.
+        let mut stale = certificate.clone();
+        stale.epoch += 1;
+        stale.save(path).unwrap();
+        assert_eq!(verify(path), None);
+
+        let _ = std::fs::remove_file(path);
+    }
+    let _ = std::fs::remove_file("agents.conf");
+}
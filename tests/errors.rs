@@ -0,0 +1,110 @@
+extern crate liars;
+extern crate tokio_test;
+
+mod common;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use liars::agent::{ErrorCode, Response};
+use liars::play::PlayArgs;
+use liars::start::*;
+use liars::transcript::Transcript;
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// Send `line` on `stream`, read the response.
+async fn exchange(stream: &mut BufReader<tokio::net::TcpStream>, line: &str) -> Response {
+    stream
+        .get_mut()
+        .write_all(line.as_bytes())
+        .await
+        .expect("Could not write");
+    let mut response = String::new();
+    stream
+        .read_line(&mut response)
+        .await
+        .expect("Could not read");
+    serde_json::from_str(&response).expect("Invalid response")
+}
+
+/// Check that agents explain why they refuse requests, and that clients can tell.
+async fn test_impl() {
+    let token = std::env::temp_dir().join(format!("liarslie-errors-{}.token", std::process::id()));
+    let transcript =
+        std::env::temp_dir().join(format!("liarslie-errors-{}.transcript", std::process::id()));
+    let start_args = StartArgs {
+        value: true,
+        liar_ratio: 0.,
+        num_agents: 3,
+        admin_token: Some(token.clone()),
+        ..common::start_args()
+    };
+    let (conf, processes) = start(&start_args).await;
+
+    // The connection survives invalid requests.
+    let stream = tokio::net::TcpStream::connect(format!("127.0.0.1:{}", conf.children[0].socket))
+        .await
+        .expect("Could not connect");
+    let mut stream = BufReader::new(stream);
+    match exchange(&mut stream, "this is not json\n").await {
+        Response::Error {
+            code: ErrorCode::Malformed,
+            ..
+        } => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+    match exchange(&mut stream, "{\"message\":\"Frobnicate\"}\n").await {
+        Response::Error {
+            code: ErrorCode::Unsupported,
+            ..
+        } => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+    match exchange(&mut stream, "{\"message\":\"Stop\"}\n").await {
+        Response::Error {
+            code: ErrorCode::Unauthorized,
+            ..
+        } => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+    match exchange(&mut stream, "{\"message\":\"GetValue\"}\n").await {
+        Response::Certificate(certificate) => assert!(certificate.value),
+        other => panic!("Unexpected response {:?}", other),
+    }
+
+    // Players tell agents that can't be reached from agents that refuse to respond.
+    let mut registry = liars::conf::Conf {
+        epoch: 0,
+        children: conf.children.clone(),
+    };
+    registry.children.push(liars::conf::Child {
+        pid: 0,
+        socket: 1,
+        ..Default::default()
+    });
+    registry
+        .save(std::path::Path::new("agents.conf"))
+        .expect("Could not write agents.conf");
+    let play_args = PlayArgs {
+        transcript: Some(transcript.clone()),
+        ..Default::default()
+    };
+    assert_eq!(liars::play::play(&play_args).await, Some(true));
+    let recorded = Transcript::load(&transcript).expect("Could not load transcript");
+    let failed: Vec<_> = recorded
+        .entries
+        .iter()
+        .filter(|entry| entry.response.is_none())
+        .collect();
+    assert_eq!(failed.len(), 1);
+    assert!(failed[0].error.is_some());
+    assert_eq!(failed[0].refused, None);
+
+    common::stop(processes).await;
+    let _ = std::fs::remove_file(&token);
+    let _ = std::fs::remove_file(&transcript);
+    let _ = std::fs::remove_file("agents.conf");
+}
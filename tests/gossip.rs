@@ -0,0 +1,131 @@
+extern crate liars;
+extern crate rand;
+extern crate tokio_test;
+
+mod common;
+
+use rand::Rng;
+
+use liars::agent::{Message, RemoteAgent, Response};
+use liars::conf::{Child, Conf};
+use liars::gossip::View;
+use liars::playexpert::PlayExpertArgs;
+use liars::start::*;
+use liars::transcript::Transcript;
+
+const REGISTRY: &str = "gossip.conf";
+const TRANSCRIPT: &str = "gossip.transcript.json";
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// Removed peers stay removed, even if others still gossip about them.
+#[test]
+fn test_remove() {
+    let child = |pid, socket| Child {
+        pid,
+        socket,
+        weight: 1,
+        fingerprint: None,
+        key: None,
+        signing_key: None,
+    };
+    let view = View::new(child(1, 1001));
+    view.merge(&[child(2, 1002), child(3, 1003)]);
+    assert_eq!(view.peers().len(), 3);
+
+    view.remove(&child(2, 1002));
+    view.merge(&[child(2, 1002), child(3, 1003)]);
+    assert_eq!(view.peers(), vec![child(1, 1001), child(3, 1003)]);
+
+    // A new agent reusing the port is another peer.
+    view.merge(&[child(4, 1002)]);
+    assert_eq!(view.peers().len(), 3);
+}
+
+/// Play while only knowing a few agents.
+async fn test_impl() {
+    let value = rand::thread_rng().gen_bool(0.5);
+    let liar_ratio = 0.2;
+    let num_agents = 15;
+    let start_args = StartArgs {
+        value,
+        liar_ratio,
+        num_agents,
+        gossip: true,
+        ..common::start_args()
+    };
+    let (conf, processes) = start(&start_args).await;
+
+    // Wait until gossip has propagated the full fleet to some agent.
+    let remote = RemoteAgent::new(conf.children[num_agents - 1].clone());
+    let mut attempts = 0;
+    loop {
+        match remote.call(&Message::GetPeers).await {
+            Ok(Response::Peers(peers)) if peers.len() == num_agents => break,
+            Ok(Response::Peers(_)) => {}
+            other => panic!("Unexpected response {:?}", other),
+        }
+        attempts += 1;
+        assert!(attempts < 100, "Gossip should converge");
+        tokio::time::delay_for(std::time::Duration::from_millis(100)).await;
+    }
+
+    // Agents campaign among the peers they have discovered.
+    let play_expert_args = PlayExpertArgs {
+        liar_ratio,
+        discover: true,
+        ..Default::default()
+    };
+    let result = liars::playexpert::play(&play_expert_args).await;
+    assert_eq!(result, Some(value), "'playexpert' should use peers");
+
+    // Peers the client doesn't know about neither get asked nor count.
+    let partial = Conf {
+        epoch: conf.epoch,
+        children: conf.children[0..2].to_vec(),
+    };
+    partial
+        .save(std::path::Path::new(REGISTRY))
+        .expect("Could not write registry");
+    let play_expert_args = PlayExpertArgs {
+        path: std::path::PathBuf::from(REGISTRY),
+        liar_ratio,
+        discover: true,
+        transcript: Some(std::path::PathBuf::from(TRANSCRIPT)),
+        ..Default::default()
+    };
+    liars::playexpert::play(&play_expert_args).await;
+    let transcript =
+        Transcript::load(std::path::Path::new(TRANSCRIPT)).expect("Could not read transcript");
+    assert_eq!(transcript.quorum, 2);
+    assert!(transcript
+        .entries
+        .iter()
+        .all(|entry| partial.children.contains(&entry.child)));
+
+    common::stop(processes).await;
+    let _ = std::fs::remove_file(REGISTRY);
+    let _ = std::fs::remove_file(TRANSCRIPT);
+
+    // Without `--gossip`, agents only know themselves.
+    let start_args = StartArgs {
+        num_agents: 3,
+        ..common::start_args()
+    };
+    let (conf, processes) = start(&start_args).await;
+    tokio::time::delay_for(std::time::Duration::from_secs(1)).await;
+    for child in &conf.children {
+        match RemoteAgent::new(child.clone())
+            .call(&Message::GetPeers)
+            .await
+        {
+            Ok(Response::Peers(peers)) => assert_eq!(peers.len(), 1),
+            other => panic!("Unexpected response {:?}", other),
+        }
+    }
+    common::stop(processes).await;
+    let _ = std::fs::remove_file("agents.conf");
+}
@@ -0,0 +1,237 @@
+extern crate liars;
+extern crate tokio_test;
+
+mod common;
+
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+use liars::agent::{CallError, ErrorCode, Message, RemoteAgent, Response};
+use liars::conf::Child;
+use liars::gossip;
+use liars::limits::{Limits, RateLimiter};
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// Spawn an agent with additional arguments `args`.
+async fn spawn(args: &[&str]) -> (Child, tokio::process::Child) {
+    let mut process = tokio::process::Command::new(env!("CARGO_BIN_EXE_liarslie"))
+        .arg("agent")
+        .arg("--value")
+        .arg("true")
+        .args(args)
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .expect("Could not spawn agent");
+    let mut port = String::new();
+    BufReader::new(process.stdout.as_mut().unwrap())
+        .read_line(&mut port)
+        .await
+        .unwrap();
+    let child = Child {
+        pid: process.id(),
+        socket: port.trim_end().parse().unwrap(),
+        ..Default::default()
+    };
+    (child, process)
+}
+
+async fn connect(child: &Child) -> BufReader<tokio::net::TcpStream> {
+    let stream = tokio::net::TcpStream::connect(format!("127.0.0.1:{}", child.socket))
+        .await
+        .expect("Could not connect");
+    BufReader::new(stream)
+}
+
+/// Send `line` on `stream`, read the response.
+async fn exchange(stream: &mut BufReader<tokio::net::TcpStream>, line: &str) -> Response {
+    stream
+        .get_mut()
+        .write_all(line.as_bytes())
+        .await
+        .expect("Could not write");
+    let mut response = String::new();
+    stream
+        .read_line(&mut response)
+        .await
+        .expect("Could not read");
+    serde_json::from_str(&response).expect("Invalid response")
+}
+
+/// Check that agents refuse requests beyond their limits, and count them.
+async fn test_impl() {
+    let (limited, mut limited_process) = spawn(&[
+        "--max-line",
+        "256",
+        "--max-children",
+        "2",
+        "--max-connections",
+        "2",
+        "--idle-timeout",
+        "2000",
+    ])
+    .await;
+
+    // Connections beyond the limit are refused.
+    let mut first = connect(&limited).await;
+    let mut second = connect(&limited).await;
+    for stream in &mut [&mut first, &mut second] {
+        match exchange(stream, "{\"message\":\"GetValue\"}\n").await {
+            Response::Certificate(certificate) => assert!(certificate.value),
+            other => panic!("Unexpected response {:?}", other),
+        }
+    }
+    let mut third = connect(&limited).await;
+    let mut response = String::new();
+    third
+        .read_line(&mut response)
+        .await
+        .expect("Could not read");
+    match serde_json::from_str(&response).expect("Invalid response") {
+        Response::Error {
+            code: ErrorCode::Overloaded,
+            ..
+        } => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+
+    // Idle connections are closed, which frees their slot.
+    tokio::time::delay_for(std::time::Duration::from_secs(3)).await;
+    let mut received = vec![];
+    first
+        .read_to_end(&mut received)
+        .await
+        .expect("Could not read");
+    assert!(received.is_empty());
+    drop(first);
+    drop(second);
+    drop(third);
+
+    // Long requests are refused, then the connection is closed.
+    let mut stream = connect(&limited).await;
+    let line = format!(
+        "{{\"message\":\"GetValue\",\"padding\":\"{}\"}}\n",
+        "x".repeat(512)
+    );
+    match exchange(&mut stream, &line).await {
+        Response::Error {
+            code: ErrorCode::TooLarge,
+            ..
+        } => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+    let mut received = vec![];
+    let _ = stream.read_to_end(&mut received).await;
+    assert!(received.is_empty());
+
+    // Requests listing too many agents are refused.
+    let children = vec![limited.clone(), limited.clone(), limited.clone()];
+    match RemoteAgent::new(limited.clone())
+        .call(&Message::Campaign(children))
+        .await
+    {
+        Err(CallError::Refused {
+            code: ErrorCode::TooLarge,
+            ..
+        }) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+
+    // Rejections show up in metrics.
+    match RemoteAgent::new(limited).call(&Message::Metrics).await {
+        Ok(Response::Metrics(metrics)) => {
+            for reason in &[
+                "too_many_connections",
+                "idle_timeout",
+                "line_too_long",
+                "too_many_children",
+            ] {
+                let counter = format!("liarslie_rejected_total{{reason=\"{}\"}}", reason);
+                assert!(metrics.contains(&counter), "Missing {}", counter);
+            }
+        }
+        other => panic!("Unexpected response {:?}", other),
+    }
+
+    // Gossip cannot grow the view of an agent without bound.
+    let (gossiped, mut gossiped_process) = spawn(&[]).await;
+    let agent = RemoteAgent::new(gossiped);
+    for batch in 0..2 {
+        let peers: Vec<Child> = (0..1000)
+            .map(|i| Child {
+                pid: 1 + batch * 1000 + i,
+                socket: 1,
+                ..Default::default()
+            })
+            .collect();
+        match agent.call(&Message::Gossip(peers)).await {
+            Ok(Response::Peers(peers)) => assert!(peers.len() <= gossip::MAX_PEERS),
+            other => panic!("Unexpected response {:?}", other),
+        }
+    }
+    gossiped_process.kill().unwrap();
+    gossiped_process.await.expect("Could not wait for process");
+
+    // Connections beyond their rate are refused.
+    let (throttled, mut throttled_process) = spawn(&["--rate", "0.1", "--burst", "2"]).await;
+    let get_value = "{\"message\":\"GetValue\"}\n";
+    let mut noisy = connect(&throttled).await;
+    for _ in 0..2 {
+        match exchange(&mut noisy, get_value).await {
+            Response::Certificate(certificate) => assert!(certificate.value),
+            other => panic!("Unexpected response {:?}", other),
+        }
+    }
+    match exchange(&mut noisy, get_value).await {
+        Response::Error {
+            code: ErrorCode::Overloaded,
+            ..
+        } => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+    // Other connections, e.g. other agents of the fleet, are not throttled.
+    let mut quiet = connect(&throttled).await;
+    match exchange(&mut quiet, get_value).await {
+        Response::Certificate(certificate) => assert!(certificate.value),
+        other => panic!("Unexpected response {:?}", other),
+    }
+    match exchange(&mut noisy, get_value).await {
+        Response::Error {
+            code: ErrorCode::Overloaded,
+            ..
+        } => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+
+    limited_process.kill().unwrap();
+    throttled_process.kill().unwrap();
+    limited_process.await.expect("Could not wait for process");
+    throttled_process.await.expect("Could not wait for process");
+}
+
+/// Check that rate limiting refills buckets.
+#[test]
+fn test_rate_limiter() {
+    let limits = Limits {
+        rate: Some(10.),
+        burst: 2,
+        ..Default::default()
+    };
+    let mut limiter = RateLimiter::default();
+    assert!(limiter.allow(&limits));
+    assert!(limiter.allow(&limits));
+    assert!(!limiter.allow(&limits));
+
+    // After 200ms, the bucket is full again, but no fuller.
+    std::thread::sleep(Duration::from_millis(250));
+    assert!(limiter.allow(&limits));
+    assert!(limiter.allow(&limits));
+    assert!(!limiter.allow(&limits));
+
+    // Without a rate, anything goes.
+    assert!(limiter.allow(&Limits::default()));
+}
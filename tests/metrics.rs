@@ -0,0 +1,114 @@
+extern crate liars;
+extern crate tokio_test;
+
+mod common;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use liars::playexpert::PlayExpertArgs;
+use liars::start::StartArgs;
+use liars::supervisor::*;
+
+const REGISTRY: &str = "metrics.conf";
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// Send `GET path`, return the response.
+async fn get(port: u16, path: &str) -> String {
+    let mut stream =
+        liars::util::retry_future(|| tokio::net::TcpStream::connect(format!("127.0.0.1:{}", port)))
+            .await
+            .expect("Could not connect to metrics endpoint");
+    stream
+        .write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes())
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+/// Scrape the metrics of a fleet from its supervisor.
+async fn test_impl() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let start_args = StartArgs {
+        value: true,
+        liar_ratio: 0.,
+        num_agents: 4,
+        ..common::start_args()
+    };
+    let supervise_args = SuperviseArgs {
+        path: std::path::PathBuf::from(REGISTRY),
+        metrics: Some(port),
+        ..Default::default()
+    };
+    let supervisor = Supervisor::start(&start_args, supervise_args).await;
+    let conf = supervisor.conf();
+    let (tshutdown, rshutdown) = tokio::sync::oneshot::channel();
+    let running = tokio::spawn(supervisor.run(rshutdown));
+
+    let play_expert_args = PlayExpertArgs {
+        path: std::path::PathBuf::from(REGISTRY),
+        liar_ratio: 0.,
+        ..Default::default()
+    };
+    assert_eq!(liars::playexpert::play(&play_expert_args).await, Some(true));
+
+    let response = get(port, "/metrics").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.contains("liarslie_fleet_agents 4"), "{}", response);
+    for child in &conf.children {
+        assert!(
+            response.contains(&format!("liarslie_agent_up{{agent=\"{}\"}} 1", child.pid)),
+            "{}",
+            response
+        );
+        assert!(response.contains(&format!(
+            "liarslie_uptime_seconds{{agent=\"{}\"}}",
+            child.pid
+        )));
+    }
+    for slot in 0..4 {
+        assert!(response.contains(&format!(
+            "liarslie_agent_restarts_total{{slot=\"{}\"}} 0",
+            slot
+        )));
+        assert!(conf.children.iter().any(|child| response.contains(&format!(
+            "liarslie_agent_info{{slot=\"{}\",agent=\"{}\"}} 1",
+            slot, child.pid
+        ))));
+    }
+    // Every agent has campaigned once.
+    assert_eq!(
+        response
+            .lines()
+            .filter(
+                |line| line.starts_with("liarslie_campaign_duration_seconds_count")
+                    && line.ends_with(" 1")
+            )
+            .count(),
+        4
+    );
+    assert!(response.contains("type=\"Campaign\""));
+    // Each metric is described once.
+    assert_eq!(
+        response
+            .matches("# TYPE liarslie_requests_total counter")
+            .count(),
+        1
+    );
+
+    let response = get(port, "/").await;
+    assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+
+    tshutdown.send(()).unwrap();
+    running.await.unwrap();
+    let _ = std::fs::remove_file(REGISTRY);
+}
@@ -1,5 +1,3 @@
-#![feature(refcell_take)]
-
 use std::cell::RefCell;
 use std::rc::Rc;
 
@@ -7,6 +5,8 @@ extern crate liars;
 extern crate rand;
 extern crate tokio_test;
 
+mod common;
+
 use rand::Rng;
 
 use liars::play::PlayArgs;
@@ -20,7 +20,7 @@ impl Drop for ProcessCleanup {
     fn drop(&mut self) {
         for child in &mut self.processes {
             let mut borrow = child.borrow_mut();
-            let _ = borrow.as_mut().unwrap().kill().unwrap();
+            borrow.as_mut().unwrap().kill().unwrap();
         }
         let _ = std::fs::remove_file("agents.conf");
     }
@@ -28,8 +28,7 @@ impl Drop for ProcessCleanup {
 
 #[test]
 fn test() {
-    env_logger::init();
-    tokio_test::block_on(test_impl());
+    common::run(test_impl());
 }
 
 /// Test with a full quorum.
@@ -41,12 +40,11 @@ async fn test_impl() {
         let value = rand::thread_rng().gen_bool(0.5);
         let liar_ratio = rand::thread_rng().gen_range(0.0, 0.5);
         let num_agents = rand::thread_rng().gen_range(10, 50);
-        let exe = std::path::PathBuf::from(env!("CARGO_BIN_EXE_liarslie"));
         let start_args = StartArgs {
             value,
             liar_ratio,
             num_agents,
-            exe,
+            ..common::start_args()
         };
         // Cleanup processes on exit.
         let (conf, processes) = start(&start_args).await;
@@ -67,9 +65,7 @@ async fn test_impl() {
                 play_runs += 1;
                 // Test that `play` provides the right result.
                 eprintln!("...Testing play in this configuration");
-                let play_args = PlayArgs {
-                    path: std::path::PathBuf::from("agents.conf"),
-                };
+                let play_args = PlayArgs::default();
                 let result = liars::play::play(&play_args).await;
                 assert_eq!(
                     result.expect("We should have a result"),
@@ -81,8 +77,8 @@ async fn test_impl() {
                 // Test that `playexpert` provides the right result.
                 eprintln!("...Testing playexpert in this configuration");
                 let play_expert_args = PlayExpertArgs {
-                    path: std::path::PathBuf::from("agents.conf"),
                     liar_ratio,
+                    ..Default::default()
                 };
                 let result = liars::playexpert::play(&play_expert_args).await;
                 assert_eq!(
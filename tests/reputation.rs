@@ -0,0 +1,141 @@
+extern crate liars;
+extern crate rand;
+extern crate tokio_test;
+
+mod common;
+
+use rand::Rng;
+
+use liars::agent::{Message, RemoteAgent, Response};
+use liars::conf::Child;
+use liars::play::PlayArgs;
+use liars::playexpert::PlayExpertArgs;
+use liars::reputation::Reputation;
+use liars::start::*;
+use liars::transcript::Transcript;
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// Spot liars with `play`, avoid them with `playexpert`.
+async fn test_impl() {
+    let value = rand::thread_rng().gen_bool(0.5);
+    let num_agents = 8;
+    let start_args = StartArgs {
+        value,
+        liar_ratio: 0.25,
+        num_agents,
+        ..common::start_args()
+    };
+    let (conf, processes) = start(&start_args).await;
+
+    let mut liars = vec![];
+    for child in &conf.children {
+        match RemoteAgent::new(child.clone())
+            .call(&Message::GetValue)
+            .await
+        {
+            Ok(Response::Certificate(certificate)) => {
+                if certificate.value != value {
+                    liars.push(child.pid);
+                }
+            }
+            other => panic!("Unexpected response {:?}", other),
+        }
+    }
+    assert!(!liars.is_empty());
+
+    let path =
+        std::env::temp_dir().join(format!("liarslie-reputation-{}.json", std::process::id()));
+    let transcript = std::env::temp_dir().join(format!(
+        "liarslie-reputation-{}.transcript",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+
+    let play_args = PlayArgs {
+        reputation: Some(path.clone()),
+        ..Default::default()
+    };
+    for _ in 0..2 {
+        assert_eq!(liars::play::play(&play_args).await, Some(value));
+    }
+    let reputation = Reputation::load(&path).expect("Could not load reputation");
+    assert_eq!(reputation.agents.len(), num_agents);
+    let mut suspects: Vec<_> = reputation
+        .suspects()
+        .iter()
+        .map(|record| record.child.pid)
+        .collect();
+    suspects.sort();
+    liars.sort();
+    assert_eq!(suspects, liars);
+    for record in reputation.agents.values() {
+        assert_eq!(record.agreed + record.disagreed, 2);
+    }
+
+    // There are enough reliable agents to avoid all suspects.
+    let play_expert_args = PlayExpertArgs {
+        liar_ratio: 0.4,
+        transcript: Some(transcript.clone()),
+        reputation: Some(path.clone()),
+        avoid_suspects: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        liars::playexpert::play(&play_expert_args).await,
+        Some(value)
+    );
+    let recorded = Transcript::load(&transcript).expect("Could not load transcript");
+    assert!(!recorded.entries.is_empty());
+    for entry in &recorded.entries {
+        assert!(!liars.contains(&entry.child.pid));
+    }
+    // Only the issuers of the certificates seen by `playexpert` are recorded.
+    let reputation = Reputation::load(&path).expect("Could not load reputation");
+    let seen = reputation
+        .agents
+        .values()
+        .filter(|record| record.agreed + record.disagreed == 3)
+        .count();
+    assert!(seen >= conf.quorum());
+
+    common::stop(processes).await;
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&transcript);
+    let _ = std::fs::remove_file("agents.conf");
+}
+
+/// Check that concurrent updates never leave a partial file behind.
+#[test]
+fn test_concurrent_updates() {
+    let path = std::env::temp_dir().join(format!(
+        "liarslie-reputation-concurrent-{}.json",
+        std::process::id()
+    ));
+    let writers: Vec<_> = (0..8)
+        .map(|pid| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let child = Child {
+                    pid,
+                    socket: 1,
+                    ..Default::default()
+                };
+                for _ in 0..50 {
+                    liars::reputation::update(&path, true, &[(child.clone(), true)])
+                        .expect("Could not update reputation");
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    // Updates may be lost, but the file is always complete.
+    let reputation = Reputation::load(&path).expect("Could not load reputation");
+    assert!(!reputation.agents.is_empty());
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,51 @@
+extern crate liars;
+extern crate rand;
+extern crate tokio_test;
+
+mod common;
+
+use rand::Rng;
+
+use liars::playsample::*;
+use liars::start::*;
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+async fn test_impl() {
+    // A few sanity checks on the posterior.
+    assert_eq!(probability_true(10, 2, 0, 0), 0.5);
+    assert_eq!(probability_true(10, 2, 3, 0), 1.);
+    assert_eq!(probability_true(10, 2, 0, 3), 0.);
+    assert!(probability_true(10, 4, 2, 1) > 0.5);
+    assert!(probability_true(10, 4, 2, 1) < probability_true(10, 3, 2, 1));
+
+    let value = rand::thread_rng().gen_bool(0.5);
+    let liar_ratio = 0.2;
+    let num_agents = 40;
+    let start_args = StartArgs {
+        value,
+        liar_ratio,
+        num_agents,
+        ..common::start_args()
+    };
+    let (_, processes) = start(&start_args).await;
+
+    for _ in 0..5 {
+        let play_args = PlaySampleArgs {
+            path: std::path::PathBuf::from("agents.conf"),
+            liar_ratio,
+            confidence: 0.999,
+            batch: 3,
+        };
+        let outcome = liars::playsample::play(&play_args).await;
+        assert_eq!(outcome.value, Some(value), "{:?}", outcome);
+        assert!(outcome.confidence >= 0.999);
+        assert!(outcome.traffic.messages <= num_agents);
+    }
+
+    common::stop(processes).await;
+    let _ = std::fs::remove_file("agents.conf");
+}
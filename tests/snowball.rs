@@ -0,0 +1,86 @@
+extern crate liars;
+extern crate rand;
+extern crate tokio_test;
+
+mod common;
+
+use rand::Rng;
+
+use liars::agent::{Message, RemoteAgent, Response};
+use liars::playsnowball::PlaySnowballArgs;
+use liars::start::*;
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// Samples must be able to conclude, and for a single value only.
+#[test]
+fn test_parameters() {
+    for (sample, alpha, beta) in &[("5", "2", "5"), ("5", "6", "5"), ("5", "3", "0")] {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_liarslie"))
+            .args(["agent", "--value", "true", "--gossip", "--snowball"])
+            .args(["--snowball-sample", sample])
+            .args(["--snowball-alpha", alpha])
+            .args(["--snowball-beta", beta])
+            .stdin(std::process::Stdio::null())
+            .output()
+            .expect("Could not run agent");
+        assert!(!output.status.success());
+    }
+}
+
+/// Check that honest agents converge on their value despite stubborn liars.
+///
+/// With fewer liars than `--snowball-alpha` (3 by default), no sample can
+/// ever sway an honest agent, so the outcome doesn't depend on luck.
+async fn test_impl() {
+    let value = rand::thread_rng().gen_bool(0.5);
+    let num_agents = 15;
+    let start_args = StartArgs {
+        value,
+        liar_ratio: 0.15,
+        num_agents,
+        gossip: true,
+        snowball: true,
+        ..common::start_args()
+    };
+    let (conf, processes) = start(&start_args).await;
+
+    // Wait until every agent has decided.
+    let mut attempts = 0;
+    let mut agreeing = 0;
+    for child in &conf.children {
+        let remote = RemoteAgent::new(child.clone());
+        loop {
+            match remote.call(&Message::GetPreference).await {
+                Ok(Response::Preference {
+                    value: preference,
+                    decided: true,
+                }) => {
+                    if preference == value {
+                        agreeing += 1;
+                    }
+                    break;
+                }
+                Ok(Response::Preference { decided: false, .. }) => {}
+                other => panic!("Unexpected response {:?}", other),
+            }
+            attempts += 1;
+            assert!(attempts < 600, "Agents should converge");
+            tokio::time::delay_for(std::time::Duration::from_millis(100)).await;
+        }
+    }
+    // Everybody but the 2 liars.
+    assert_eq!(agreeing, num_agents - 2);
+
+    let play_args = PlaySnowballArgs {
+        path: std::path::PathBuf::from("agents.conf"),
+        ask: num_agents,
+    };
+    assert_eq!(liars::playsnowball::play(&play_args).await, Some(value));
+
+    common::stop(processes).await;
+    let _ = std::fs::remove_file("agents.conf");
+}
@@ -0,0 +1,112 @@
+extern crate liars;
+extern crate tokio_test;
+
+mod common;
+
+use std::collections::HashMap;
+
+use liars::conf::Conf;
+use liars::playexpert::PlayExpertArgs;
+use liars::start::{spawn_agent, SpawnArgs};
+use liars::trace;
+
+const REGISTRY: &str = "spans.conf";
+const TRACE_FILE: &str = "spans.jsonl";
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+struct Span {
+    trace: String,
+    parent: String,
+    name: String,
+    pid: String,
+}
+
+/// Reconstruct a round of `playexpert` from the spans of all processes.
+async fn test_impl() {
+    let _ = std::fs::remove_file(TRACE_FILE);
+    // Inherited by agents.
+    std::env::set_var(trace::TRACE_FILE, TRACE_FILE);
+
+    let exe = std::path::PathBuf::from(env!("CARGO_BIN_EXE_liarslie"));
+    let mut processes = vec![];
+    let mut children = vec![];
+    for value in &[true, true, false] {
+        let (proc, child) = spawn_agent(
+            &exe,
+            *value,
+            !*value,
+            std::process::Stdio::inherit(),
+            &SpawnArgs::default(),
+        )
+        .await
+        .expect("Could not spawn agent");
+        processes.push(proc);
+        children.push(child);
+    }
+    Conf { epoch: 0, children }
+        .save(std::path::Path::new(REGISTRY))
+        .expect("Could not write registry");
+
+    let play_expert_args = PlayExpertArgs {
+        path: std::path::PathBuf::from(REGISTRY),
+        liar_ratio: 0.,
+        ..Default::default()
+    };
+    assert_eq!(liars::playexpert::play(&play_expert_args).await, Some(true));
+
+    common::stop(processes).await;
+
+    let mut spans = HashMap::new();
+    for line in std::fs::read_to_string(TRACE_FILE).unwrap().lines() {
+        let request: serde_json::Value = serde_json::from_str(line).unwrap();
+        let resource = &request["resourceSpans"][0];
+        let pid = resource["resource"]["attributes"][1]["value"]["intValue"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let span = &resource["scopeSpans"][0]["spans"][0];
+        spans.insert(
+            span["spanId"].as_str().unwrap().to_string(),
+            Span {
+                trace: span["traceId"].as_str().unwrap().to_string(),
+                parent: span["parentSpanId"].as_str().unwrap().to_string(),
+                name: span["name"].as_str().unwrap().to_string(),
+                pid,
+            },
+        );
+    }
+
+    // A single round, rooted in the player.
+    let roots: Vec<_> = spans
+        .values()
+        .filter(|span| span.parent.is_empty())
+        .collect();
+    assert_eq!(roots.len(), 1);
+    let root = roots[0];
+    assert_eq!(root.name, "playexpert");
+    assert_eq!(root.pid, format!("{}", std::process::id()));
+    assert!(spans.values().all(|span| span.trace == root.trace));
+
+    // Every span but the root has a parent in the round.
+    let parent = |span: &Span| &spans[&span.parent];
+    let count = |name: &str, parent_name: &str| {
+        spans
+            .values()
+            .filter(|span| span.name == name && !span.parent.is_empty())
+            .filter(|span| parent(span).name == parent_name)
+            .count()
+    };
+    // The player calls every agent, each agent campaigns among 3 agents.
+    assert_eq!(count("call Campaign", "playexpert"), 3);
+    assert_eq!(count("handle Campaign", "call Campaign"), 3);
+    assert_eq!(count("call GetValue", "handle Campaign"), 9);
+    assert_eq!(count("handle GetValue", "call GetValue"), 9);
+    assert_eq!(spans.len(), 1 + 3 + 3 + 9 + 9);
+
+    let _ = std::fs::remove_file(REGISTRY);
+    let _ = std::fs::remove_file(TRACE_FILE);
+}
@@ -0,0 +1,102 @@
+extern crate liars;
+extern crate rand;
+extern crate tokio_test;
+
+mod common;
+
+use rand::Rng;
+
+use liars::agent::{Message, RemoteAgent, Response};
+use liars::playexpert::PlayExpertArgs;
+use liars::start::*;
+use liars::stats;
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// Measure the traffic of `playexpert`.
+async fn test_impl() {
+    let value = rand::thread_rng().gen_bool(0.5);
+    let liar_ratio = 0.2;
+    let num_agents = 8;
+    let start_args = StartArgs {
+        value,
+        liar_ratio,
+        num_agents,
+        ..common::start_args()
+    };
+    let (conf, processes) = start(&start_args).await;
+
+    let play_expert_args = PlayExpertArgs {
+        liar_ratio,
+        ..Default::default()
+    };
+    let before = stats::traffic();
+    assert_eq!(
+        liars::playexpert::play(&play_expert_args).await,
+        Some(value)
+    );
+    let traffic = stats::traffic().since(&before);
+    let interlocutors = (num_agents as f64 * (1.0 - liar_ratio)) as usize + 1;
+
+    // Client side.
+    assert_eq!(traffic.messages, interlocutors);
+    assert_eq!(traffic.connections, interlocutors);
+    assert!(traffic.bytes_sent > 0);
+    assert!(traffic.bytes_received > 0);
+    let local = stats::stats();
+    assert_eq!(
+        local.outgoing.by_message["Campaign"].messages,
+        interlocutors
+    );
+    assert_eq!(local.outgoing.by_peer.len(), interlocutors);
+
+    // Agent side: each interlocutor has asked every agent for its vote.
+    let ports: Vec<u16> = conf.children.iter().map(|child| child.socket).collect();
+    let mut get_vote = 0;
+    let mut campaign = 0;
+    let mut from_agents = 0;
+    for child in &conf.children {
+        match RemoteAgent::new(child.clone()).call(&Message::Stats).await {
+            Ok(Response::Stats(stats)) => {
+                if let Some(traffic) = stats.outgoing.by_message.get("GetVote") {
+                    get_vote += traffic.messages;
+                }
+                if let Some(traffic) = stats.incoming.by_message.get("Campaign") {
+                    campaign += traffic.messages;
+                    assert!(traffic.bytes_received > 0);
+                }
+                // Agents identify themselves, the player doesn't.
+                for (port, traffic) in &stats.incoming.by_peer {
+                    assert!(ports.contains(port));
+                    from_agents += traffic.messages;
+                }
+            }
+            other => panic!("Unexpected response {:?}", other),
+        }
+    }
+    assert_eq!(campaign, interlocutors);
+    assert_eq!(get_vote, interlocutors * num_agents);
+    assert_eq!(from_agents, interlocutors * num_agents);
+
+    // Players print their traffic per message type and per agent.
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_liarslie"))
+        .arg("playexpert")
+        .arg("--liar-ratio")
+        .arg(format!("{}", liar_ratio))
+        .output()
+        .await
+        .expect("Could not run playexpert");
+    assert!(output.status.success());
+    let output = String::from_utf8(output.stdout).unwrap();
+    let mut lines = output.lines();
+    assert_eq!(lines.next(), Some(format!("{}", value).as_str()));
+    assert!(lines.next().unwrap().starts_with("messages: "));
+    assert!(output.contains(&format!("  Campaign: messages: {}, ", interlocutors)));
+    assert_eq!(output.matches("  port ").count(), interlocutors);
+
+    common::stop(processes).await;
+    let _ = std::fs::remove_file("agents.conf");
+}
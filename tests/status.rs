@@ -0,0 +1,133 @@
+extern crate liars;
+extern crate tokio_test;
+
+mod common;
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use liars::agent::{Message, RemoteAgent, Response};
+use liars::conf::Child;
+use liars::start::*;
+use liars::status::{Status, Strategy};
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// Spawn an agent with additional arguments `args`.
+async fn spawn(args: &[&str]) -> (Child, tokio::process::Child) {
+    let mut process = tokio::process::Command::new(env!("CARGO_BIN_EXE_liarslie"))
+        .arg("agent")
+        .arg("--value")
+        .arg("true")
+        .args(args)
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .expect("Could not spawn agent");
+    let mut port = String::new();
+    BufReader::new(process.stdout.as_mut().unwrap())
+        .read_line(&mut port)
+        .await
+        .unwrap();
+    let child = Child {
+        pid: process.id(),
+        socket: port.trim_end().parse().unwrap(),
+        ..Default::default()
+    };
+    (child, process)
+}
+
+async fn status(child: &Child) -> Status {
+    match RemoteAgent::new(child.clone()).call(&Message::Status).await {
+        Ok(Response::Status(status)) => status,
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
+
+/// Check that agents describe themselves, only disclose their strategy if
+/// asked to, and that `status` flags the agents it can't reach.
+async fn test_impl() {
+    let num_agents = 4;
+    let start_args = StartArgs {
+        value: true,
+        liar_ratio: 0.25,
+        num_agents,
+        ..common::start_args()
+    };
+    let (mut conf, processes) = start(&start_args).await;
+
+    let mut liars = 0;
+    for child in &conf.children {
+        let first = status(child).await;
+        if !first.value {
+            liars += 1;
+        }
+        assert_eq!(first.pid, child.pid);
+        assert_eq!(first.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(first.strategy, None);
+        assert_eq!(first.epoch, Some(conf.epoch));
+        let second = status(child).await;
+        assert!(second.uptime_ms >= first.uptime_ms);
+        assert!(second.requests["Status"] > first.requests.get("Status").cloned().unwrap_or(0));
+        // At least the connection of the request itself.
+        assert!(second.connections >= 1);
+    }
+    assert_eq!(liars, 1);
+
+    // Strategies are only disclosed with `--debug-status`.
+    let (honest, mut honest_process) = spawn(&["--debug-status"]).await;
+    assert_eq!(status(&honest).await.strategy, Some(Strategy::Honest));
+    let (stubborn, mut stubborn_process) =
+        spawn(&["--debug-status", "--gossip", "--snowball", "--stubborn"]).await;
+    assert_eq!(status(&stubborn).await.strategy, Some(Strategy::Stubborn));
+    let (equivocating, mut equivocating_process) = spawn(&["--debug-status", "--equivocate"]).await;
+    assert_eq!(
+        status(&equivocating).await.strategy,
+        Some(Strategy::Equivocating)
+    );
+    for process in &mut [
+        &mut honest_process,
+        &mut stubborn_process,
+        &mut equivocating_process,
+    ] {
+        process.kill().unwrap();
+    }
+
+    // The killed agents are still in the registry.
+    conf.children.push(honest);
+    conf.save(std::path::Path::new("agents.conf")).unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_liarslie"))
+        .arg("status")
+        .arg("--agents")
+        .arg("agents.conf")
+        .output()
+        .expect("Could not run status");
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.matches("UNREACHABLE").count(), 1, "{}", stdout);
+    assert_eq!(
+        stdout.lines().filter(|line| line.ends_with("  ok")).count(),
+        num_agents,
+        "{}",
+        stdout
+    );
+    // Each agent tells the epoch of its registry.
+    let epoch = conf.epoch.to_string();
+    assert!(
+        stdout
+            .lines()
+            .filter(|line| line.ends_with("  ok"))
+            .all(|line| line.split_whitespace().nth(6) == Some(epoch.as_str())),
+        "{}",
+        stdout
+    );
+
+    common::stop(processes).await;
+    honest_process.await.expect("Could not wait for process");
+    stubborn_process.await.expect("Could not wait for process");
+    equivocating_process
+        .await
+        .expect("Could not wait for process");
+    let _ = std::fs::remove_file("agents.conf");
+}
@@ -0,0 +1,172 @@
+extern crate liars;
+extern crate rand;
+extern crate tokio_test;
+
+mod common;
+
+use rand::Rng;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use liars::accountability;
+use liars::agent::{Message, RemoteAgent, Response};
+use liars::conf::Child;
+use liars::playexpert::PlayExpertArgs;
+use liars::start::*;
+use liars::transcript::Transcript;
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// How long the slow agent takes to fail.
+const DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How long each ally takes to vouch, see `test_impl`.
+const ALLY_DELAY: std::time::Duration = std::time::Duration::from_millis(20);
+
+/// The number of connections served by `child`, besides the one asking.
+async fn connections(child: &Child) -> usize {
+    match RemoteAgent::new(child.clone()).call(&Message::Status).await {
+        Ok(Response::Status(status)) => status.connections - 1,
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
+
+/// Check that agents stream their allies, and that players decide without
+/// waiting for the slowest campaign.
+async fn test_impl() {
+    let value = rand::thread_rng().gen_bool(0.5);
+    let num_agents = 6;
+    let start_args = StartArgs {
+        value,
+        liar_ratio: 0.,
+        num_agents,
+        ..common::start_args()
+    };
+    let (mut conf, processes) = start(&start_args).await;
+
+    // Votes arrive one by one, then the end of the stream.
+    let mut streamed = 0;
+    let votes = RemoteAgent::new(conf.children[0].clone())
+        .stream(&Message::StreamCampaign(conf.children.clone()), |vote| {
+            assert!(vote.verify(&conf.children));
+            streamed += 1;
+        })
+        .await
+        .expect("Could not stream");
+    assert_eq!(votes.len(), num_agents);
+    assert_eq!(streamed, num_agents);
+    assert!(votes.iter().all(|vote| vote.value == value));
+
+    // A slow agent, last in the registry, which takes a while to fail.
+    let mut slow = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Could not bind");
+    conf.children.push(Child {
+        pid: 0,
+        socket: slow.local_addr().unwrap().port(),
+        ..Default::default()
+    });
+    conf.save(std::path::Path::new("agents.conf")).unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = slow.accept().await {
+            tokio::spawn(async move {
+                let mut stream = BufReader::new(stream);
+                let mut line = String::new();
+                let _ = stream.read_line(&mut line).await;
+                tokio::time::delay_for(DELAY).await;
+            });
+        }
+    });
+
+    // Every campaign ends with the slow agent, but players don't wait for it.
+    let path = std::env::temp_dir().join(format!("liarslie-stream-{}.json", std::process::id()));
+    let play_expert_args = PlayExpertArgs {
+        liar_ratio: 0.,
+        transcript: Some(path.clone()),
+        stream: true,
+        ..Default::default()
+    };
+    let started = std::time::Instant::now();
+    assert_eq!(
+        liars::playexpert::play(&play_expert_args).await,
+        Some(value)
+    );
+    assert!(started.elapsed() < DELAY, "{:?}", started.elapsed());
+
+    // The transcript records the votes received before deciding.
+    let transcript = Transcript::load(&path).expect("Could not load transcript");
+    assert_eq!(transcript.entries.len(), num_agents + 1);
+    assert!(transcript.entries.iter().any(|entry| match entry.response {
+        Some(Response::Quorum(ref party)) => party.len() >= transcript.quorum,
+        _ => false,
+    }));
+    assert_eq!(transcript.replay(), Some(value));
+
+    // A client that gives up on a stream doesn't hold the connection: many
+    // allies, more than the agent buffers, each vouching after a while.
+    let mut allies = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Could not bind");
+    let port = allies.local_addr().unwrap().port();
+    let ally = move |pid: u32| Child {
+        pid,
+        socket: port,
+        signing_key: Some(accountability::public_key()),
+        ..Default::default()
+    };
+    let children: Vec<_> = (1..=48).map(ally).collect();
+    let epoch = conf.epoch;
+    tokio::spawn(async move {
+        // Agents ask their allies one at a time, in order, for votes bound
+        // to the epoch of their registry.
+        let mut pid = 0;
+        while let Ok((stream, _)) = allies.accept().await {
+            pid += 1;
+            let certificate = accountability::sign_in_epoch(value, &ally(pid), epoch);
+            tokio::spawn(async move {
+                let mut stream = BufReader::new(stream);
+                let mut line = String::new();
+                let _ = stream.read_line(&mut line).await;
+                tokio::time::delay_for(ALLY_DELAY).await;
+                let mut response =
+                    serde_json::to_string(&Response::Certificate(certificate)).unwrap();
+                response.push('\n');
+                let _ = stream.get_mut().write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    let target = conf.children[0].clone();
+    let mut stream = BufReader::new(
+        tokio::net::TcpStream::connect(format!("127.0.0.1:{}", target.socket))
+            .await
+            .expect("Could not connect"),
+    );
+    let mut request =
+        serde_json::to_string(&serde_json::json!({ "message": Message::StreamCampaign(children) }))
+            .unwrap();
+    request.push('\n');
+    stream
+        .get_mut()
+        .write_all(request.as_bytes())
+        .await
+        .expect("Could not write");
+    let mut line = String::new();
+    stream.read_line(&mut line).await.expect("Could not read");
+    match serde_json::from_str(&line).expect("Invalid response") {
+        Response::Vote(vote) => assert_eq!(vote.issuer.pid, 1),
+        other => panic!("Unexpected response {:?}", other),
+    }
+    drop(stream);
+    let mut attempts = 0;
+    while connections(&target).await > 0 {
+        attempts += 1;
+        assert!(attempts < 200, "The connection should be closed");
+        tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+    }
+
+    common::stop(processes).await;
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file("agents.conf");
+}
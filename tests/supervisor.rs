@@ -0,0 +1,230 @@
+extern crate liars;
+extern crate rand;
+extern crate tokio_test;
+
+mod common;
+
+use std::os::unix::fs::PermissionsExt;
+
+use liars::conf::{Child, Conf};
+use liars::start::StartArgs;
+use liars::supervisor::*;
+
+const REGISTRY: &str = "supervisor.conf";
+const CONTROL: &str = "supervisor-test.sock";
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+async fn status(supervisor: &RemoteSupervisor) -> Vec<AgentStatus> {
+    match supervisor.call(&Command::Status).await {
+        Ok(Reply::Status(agents)) => agents,
+        other => panic!("Unexpected reply {:?}", other),
+    }
+}
+
+async fn membership(supervisor: &RemoteSupervisor) -> Conf {
+    match supervisor.call(&Command::Membership).await {
+        Ok(Reply::Membership(conf)) => conf,
+        other => panic!("Unexpected reply {:?}", other),
+    }
+}
+
+/// Wait until the supervisor has respawned every agent we killed.
+async fn wait_for_restarts(supervisor: &RemoteSupervisor, restarts: usize) -> Vec<AgentStatus> {
+    loop {
+        let agents = status(supervisor).await;
+        if agents.iter().map(|agent| agent.restarts).sum::<usize>() >= restarts {
+            return agents;
+        }
+        tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+    }
+}
+
+/// Drive a fleet through its control socket.
+async fn test_impl() {
+    let value = true;
+    let start_args = StartArgs {
+        value,
+        liar_ratio: 0.,
+        num_agents: 5,
+        ..common::start_args()
+    };
+    let supervise_args = SuperviseArgs {
+        path: std::path::PathBuf::from(REGISTRY),
+        control: Some(std::path::PathBuf::from(CONTROL)),
+        ..Default::default()
+    };
+    let supervisor = Supervisor::start(&start_args, supervise_args).await;
+    let (_tshutdown, rshutdown) = tokio::sync::oneshot::channel();
+    let running = tokio::spawn(supervisor.run(rshutdown));
+
+    let remote = RemoteSupervisor::new(std::path::Path::new(CONTROL));
+    // Wait until the control socket is open.
+    liars::util::retry_future(|| remote.call(&Command::Status))
+        .await
+        .expect("Could not reach supervisor");
+    // Only we may send commands.
+    let mode = std::fs::metadata(CONTROL).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let agents = status(&remote).await;
+    assert_eq!(agents.len(), 5);
+    assert!(agents.iter().all(|agent| agent.value == value));
+
+    // Add agents, a few at a time.
+    assert!(matches!(
+        remote.call(&Command::AddAgents(MAX_ADD_AGENTS + 1)).await,
+        Ok(Reply::Error(_))
+    ));
+    assert!(matches!(
+        remote.call(&Command::AddAgents(2)).await,
+        Ok(Reply::Ok)
+    ));
+    let agents = status(&remote).await;
+    assert_eq!(agents.len(), 7);
+
+    // Agents added after one has left take over its slot.
+    let (slot, left) = (agents[1].slot, agents[1].child.clone());
+    assert!(matches!(
+        remote.call(&Command::Leave(left.clone())).await,
+        Ok(Reply::Ok)
+    ));
+    assert!(matches!(
+        remote.call(&Command::AddAgents(1)).await,
+        Ok(Reply::Ok)
+    ));
+    let agents = status(&remote).await;
+    assert_eq!(agents.len(), 7);
+    assert!(agents.iter().all(|agent| agent.slot < 7));
+    let reused = agents.iter().find(|agent| agent.slot == slot).unwrap();
+    assert_ne!(reused.child.pid, left.pid);
+
+    // Restart an agent.
+    let pid = agents[0].child.pid;
+    assert!(matches!(
+        remote.call(&Command::Restart(pid)).await,
+        Ok(Reply::Ok)
+    ));
+    let agents = wait_for_restarts(&remote, 1).await;
+    assert_ne!(agents[0].child.pid, pid);
+    assert!(matches!(
+        remote.call(&Command::Restart(pid)).await,
+        Ok(Reply::Error(_))
+    ));
+
+    // Turn some agents into liars.
+    assert!(matches!(
+        remote.call(&Command::SetLiars(2)).await,
+        Ok(Reply::Ok)
+    ));
+    let agents = wait_for_restarts(&remote, 3).await;
+    assert_eq!(
+        agents.iter().filter(|agent| agent.value != value).count(),
+        2
+    );
+
+    // The registry follows.
+    let play_args = liars::play::PlayArgs {
+        path: std::path::PathBuf::from(REGISTRY),
+        ..Default::default()
+    };
+    assert_eq!(liars::play::play(&play_args).await, Some(value));
+
+    // An agent started independently joins the fleet.
+    let before = membership(&remote).await;
+    let mut guest = tokio::process::Command::new(env!("CARGO_BIN_EXE_liarslie"))
+        .arg("agent")
+        .arg("--value")
+        .arg(if value { "true" } else { "false" })
+        .arg("--join")
+        .arg(CONTROL)
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .expect("Could not spawn agent");
+    let after = loop {
+        let conf = membership(&remote).await;
+        if conf.children.iter().any(|child| child.pid == guest.id()) {
+            break conf;
+        }
+        tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+    };
+    assert!(after.epoch > before.epoch);
+    assert_eq!(after.children.len(), before.children.len() + 1);
+
+    // Clients may fetch the membership from the supervisor.
+    let play_args = liars::play::PlayArgs {
+        path: std::path::PathBuf::from(CONTROL),
+        ..Default::default()
+    };
+    assert_eq!(liars::play::play(&play_args).await, Some(value));
+
+    // The agent leaves when terminated.
+    std::process::Command::new("kill")
+        .arg("-TERM")
+        .arg(format!("{}", guest.id()))
+        .status()
+        .expect("Could not send signal");
+    assert!((&mut guest).await.unwrap().success());
+    let after_leave = membership(&remote).await;
+    assert!(after_leave.epoch > after.epoch);
+    assert_eq!(after_leave.children, before.children);
+
+    // Guests cannot make themselves heavier than other agents.
+    let heavy = Child {
+        pid: 1,
+        socket: 1,
+        weight: 100,
+        ..Default::default()
+    };
+    assert!(matches!(
+        remote.call(&Command::Join(heavy.clone())).await,
+        Ok(Reply::Ok)
+    ));
+    let joined = membership(&remote).await;
+    let guest_weight = joined
+        .children
+        .iter()
+        .find(|child| **child == heavy)
+        .map(|child| child.weight);
+    assert_eq!(guest_weight, Some(1));
+    assert!(matches!(
+        remote.call(&Command::Leave(heavy)).await,
+        Ok(Reply::Ok)
+    ));
+
+    // An agent that dies without leaving is removed once it stops responding.
+    let mut guest = tokio::process::Command::new(env!("CARGO_BIN_EXE_liarslie"))
+        .arg("agent")
+        .arg("--value")
+        .arg(if value { "true" } else { "false" })
+        .arg("--join")
+        .arg(CONTROL)
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .expect("Could not spawn agent");
+    while !membership(&remote)
+        .await
+        .children
+        .iter()
+        .any(|child| child.pid == guest.id())
+    {
+        tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+    }
+    guest.kill().unwrap();
+    (&mut guest).await.unwrap();
+    let mut attempts = 0;
+    while membership(&remote).await.children != before.children {
+        attempts += 1;
+        assert!(attempts < 200, "Dead guests should be removed");
+        tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+    }
+
+    // Stop everything.
+    assert!(matches!(remote.call(&Command::Stop).await, Ok(Reply::Ok)));
+    running.await.unwrap();
+    assert!(!std::path::Path::new(CONTROL).exists());
+    let _ = std::fs::remove_file(REGISTRY);
+}
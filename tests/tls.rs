@@ -0,0 +1,87 @@
+extern crate liars;
+extern crate rand;
+extern crate tokio_test;
+
+mod common;
+
+use rand::Rng;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use liars::agent::{Message, RemoteAgent, Response};
+use liars::play::PlayArgs;
+use liars::playexpert::PlayExpertArgs;
+use liars::start::*;
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// Play with agents that only speak TLS, check that they only talk to clients that pin their certificate.
+async fn test_impl() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = std::env::temp_dir().join(format!("liarslie-tls-{}", std::process::id()));
+    let value = rand::thread_rng().gen_bool(0.5);
+    let start_args = StartArgs {
+        value,
+        liar_ratio: 0.2,
+        num_agents: 5,
+        tls: Some(dir.clone()),
+        gossip: true,
+        ..common::start_args()
+    };
+    let (conf, processes) = start(&start_args).await;
+    // Certificates are self-signed, there is no authority to trust.
+    assert!(!dir.join("ca.pem").exists());
+    assert!(dir.join("agent-0.pem").exists());
+    let mode = std::fs::metadata(dir.join("agent-0.key"))
+        .expect("Missing private key")
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o077, 0);
+    for child in &conf.children {
+        assert_eq!(child.fingerprint.as_ref().map(String::len), Some(64));
+    }
+
+    // Clients and agents talk over TLS.
+    let play_args = PlayArgs::default();
+    assert_eq!(liars::play::play(&play_args).await, Some(value));
+    let play_expert_args = PlayExpertArgs {
+        liar_ratio: 0.2,
+        discover: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        liars::playexpert::play(&play_expert_args).await,
+        Some(value)
+    );
+
+    // Agents don't listen to plaintext.
+    let target = conf.children[0].clone();
+    let mut stream = tokio::net::TcpStream::connect(format!("127.0.0.1:{}", target.socket))
+        .await
+        .expect("Could not connect");
+    stream
+        .write_all(b"{\"message\":\"Stop\"}\n")
+        .await
+        .expect("Could not write");
+    let mut received = vec![];
+    let _ = stream.read_to_end(&mut received).await;
+    assert!(received.is_empty());
+
+    // Clients don't accept an agent that presents another certificate.
+    let mut impostor = target.clone();
+    impostor.fingerprint = conf.children[1].fingerprint.clone();
+    assert!(RemoteAgent::new(impostor)
+        .call(&Message::GetValue)
+        .await
+        .is_err());
+    match RemoteAgent::new(target).call(&Message::GetValue).await {
+        Ok(Response::Certificate(certificate)) => assert!(certificate.verify(&conf.children)),
+        other => panic!("Unexpected response {:?}", other),
+    }
+
+    common::stop(processes).await;
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_file("agents.conf");
+}
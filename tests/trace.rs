@@ -0,0 +1,79 @@
+extern crate liars;
+extern crate tokio_test;
+
+mod common;
+
+use tokio::io::AsyncBufReadExt;
+
+use liars::agent::{Message, RemoteAgent, Response};
+use liars::start::{spawn_agent, SpawnArgs};
+use liars::trace;
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// Follow a round across the JSON logs of several agents.
+async fn test_impl() {
+    // Inherited by agents.
+    std::env::set_var(trace::LOG_FORMAT, "json");
+    std::env::set_var("RUST_LOG", "agent=debug,campaign=debug");
+
+    let exe = std::path::PathBuf::from(env!("CARGO_BIN_EXE_liarslie"));
+    let mut processes = vec![];
+    let mut children = vec![];
+    let mut logs = vec![];
+    for _ in 0..3 {
+        let (mut proc, child) = spawn_agent(
+            &exe,
+            true,
+            false,
+            std::process::Stdio::piped(),
+            &SpawnArgs::default(),
+        )
+        .await
+        .expect("Could not spawn agent");
+        let stderr = proc.stderr.take().unwrap();
+        logs.push(tokio::spawn(async move {
+            let mut lines = tokio::io::BufReader::new(stderr).lines();
+            let mut collected = vec![];
+            while let Ok(Some(line)) = lines.next_line().await {
+                collected.push(line);
+            }
+            collected
+        }));
+        processes.push(proc);
+        children.push(child);
+    }
+
+    let round = trace::new_id();
+    let remote = RemoteAgent::new(children[0].clone());
+    let response = trace::scope(
+        Some(round),
+        remote.call(&Message::Campaign(children.clone())),
+    )
+    .await;
+    match response {
+        Ok(Response::Quorum(party)) => assert_eq!(party.len(), 3),
+        other => panic!("Unexpected response {:?}", other),
+    }
+
+    common::stop(processes).await;
+
+    // Every agent has logged the round: the first one while campaigning,
+    // all of them while responding to `GetValue`.
+    let expected = format!("{:016x}", round);
+    for (log, child) in logs.into_iter().zip(children.iter()) {
+        let lines = log.await.unwrap();
+        let traced: Vec<serde_json::Value> = lines
+            .iter()
+            .map(|line| serde_json::from_str(line).expect("Log line should be JSON"))
+            .filter(|line: &serde_json::Value| line["trace"] == expected.as_str())
+            .collect();
+        assert!(!traced.is_empty(), "{:?}", lines);
+        assert!(traced
+            .iter()
+            .all(|line| line["pid"] == child.pid && line["msg"].is_string()));
+    }
+}
@@ -0,0 +1,82 @@
+extern crate liars;
+extern crate rand;
+extern crate tokio_test;
+
+mod common;
+
+use rand::Rng;
+
+use liars::agent::Response;
+use liars::play::PlayArgs;
+use liars::playexpert::PlayExpertArgs;
+use liars::start::*;
+use liars::transcript::{Strategy, Transcript};
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// Record rounds of `play` and `playexpert`, replay them offline.
+async fn test_impl() {
+    let value = rand::thread_rng().gen_bool(0.5);
+    let liar_ratio = 0.2;
+    let num_agents = 8;
+    let start_args = StartArgs {
+        value,
+        liar_ratio,
+        num_agents,
+        ..common::start_args()
+    };
+    let (_, processes) = start(&start_args).await;
+
+    let path =
+        std::env::temp_dir().join(format!("liarslie-transcript-{}.json", std::process::id()));
+
+    // `play` asks every agent.
+    let play_args = PlayArgs {
+        transcript: Some(path.clone()),
+        ..Default::default()
+    };
+    assert_eq!(liars::play::play(&play_args).await, Some(value));
+    let mut transcript = Transcript::load(&path).expect("Could not load transcript");
+    assert_eq!(transcript.strategy, Strategy::Play);
+    assert_eq!(transcript.result, Some(value));
+    assert_eq!(transcript.entries.len(), num_agents);
+    assert!(transcript.trace.is_some());
+    assert_eq!(transcript.replay(), Some(value));
+    liars::transcript::replay(&path).expect("Replay should match");
+
+    // Tampering with the recorded responses changes the decision: the
+    // signatures don't match anymore, so the certificates are ignored.
+    for entry in &mut transcript.entries {
+        if let Some(Response::Certificate(ref mut certificate)) = entry.response {
+            certificate.value = !value;
+        }
+    }
+    assert_eq!(transcript.replay(), None);
+    transcript.save(&path).expect("Could not save transcript");
+    assert!(liars::transcript::replay(&path).is_err());
+
+    // `playexpert` only asks some agents.
+    let play_expert_args = PlayExpertArgs {
+        liar_ratio,
+        transcript: Some(path.clone()),
+        ..Default::default()
+    };
+    assert_eq!(
+        liars::playexpert::play(&play_expert_args).await,
+        Some(value)
+    );
+    let transcript = Transcript::load(&path).expect("Could not load transcript");
+    assert_eq!(transcript.strategy, Strategy::PlayExpert);
+    assert_eq!(
+        transcript.entries.len(),
+        (num_agents as f64 * (1.0 - liar_ratio)) as usize + 1
+    );
+    assert_eq!(transcript.replay(), Some(value));
+
+    common::stop(processes).await;
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file("agents.conf");
+}
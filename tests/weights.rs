@@ -0,0 +1,113 @@
+extern crate liars;
+extern crate rand;
+extern crate tokio_test;
+
+mod common;
+
+use rand::Rng;
+
+use liars::conf::{Child, Conf};
+use liars::play::PlayArgs;
+use liars::playexpert::PlayExpertArgs;
+use liars::start::*;
+
+#[test]
+fn test() {
+    common::run(test_impl());
+}
+
+/// Start weighted fleets, check that the decision follows the weight rather than the headcount.
+async fn test_impl() {
+    // Registries without weights give weight 1 to every agent.
+    let child: Child = serde_json::from_str(r#"{ "pid": 1, "socket": 2 }"#).unwrap();
+    assert_eq!(child.weight, 1);
+
+    let num_agents = 10;
+    for &weights in &Weights::ALL {
+        let value = rand::thread_rng().gen_bool(0.5);
+        let start_args = StartArgs {
+            value,
+            liar_ratio: 0.3,
+            num_agents,
+            weights,
+            ..common::start_args()
+        };
+        let (conf, processes) = start(&start_args).await;
+        let reloaded = Conf::load(std::path::Path::new("agents.conf"))
+            .await
+            .expect("Could not read agents.conf");
+        assert_eq!(reloaded.weight(), conf.weight());
+        match weights {
+            Weights::Uniform => assert_eq!(conf.weight(), num_agents),
+            Weights::Random => assert!(conf
+                .children
+                .iter()
+                .all(|child| (1..=10).contains(&child.weight))),
+            Weights::Zipf => assert_eq!(
+                conf.children.iter().map(|child| child.weight).max(),
+                Some(num_agents)
+            ),
+        }
+
+        common::stop(processes).await;
+    }
+
+    // One heavy liar against lighter honest agents: the liar wins if and
+    // only if it weighs a quorum.
+    let quorum = 5;
+    for &liar_weight in &[quorum - 1, quorum] {
+        let value = rand::thread_rng().gen_bool(0.5);
+        let honest_weights = vec![1; 2 * quorum - 1 - liar_weight];
+        let (conf, processes) = fleet(value, liar_weight, &honest_weights).await;
+        assert_eq!(conf.quorum(), quorum);
+        let expected = if liar_weight < quorum {
+            Some(value)
+        } else {
+            Some(!value)
+        };
+
+        let play_args = PlayArgs::default();
+        assert_eq!(liars::play::play(&play_args).await, expected);
+        let play_expert_args = PlayExpertArgs {
+            liar_ratio: 0.,
+            ..Default::default()
+        };
+        // With a liar ratio of 0, we ask every agent, including the liar.
+        assert_eq!(liars::playexpert::play(&play_expert_args).await, expected);
+
+        common::stop(processes).await;
+    }
+    let _ = std::fs::remove_file("agents.conf");
+}
+
+/// Spawn a liar of weight `liar_weight` and honest agents of weights
+/// `honest_weights`, write them to `agents.conf`.
+async fn fleet(
+    value: bool,
+    liar_weight: usize,
+    honest_weights: &[usize],
+) -> (Conf, Vec<tokio::process::Child>) {
+    let exe = std::path::PathBuf::from(env!("CARGO_BIN_EXE_liarslie"));
+    let mut processes = vec![];
+    let mut children = vec![];
+    let agents = std::iter::once((!value, liar_weight))
+        .chain(honest_weights.iter().map(|&weight| (value, weight)));
+    for (agent_value, weight) in agents {
+        let (proc, mut child) = spawn_agent(
+            &exe,
+            agent_value,
+            agent_value != value,
+            std::process::Stdio::inherit(),
+            &SpawnArgs::default(),
+        )
+        .await
+        .expect("Could not spawn agent");
+        child.weight = weight;
+        processes.push(proc);
+        children.push(child);
+    }
+    let conf = Conf { epoch: 0, children };
+    conf.save(std::path::Path::new("agents.conf"))
+        .expect("Could not write agents.conf");
+    (conf, processes)
+}